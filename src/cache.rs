@@ -0,0 +1,602 @@
+use crate::error::Error;
+use crate::export::QueryExport;
+use crate::metadata::Metadata;
+use crate::output::{write_atomically, GeneratedFile};
+use crate::query::Query;
+use crate::util::fnv1a_hex;
+use log::warn;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry as HashMapEntry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Path to the cache file (relative to the dir containing the
+/// manifest file)
+pub const CACHE_FILE: &str = ".tapestry/cache";
+
+/// Hash of the inputs that an output is rendered from, along with
+/// the hash of the output file as it was written
+///
+/// In case of queries, the export of the query (if required) is also
+/// stored (see `QueryExport`).
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    inputs: String,
+    output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    export: Option<QueryExport>,
+}
+
+/// Cache of the last rendered queries and tests, used for skipping
+/// the ones whose inputs haven't changed since
+///
+/// An output is considered fresh only if the hash of it's inputs is
+/// the same and the output file hasn't been modified (or deleted)
+/// since it was last written by tapestry.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cache {
+    queries: BTreeMap<String, Entry>,
+    tests: BTreeMap<PathBuf, Entry>,
+}
+
+/// Hashes of the inputs of all the queries and tests in the manifest
+///
+/// The default value has no hashes at all i.e. nothing is considered
+/// fresh as per any cache.
+#[derive(Default)]
+pub struct Inputs<'a> {
+    queries: HashMap<&'a str, String>,
+    tests: HashMap<&'a Path, String>,
+}
+
+/// Queries and tests whose outputs are fresh as per the cache
+#[derive(Default)]
+pub struct Fresh<'a> {
+    queries: HashSet<&'a str>,
+    tests: HashSet<&'a Path>,
+}
+
+/// Queries and tests that have been rendered in the current run
+pub struct Rendered<'a> {
+    pub queries: HashSet<&'a str>,
+    pub tests: HashSet<&'a Path>,
+}
+
+impl Fresh<'_> {
+    pub fn has_query(&self, id: &str) -> bool {
+        self.queries.contains(id)
+    }
+
+    pub fn has_test(&self, path: &Path) -> bool {
+        self.tests.contains(path)
+    }
+}
+
+fn dependency_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r#"\{%[-+]?\s*(?:include|extends|import|from)\s+(?:"([^"]+)"|'([^']+)')"#)
+            .unwrap()
+    })
+}
+
+/// Returns names of the templates that are included, extended or
+/// imported by the template `source`
+fn template_dependencies(source: &str) -> Vec<&str> {
+    dependency_regex()
+        .captures_iter(source)
+        .filter_map(|c| c.get(1).or_else(|| c.get(2)))
+        .map(|m| m.as_str())
+        .collect()
+}
+
+/// Appends the source of the template at `path` followed by the
+/// sources of all the templates it depends on (recursively) to `acc`
+///
+/// The dependencies are looked up in the templates dir `dir`. Missing
+/// dependencies are ignored as rendering would fail anyway.
+fn append_template_sources(
+    dir: &Path,
+    path: &Path,
+    seen: &mut HashSet<PathBuf>,
+    acc: &mut String,
+) -> Result<(), Error> {
+    if !seen.insert(path.to_path_buf()) || !path.is_file() {
+        return Ok(());
+    }
+    let source = fs::read_to_string(path).map_err(Error::Io)?;
+    acc.push_str(&source);
+    for name in template_dependencies(&source) {
+        append_template_sources(dir, &dir.join(name), seen, acc)?;
+    }
+    Ok(())
+}
+
+fn template_sources(dir: &Path, path: &Path) -> Result<String, Error> {
+    let mut result = String::new();
+    append_template_sources(dir, path, &mut HashSet::new(), &mut result)?;
+    Ok(result)
+}
+
+/// Returns the manifest config that affects all the outputs
+///
+/// In case of external formatters, the executable and it's version
+/// are included too, so that upgrading the formatter invalidates the
+/// cache. The version is looked up by running the formatter only once
+/// per run (see `ExternalFormatter::version`).
+fn global_inputs(metadata: &Metadata) -> Result<String, Error> {
+    let formatter = match &metadata.formatter {
+        Some(f) => {
+            let config = serde_json::to_string(&f.config_toml_table()).map_err(Error::Json)?;
+            let config_file = match f.config_file_path() {
+                Some(p) if p.is_file() => fs::read_to_string(p).map_err(Error::Io)?,
+                _ => String::new(),
+            };
+            let executable = f.executable().map(|p| p.display().to_string());
+            format!("{config}\n{config_file}\n{executable:?}\n{:?}", f.version())
+        }
+        None => String::new(),
+    };
+    let name_tagger = match &metadata.name_tagger {
+        Some(t) => format!(
+            "{:?}\n{:?}\n{:?}",
+            t.style,
+            t.flavor,
+            t.template.as_ref().map(|tmpl| tmpl.source())
+        ),
+        None => String::new(),
+    };
+    let params_comment = metadata.query_params.as_ref().is_some_and(|pe| pe.comment);
+    Ok(format!(
+        "{}\n{name_tagger}\n{:?}\n{}\n{}\n{params_comment}\n{formatter}",
+        env!("CARGO_PKG_VERSION"),
+        metadata.query_output_layout,
+        metadata.generated_marker,
+        metadata.generated_header,
+    ))
+}
+
+/// Returns the hash of the inputs of the query i.e. the query
+/// template source (including it's dependencies), conds, placeholder
+/// style etc. along with the `global` inputs
+fn query_inputs(metadata: &Metadata, query: &Query, global: &str) -> Result<String, Error> {
+    let mut conds = query.conds.iter().collect::<Vec<_>>();
+    conds.sort();
+    let mut s = String::from(global);
+    // @UNWRAP: Writing to a String doesn't fail
+    write!(
+        s,
+        "\n{}\n{}\n{conds:?}\n{}\n{:?}\n{:?}\n{:?}\n{:?}\n",
+        query.id,
+        query.template.display(),
+        query.output.display(),
+        query.name_tag,
+        metadata.query_placeholder(query),
+        metadata.query_result(query),
+        metadata.query_description(query),
+    )
+    .unwrap();
+    if let Some(qt) = metadata.query_templates.get(&query.template) {
+        let mut all_conds = qt.all_conds.iter().collect::<Vec<_>>();
+        all_conds.sort();
+        writeln!(s, "{all_conds:?}").unwrap();
+        s.push_str(&template_sources(&metadata.query_templates_dir, &qt.path)?);
+    }
+    Ok(fnv1a_hex(&s))
+}
+
+impl<'a> Inputs<'a> {
+    pub fn compute(metadata: &'a Metadata) -> Result<Self, Error> {
+        let global = global_inputs(metadata)?;
+        let mut queries = HashMap::new();
+        for query in metadata.queries.iter() {
+            queries.insert(query.id.as_str(), query_inputs(metadata, query, &global)?);
+        }
+        let mut tests = HashMap::new();
+        for tt in metadata.test_templates.iter() {
+            let query_hash = queries.get(tt.query.as_str()).map_or("", |h| h.as_str());
+            let s = format!(
+                "{global}\n{}\n{}\n{query_hash}\n{}",
+                tt.path.display(),
+                tt.output.display(),
+                template_sources(&metadata.test_templates_dir, &tt.path)?,
+            );
+            tests.insert(tt.path.as_path(), fnv1a_hex(&s));
+        }
+        Ok(Self { queries, tests })
+    }
+}
+
+/// Returns hash of the contents of the file at `path` or `None` if it
+/// doesn't exist
+fn file_hash(path: &Path) -> Result<Option<String>, Error> {
+    if !path.try_exists().map_err(Error::Io)? {
+        return Ok(None);
+    }
+    let contents = fs::read(path).map_err(Error::Io)?;
+    Ok(Some(fnv1a_hex(&String::from_utf8_lossy(&contents))))
+}
+
+impl Cache {
+    /// Loads the cache from the file at `path`
+    ///
+    /// If the file doesn't exist or can't be parsed (e.g. written by
+    /// an incompatible version), an empty cache is returned.
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                warn!("Ignoring invalid cache file {}: {e}", path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Saves the cache to the file at `path` (creating the parent
+    /// dirs if required)
+    ///
+    /// The file is written atomically so that an interrupted run
+    /// doesn't leave behind a truncated cache file.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let contents = serde_json::to_string_pretty(self).map_err(Error::Json)?;
+        write_atomically(&[GeneratedFile::new(path.to_path_buf(), contents)])
+    }
+
+    /// Returns the queries and tests whose outputs are fresh i.e.
+    /// need not be rendered again
+    pub fn fresh<'a>(&self, metadata: &'a Metadata, inputs: &Inputs) -> Result<Fresh<'a>, Error> {
+        // In case of combined layouts, the same output file is shared
+        // by multiple queries, hence the hashes are memoized
+        let mut output_hashes: HashMap<&Path, Option<String>> = HashMap::new();
+        let mut is_fresh = |entry: Option<&Entry>, inputs: Option<&String>, path: &'a Path| match (
+            entry, inputs,
+        ) {
+            (Some(e), Some(i)) if e.inputs == *i => {
+                let hash = match output_hashes.entry(path) {
+                    HashMapEntry::Occupied(o) => o.get().clone(),
+                    HashMapEntry::Vacant(v) => v.insert(file_hash(path)?).clone(),
+                };
+                Ok::<bool, Error>(hash.as_ref() == Some(&e.output))
+            }
+            _ => Ok(false),
+        };
+        let mut result = Fresh::default();
+        for query in metadata.queries.iter() {
+            let entry = self.queries.get(&query.id);
+            if is_fresh(entry, inputs.queries.get(query.id.as_str()), &query.output)? {
+                result.queries.insert(query.id.as_str());
+            }
+        }
+        for tt in metadata.test_templates.iter() {
+            let entry = self.tests.get(&tt.path);
+            if is_fresh(entry, inputs.tests.get(tt.path.as_path()), &tt.output)? {
+                result.tests.insert(tt.path.as_path());
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns the export of the query if it's inputs haven't
+    /// changed since it was cached
+    pub fn export(&self, query_id: &str, inputs: &Inputs) -> Option<&QueryExport> {
+        let entry = self.queries.get(query_id)?;
+        if inputs.queries.get(query_id) == Some(&entry.inputs) {
+            entry.export.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Updates the cache after the `files` have been written
+    ///
+    /// `rendered` are the queries and tests that were rendered to the
+    /// `files`. Entries of the fresh queries are
+    /// retained (and their output hashes updated if the combined
+    /// output file containing them was rewritten). Entries of any
+    /// other queries whose output files were rewritten can no longer
+    /// be verified and are hence removed, as are the entries of the
+    /// queries and tests that are not in the manifest any more. The
+    /// `exports` are stored in the entries that are as per the
+    /// current inputs.
+    pub fn update(
+        &mut self,
+        metadata: &Metadata,
+        inputs: &Inputs,
+        fresh: &Fresh,
+        rendered: &Rendered,
+        files: &[GeneratedFile],
+        exports: &[(&Query, QueryExport)],
+    ) {
+        let exports = exports
+            .iter()
+            .map(|(q, e)| (q.id.as_str(), e))
+            .collect::<HashMap<&str, &QueryExport>>();
+        let written = files
+            .iter()
+            .map(|f| (f.path.as_path(), fnv1a_hex(&f.contents)))
+            .collect::<HashMap<&Path, String>>();
+
+        let mut queries = BTreeMap::new();
+        for query in metadata.queries.iter() {
+            let id = query.id.as_str();
+            let prev = self.queries.remove(id);
+            let entry = match (written.get(query.output.as_path()), inputs.queries.get(id)) {
+                (Some(output), Some(i)) if rendered.queries.contains(id) || fresh.has_query(id) => {
+                    Some(Entry {
+                        inputs: i.clone(),
+                        output: output.clone(),
+                        export: None,
+                    })
+                }
+                (Some(_), _) => None,
+                (None, _) => prev,
+            };
+            if let Some(mut e) = entry {
+                if inputs.queries.get(id) == Some(&e.inputs) {
+                    e.export = exports.get(id).map(|x| (*x).clone());
+                }
+                queries.insert(query.id.clone(), e);
+            }
+        }
+
+        let mut tests = BTreeMap::new();
+        for tt in metadata.test_templates.iter() {
+            let path = tt.path.as_path();
+            let prev = self.tests.remove(path);
+            let entry = match (written.get(tt.output.as_path()), inputs.tests.get(path)) {
+                (Some(output), Some(i)) if rendered.tests.contains(path) => Some(Entry {
+                    inputs: i.clone(),
+                    output: output.clone(),
+                    export: None,
+                }),
+                (Some(_), _) => None,
+                (None, _) => prev,
+            };
+            if let Some(e) = entry {
+                tests.insert(tt.path.clone(), e);
+            }
+        }
+
+        self.queries = queries;
+        self.tests = tests;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::formatters::{Formatter, PgFormatter};
+    use crate::query::test_util::setup_queries;
+    use crate::query_template::test_util::setup_query_templates;
+    use crate::tagging::{NameTagFlavor, NameTagStyle, NameTagTemplate, NameTagger};
+    #[cfg(unix)]
+    use crate::util::test_util::stub_executable;
+    use crate::util::test_util::TempDir;
+
+    // Setup a project in `dir` with a query template that includes
+    // another template, and metadata with two queries for it
+    fn setup_metadata(dir: &Path) -> Metadata {
+        let templates_dir = dir.join("templates");
+        fs::create_dir_all(&templates_dir).unwrap();
+        fs::write(
+            templates_dir.join("songs.sql.j2"),
+            "SELECT * FROM songs{% include 'limit.sql.j2' %};",
+        )
+        .unwrap();
+        fs::write(
+            templates_dir.join("limit.sql.j2"),
+            "{% if cond__limit %} LIMIT 10{% endif %}",
+        )
+        .unwrap();
+        let template = templates_dir.join("songs.sql.j2");
+        let template = template.to_str().unwrap();
+        let output_dir = dir.join("output");
+        let mut metadata = Metadata::default();
+        metadata.query_templates_dir = templates_dir.clone();
+        metadata.query_templates = setup_query_templates(vec![(template, vec!["limit"])]);
+        metadata.queries = setup_queries(vec![
+            (
+                "songs",
+                template,
+                vec![],
+                output_dir.join("songs.sql").to_str().unwrap(),
+                None,
+            ),
+            (
+                "songs@limit",
+                template,
+                vec!["limit"],
+                output_dir.join("songs-limit.sql").to_str().unwrap(),
+                None,
+            ),
+        ]);
+        metadata
+    }
+
+    // Simulates the render command i.e. writes the outputs of the
+    // queries that are not fresh as per the cache and updates the
+    // cache. Returns the ids of the rendered queries.
+    fn render(cache: &mut Cache, metadata: &Metadata) -> Vec<String> {
+        let inputs = Inputs::compute(metadata).unwrap();
+        let fresh = cache.fresh(metadata, &inputs).unwrap();
+        let mut files = vec![];
+        let mut rendered = Rendered {
+            queries: HashSet::new(),
+            tests: HashSet::new(),
+        };
+        for query in metadata.queries.iter() {
+            if !fresh.has_query(&query.id) {
+                let contents = format!("-- {}\n", query.id);
+                files.push(GeneratedFile::new(query.output.clone(), contents));
+                rendered.queries.insert(query.id.as_str());
+            }
+        }
+        write_atomically(&files).unwrap();
+        let exports = metadata
+            .queries
+            .iter()
+            .map(|q| {
+                let export = QueryExport {
+                    name_tag: None,
+                    sql: None,
+                    params: vec![],
+                };
+                (q.as_ref(), export)
+            })
+            .collect::<Vec<_>>();
+        cache.update(metadata, &inputs, &fresh, &rendered, &files, &exports);
+        let mut result = rendered
+            .queries
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<String>>();
+        result.sort();
+        result
+    }
+
+    #[test]
+    fn test_cache() {
+        let tmp = TempDir::new("cache");
+        let dir = tmp.path();
+        let mut metadata = setup_metadata(dir);
+        let all = vec!["songs", "songs@limit"];
+        let none: Vec<&str> = vec![];
+
+        // Miss when the cache is empty
+        let mut cache = Cache::default();
+        assert_eq!(all, render(&mut cache, &metadata));
+
+        // Hit when nothing has changed since
+        assert_eq!(none, render(&mut cache, &metadata));
+        let inputs = Inputs::compute(&metadata).unwrap();
+        assert!(cache.export("songs", &inputs).is_some());
+
+        // The cache survives a round trip to the file
+        let cache_path = dir.join(CACHE_FILE);
+        cache.save(&cache_path).unwrap();
+        let mut cache = Cache::load(&cache_path);
+        assert_eq!(none, render(&mut cache, &metadata));
+
+        // Miss when the output file is modified or deleted
+        let songs = metadata.queries.get("songs").unwrap();
+        fs::write(&songs.output, "SELECT 1;\n").unwrap();
+        assert_eq!(vec!["songs"], render(&mut cache, &metadata));
+        fs::remove_file(&songs.output).unwrap();
+        assert_eq!(vec!["songs"], render(&mut cache, &metadata));
+
+        // Invalidated when the template changes
+        let template = dir.join("templates/songs.sql.j2");
+        fs::write(
+            &template,
+            "SELECT * FROM songs ORDER BY id{% include 'limit.sql.j2' %};",
+        )
+        .unwrap();
+        let inputs = Inputs::compute(&metadata).unwrap();
+        assert!(cache.export("songs", &inputs).is_none());
+        assert_eq!(all, render(&mut cache, &metadata));
+        assert_eq!(none, render(&mut cache, &metadata));
+
+        // Invalidated when a template that it depends on changes
+        fs::write(
+            dir.join("templates/limit.sql.j2"),
+            "{% if cond__limit %} LIMIT 20{% endif %}",
+        )
+        .unwrap();
+        assert_eq!(all, render(&mut cache, &metadata));
+        assert_eq!(none, render(&mut cache, &metadata));
+
+        // Invalidated when the manifest changes
+        metadata.generated_header = true;
+        assert_eq!(all, render(&mut cache, &metadata));
+        assert_eq!(none, render(&mut cache, &metadata));
+
+        // Invalidated when the name tagger template changes, but not
+        // when it's parsed again from the same source
+        let tagger = |source: &str| NameTagger {
+            style: NameTagStyle::SnakeCase,
+            flavor: NameTagFlavor::Default,
+            template: Some(NameTagTemplate::new(source.to_owned()).unwrap()),
+        };
+        metadata.name_tagger = Some(tagger("{{ template_stem }}"));
+        assert_eq!(all, render(&mut cache, &metadata));
+        metadata.name_tagger = Some(tagger("{{ template_stem }}"));
+        assert_eq!(none, render(&mut cache, &metadata));
+        metadata.name_tagger = Some(tagger("{{ id }}"));
+        assert_eq!(all, render(&mut cache, &metadata));
+
+        // Entries of the queries that are no longer in the manifest
+        // are removed
+        let template = template.to_str().unwrap().to_owned();
+        let output = metadata.queries.get("songs").unwrap().output.clone();
+        metadata.queries = setup_queries(vec![(
+            "songs",
+            &template,
+            vec![],
+            output.to_str().unwrap(),
+            None,
+        )]);
+        assert_eq!(none, render(&mut cache, &metadata));
+        assert_eq!(vec!["songs"], cache.queries.keys().collect::<Vec<_>>());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cache_formatter() {
+        let tmp = TempDir::new("cache-formatter");
+        let dir = tmp.path();
+        let mut metadata = setup_metadata(dir);
+        let all = vec!["songs", "songs@limit"];
+        let none: Vec<&str> = vec![];
+
+        // Stub of pg_format that prints the version from a file
+        let version_file = dir.join("version");
+        fs::write(&version_file, "1.0").unwrap();
+        let exec = dir.join("pg_format");
+        stub_executable(&exec, &format!("cat {}", version_file.display()));
+        metadata.formatter = Some(Formatter::PgFormatter(PgFormatter::new(exec.clone(), None)));
+
+        let mut cache = Cache::default();
+        assert_eq!(all, render(&mut cache, &metadata));
+        assert_eq!(none, render(&mut cache, &metadata));
+
+        // The version is looked up only once per run i.e. for the
+        // same formatter instance
+        fs::write(&version_file, "2.0").unwrap();
+        assert_eq!(none, render(&mut cache, &metadata));
+
+        // Invalidated when the version of the formatter changes
+        metadata.formatter = Some(Formatter::PgFormatter(PgFormatter::new(exec.clone(), None)));
+        assert_eq!(all, render(&mut cache, &metadata));
+        assert_eq!(none, render(&mut cache, &metadata));
+
+        // Invalidated when a different executable is configured
+        let other_exec = dir.join("pg_format_other");
+        stub_executable(&other_exec, &format!("cat {}", version_file.display()));
+        metadata.formatter = Some(Formatter::PgFormatter(PgFormatter::new(other_exec, None)));
+        assert_eq!(all, render(&mut cache, &metadata));
+        assert_eq!(none, render(&mut cache, &metadata));
+
+        // Invalidated when the formatter is changed
+        metadata.formatter = None;
+        assert_eq!(all, render(&mut cache, &metadata));
+        assert_eq!(none, render(&mut cache, &metadata));
+    }
+
+    #[test]
+    fn test_template_dependencies() {
+        let source = r#"{% include "common/filters.sql.j2" %}
+{%- import 'macros.sql.j2' as m -%}
+{% from "helpers.sql.j2" import limit %}
+SELECT * FROM artists {% if cond__limit %}LIMIT 10{% endif %};"#;
+        assert_eq!(
+            vec!["common/filters.sql.j2", "macros.sql.j2", "helpers.sql.j2"],
+            template_dependencies(source)
+        );
+        assert!(template_dependencies("SELECT 1;").is_empty());
+    }
+}
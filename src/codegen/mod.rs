@@ -0,0 +1,362 @@
+use crate::error::{parse_error, Error};
+use crate::export::QueryExport;
+use crate::metadata::Metadata;
+use crate::output::{GeneratedFile, Layout};
+use crate::query::Query;
+use crate::render::Param;
+use crate::tagging::extract_name_tag;
+use crate::toml::decode_pathbuf;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use toml::{Table, Value};
+
+pub use self::python::PythonCodegen;
+pub use self::rust::RustCodegen;
+pub use self::typescript::TypeScriptCodegen;
+
+mod python;
+mod rust;
+mod typescript;
+
+/// Query as required for generating client code
+pub struct CodegenQuery<'a> {
+    pub query: &'a Query,
+    /// Name of the query as per it's name tag
+    pub name: String,
+    /// Formatted sql of the query (without the name tag)
+    pub sql: String,
+    pub params: Vec<Param>,
+}
+
+/// Config for generating client code for the queries in various
+/// programming languages
+#[derive(Debug, Default)]
+pub struct Codegen {
+    pub rust: Option<RustCodegen>,
+    pub python: Option<PythonCodegen>,
+    pub typescript: Option<TypeScriptCodegen>,
+}
+
+impl Codegen {
+    pub fn decode(value: &Value, layout: &Layout) -> Result<Self, Error> {
+        let rust = match value.get("rust") {
+            Some(v) => Some(RustCodegen::decode(v, layout)?),
+            None => None,
+        };
+        let python = match value.get("python") {
+            Some(v) => Some(PythonCodegen::decode(v)?),
+            None => None,
+        };
+        let typescript = match value.get("typescript") {
+            Some(v) => Some(TypeScriptCodegen::decode(v)?),
+            None => None,
+        };
+        Ok(Self {
+            rust,
+            python,
+            typescript,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rust.is_none() && self.python.is_none() && self.typescript.is_none()
+    }
+
+    /// Returns paths of all the files to be generated
+    pub fn output_paths(&self) -> Vec<&Path> {
+        let mut result = vec![];
+        if let Some(rc) = &self.rust {
+            result.push(rc.output_file.as_path());
+        }
+        if let Some(pc) = &self.python {
+            result.push(pc.output_file.as_path());
+        }
+        if let Some(tc) = &self.typescript {
+            result.push(tc.output_file.as_path());
+        }
+        result
+    }
+}
+
+/// Decodes the required `output_file` key of the config table of a
+/// codegen target
+fn decode_output_file(t: &Table, target: &str) -> Result<PathBuf, Error> {
+    let key = format!("codegen.{target}.output_file");
+    t.get("output_file")
+        .ok_or(parse_error!("Key '{key}' is missing"))
+        .map(|v| decode_pathbuf(v, None, &key))?
+}
+
+/// Returns the names of the constants for the queries (in the same
+/// order) i.e. identifiers derived from the query ids in
+/// SCREAMING_SNAKE_CASE
+///
+/// An error is returned if the names of two constants conflict.
+fn constant_names(queries: &[CodegenQuery], target: &str) -> Result<Vec<String>, Error> {
+    let mut seen: HashMap<String, &str> = HashMap::with_capacity(queries.len());
+    let mut result = Vec::with_capacity(queries.len());
+    for cq in queries {
+        let name = identifier(&cq.query.id).to_uppercase();
+        if let Some(other) = seen.get(&name) {
+            return Err(Error::Codegen(format!(
+                "Queries '{other}' and '{}' map to the same {target} constant '{name}'",
+                cq.query.id
+            )));
+        }
+        seen.insert(name.clone(), &cq.query.id);
+        result.push(name);
+    }
+    Ok(result)
+}
+
+/// Returns the name of the query i.e. the one in the name tag of the
+/// query output
+///
+/// If the query template itself begins with a name tag (i.e.
+/// `template_name_tag`), it's used as it is. Otherwise it's derived
+/// as per the `name_tagger` config. In absence of the config, the id
+/// of the query is considered as it's name.
+fn query_name(metadata: &Metadata, query: &Query, template_name_tag: Option<&str>) -> String {
+    match template_name_tag {
+        Some(name) => name.to_owned(),
+        None => match &metadata.name_tagger {
+            Some(nt) => nt.tag(&query.name_tag).into_owned(),
+            None => query.id.clone(),
+        },
+    }
+}
+
+/// Returns path `to` relative to the directory `from_dir`
+///
+/// Relative paths are considered relative to the current working
+/// directory i.e. the dir containing the manifest file.
+fn relative_path(from_dir: &Path, to: &Path) -> Result<PathBuf, Error> {
+    let cwd = std::env::current_dir().map_err(Error::Io)?;
+    let from_dir = cwd.join(from_dir);
+    let to = cwd.join(to);
+    let from = from_dir
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect::<Vec<Component>>();
+    let to = to
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect::<Vec<Component>>();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut result = PathBuf::new();
+    for _ in common..from.len() {
+        result.push("..");
+    }
+    for c in &to[common..] {
+        result.push(c);
+    }
+    Ok(result)
+}
+
+/// Returns the client code files for all queries as per the `codegen`
+/// config in the manifest
+///
+/// `exports` are expected to be of all the queries (refer to
+/// `export::query_exports`).
+pub fn generated_files(
+    metadata: &Metadata,
+    exports: &[(&Query, QueryExport)],
+) -> Result<Vec<GeneratedFile>, Error> {
+    if metadata.codegen.is_empty() {
+        return Ok(vec![]);
+    }
+    let queries = exports
+        .iter()
+        .map(|(query, export)| CodegenQuery {
+            query,
+            name: query_name(metadata, query, export.name_tag.as_deref()),
+            // @NOTE: The sql is always exported when codegen is
+            // configured
+            sql: export.sql.clone().unwrap_or_default(),
+            params: export.params.clone(),
+        })
+        .collect::<Vec<_>>();
+    let mut result = vec![];
+    if let Some(rc) = &metadata.codegen.rust {
+        result.push(rc.generate(&queries)?);
+    }
+    if let Some(pc) = &metadata.codegen.python {
+        result.push(pc.generate(&queries)?);
+    }
+    if let Some(tc) = &metadata.codegen.typescript {
+        result.push(tc.generate(&queries)?);
+    }
+    Ok(result)
+}
+
+/// Strips the name tag line (if any) from the beginning of the sql
+pub fn strip_name_tag(sql: &str) -> &str {
+    match extract_name_tag(sql) {
+        Some(_) => sql
+            .trim_start()
+            .split_once('\n')
+            .map(|(_, rest)| rest)
+            .unwrap_or(""),
+        None => sql,
+    }
+}
+
+/// Converts the query id into an identifier that's valid in most
+/// programming languages by replacing every non-alphanumeric char
+/// with an underscore
+pub fn identifier(id: &str) -> Cow<'_, str> {
+    let mut result = id.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    if result.starts_with(|c: char| c.is_ascii_digit()) {
+        result.insert(0, '_');
+    }
+    Cow::from(result)
+}
+
+#[cfg(test)]
+pub mod test_util {
+
+    use super::*;
+    use crate::query::Queries;
+
+    pub fn setup_queries() -> Queries {
+        crate::query::test_util::setup_queries(vec![
+            (
+                "artists_long_songs@genre*limit",
+                "artists_long_songs.sql.j2",
+                vec!["genre", "limit"],
+                "output/queries/artists_long_songs-genre-limit.sql",
+                None,
+            ),
+            (
+                "artists_long_songs",
+                "artists_long_songs.sql.j2",
+                vec![],
+                "output/queries/artists_long_songs.sql",
+                None,
+            ),
+        ])
+    }
+
+    // Setup `CodegenQuery` instances for the queries returned by
+    // `setup_queries`
+    pub fn setup_codegen_queries(queries: &Queries) -> Vec<CodegenQuery<'_>> {
+        queries
+            .iter()
+            .map(|q| CodegenQuery {
+                query: q,
+                name: q.id.replace(['@', '*'], "-"),
+                sql: String::from("SELECT 1;"),
+                params: if q.conds.is_empty() {
+                    vec![]
+                } else {
+                    vec![
+                        Param {
+                            name: "genre".to_owned(),
+                            placeholder: "$1".to_owned(),
+                            sql_type: None,
+                        },
+                        Param {
+                            name: "limit".to_owned(),
+                            placeholder: "$2".to_owned(),
+                            sql_type: None,
+                        },
+                    ]
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::cache::{Cache, Inputs};
+    use crate::export::{query_exports, Exports};
+    use crate::formatters::BATCH_DIR;
+    use crate::output;
+    use crate::render::{Engine, RenderedQuery};
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            PathBuf::from("../output/queries/artists_long_songs.sql"),
+            relative_path(
+                Path::new("src"),
+                Path::new("output/queries/artists_long_songs.sql")
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            PathBuf::from("queries/all.sql"),
+            relative_path(Path::new("./output"), Path::new("output/queries/all.sql")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(
+            "artists_long_songs_genre_limit",
+            identifier("artists_long_songs@genre*limit")
+        );
+        assert_eq!("_2fa_codes", identifier("2fa-codes"));
+    }
+
+    // Returns the rust code generated for a query whose template
+    // begins with a name tag
+    fn generated_rust_code(sql: rust::SqlSource) -> String {
+        let mut metadata = crate::export::test_util::setup_metadata();
+        metadata.codegen.rust = Some(RustCodegen {
+            output_file: PathBuf::from("src/queries.rs"),
+            sql,
+        });
+        let mut exports = Exports::new(&metadata);
+        let query = metadata.queries.get("artists_long_songs").unwrap();
+        let rendered = RenderedQuery {
+            sql: String::from("-- name: all-artists\nSELECT 1;"),
+            params: vec![],
+        };
+        exports.add(&metadata, query, rendered);
+        let unformatted = exports.take_unformatted();
+        exports
+            .complete(output::format_outputs(None, unformatted, 1, Path::new(BATCH_DIR)).unwrap());
+        let exports = query_exports(
+            &metadata,
+            &Engine::from(&metadata),
+            exports,
+            &Cache::default(),
+            &Inputs::compute(&metadata).unwrap(),
+            1,
+        )
+        .unwrap();
+        let mut files = generated_files(&metadata, &exports).unwrap();
+        assert_eq!(1, files.len());
+        files.remove(0).contents
+    }
+
+    #[test]
+    fn test_generated_files() {
+        // The name is as per the name tag in the template and the
+        // inlined sql doesn't include it
+        let code = generated_rust_code(rust::SqlSource::Inline);
+        assert!(code.contains(
+            "pub const ARTISTS_LONG_SONGS: Query = Query {\n    name: \"all-artists\",\n    sql: r#\"SELECT 1;\"#,"
+        ));
+
+        // Whereas with `include_str`, the sql is the query output
+        // file as it is (i.e. including the name tag)
+        let code = generated_rust_code(rust::SqlSource::IncludeStr);
+        assert!(code.contains("sql: include_str!(\"../output/queries/artists_long_songs.sql\"),"));
+    }
+
+    #[test]
+    fn test_strip_name_tag() {
+        assert_eq!("SELECT 1;", strip_name_tag("-- name: one\nSELECT 1;"));
+        assert_eq!("SELECT 1;", strip_name_tag("SELECT 1;"));
+    }
+}
@@ -0,0 +1,190 @@
+use super::{constant_names, decode_output_file, CodegenQuery};
+use crate::error::{parse_error, Error};
+use crate::output::GeneratedFile;
+use std::fmt::Write;
+use std::path::PathBuf;
+use toml::Value;
+
+/// Config for generating a python module with a constant for every
+/// query
+#[derive(Debug)]
+pub struct PythonCodegen {
+    pub output_file: PathBuf,
+}
+
+const PREAMBLE: &str = r#"# This file is generated by tapestry. Do not edit it manually.
+
+from typing import NamedTuple, Tuple
+
+
+class Query(NamedTuple):
+    """Query rendered by tapestry"""
+
+    # Name of the query as per it's name tag
+    name: str
+    sql: str
+    # Names of the params in the order in which they are to be passed
+    # upon executing the query
+    params: Tuple[str, ...]
+"#;
+
+/// Returns the sql as a triple quoted python string literal
+///
+/// Backslashes are escaped. Double quotes are escaped only if
+/// required i.e. when followed by another double quote or at the end
+/// of the string, so that quoted identifiers in the sql remain
+/// readable. Control chars other than newlines and tabs are escaped
+/// as python would otherwise normalize `\r` or reject them.
+fn string_literal(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 6);
+    result.push_str("\"\"\"");
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' if matches!(chars.peek(), Some('"') | None) => result.push_str("\\\""),
+            '\n' | '\t' => result.push(c),
+            c if c.is_control() => result.push_str(&control_char_escape(c)),
+            _ => result.push(c),
+        }
+    }
+    result.push_str("\"\"\"");
+    result
+}
+
+/// Returns a double quoted python string literal
+///
+/// Unlike rust's `Debug` formatting, the escape sequences used are
+/// the ones that python understands.
+fn quoted_string_literal(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            c if c.is_control() => result.push_str(&control_char_escape(c)),
+            _ => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Returns the python escape sequence for a control char
+fn control_char_escape(c: char) -> String {
+    match c {
+        '\n' => String::from("\\n"),
+        '\r' => String::from("\\r"),
+        '\t' => String::from("\\t"),
+        // Control chars are all within U+0000 to U+009F
+        _ => format!("\\x{:02x}", c as u32),
+    }
+}
+
+/// Returns the params as a python tuple literal
+fn tuple_literal(items: &[String]) -> String {
+    match items {
+        [] => String::from("()"),
+        [x] => format!("({x},)"),
+        _ => format!("({})", items.join(", ")),
+    }
+}
+
+impl PythonCodegen {
+    pub fn decode(value: &Value) -> Result<Self, Error> {
+        let t = value.as_table().ok_or(parse_error!(
+            "Value of 'codegen.python' expected to be a table"
+        ))?;
+        let output_file = decode_output_file(t, "python")?;
+        Ok(Self { output_file })
+    }
+
+    /// Returns the generated python module with one constant per
+    /// query (refer to `constant_names` for naming of the constants)
+    pub fn generate(&self, queries: &[CodegenQuery]) -> Result<GeneratedFile, Error> {
+        let mut code = String::from(PREAMBLE);
+        let const_names = constant_names(queries, "python")?;
+        for (cq, const_name) in queries.iter().zip(const_names) {
+            let params = cq
+                .params
+                .iter()
+                .map(|p| quoted_string_literal(&p.name))
+                .collect::<Vec<String>>();
+            // @UNWRAP: Writing to a String doesn't fail
+            write!(
+                code,
+                "\n\n# `{}`\n{const_name} = Query(\n    name={},\n    sql={},\n    params={},\n)\n",
+                cq.query.id,
+                quoted_string_literal(&cq.name),
+                string_literal(&cq.sql),
+                tuple_literal(&params),
+            )
+            .unwrap();
+        }
+        Ok(GeneratedFile::new(self.output_file.clone(), code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::codegen::test_util::{setup_codegen_queries, setup_queries};
+
+    #[test]
+    fn test_string_literal() {
+        assert_eq!(r#""""SELECT 1;""""#, string_literal("SELECT 1;"));
+        assert_eq!(
+            r#""""SELECT "name" FROM "artist";""""#,
+            string_literal(r#"SELECT "name" FROM "artist";"#)
+        );
+        assert_eq!(
+            r##""""SELECT '\\', '\"\""' AS "x\"""""##,
+            string_literal(r#"SELECT '\', '"""' AS "x""#)
+        );
+        assert_eq!(
+            "\"\"\"SELECT '\\r', '\\x00';\n\t\"\"\"",
+            string_literal("SELECT '\r', '\0';\n\t")
+        );
+    }
+
+    #[test]
+    fn test_quoted_string_literal() {
+        assert_eq!(r#""limit""#, quoted_string_literal("limit"));
+        assert_eq!(
+            r#""a\"b\\c\n\t\x1b\x7f""#,
+            quoted_string_literal("a\"b\\c\n\t\u{1b}\u{7f}")
+        );
+        assert_eq!(r#""émoji 🎉""#, quoted_string_literal("émoji 🎉"));
+    }
+
+    #[test]
+    fn test_generate() {
+        let queries = setup_queries();
+        let cqs = setup_codegen_queries(&queries);
+        let pc = PythonCodegen {
+            output_file: PathBuf::from("queries.py"),
+        };
+        let generated = pc.generate(&cqs).unwrap();
+        let expected = r#"
+
+# `artists_long_songs@genre*limit`
+ARTISTS_LONG_SONGS_GENRE_LIMIT = Query(
+    name="artists_long_songs-genre-limit",
+    sql="""SELECT 1;""",
+    params=("genre", "limit"),
+)
+
+
+# `artists_long_songs`
+ARTISTS_LONG_SONGS = Query(
+    name="artists_long_songs",
+    sql="""SELECT 1;""",
+    params=(),
+)
+"#;
+        assert_eq!(format!("{PREAMBLE}{expected}"), generated.contents);
+        assert_eq!("(\"genre\",)", tuple_literal(&["\"genre\"".to_owned()]));
+    }
+}
@@ -0,0 +1,164 @@
+use super::{constant_names, decode_output_file, relative_path, CodegenQuery};
+use crate::error::{parse_error, Error};
+use crate::output::{GeneratedFile, Layout};
+use std::fmt::Write;
+use std::path::PathBuf;
+use toml::Value;
+
+/// How the sql of the queries is to be included in the generated code
+#[derive(Debug, PartialEq)]
+pub enum SqlSource {
+    /// Using `include_str!` macro with the path to the query output
+    /// file
+    IncludeStr,
+    /// As a raw string literal
+    Inline,
+}
+
+/// Config for generating a rust module with a constant for every
+/// query
+#[derive(Debug)]
+pub struct RustCodegen {
+    pub output_file: PathBuf,
+    pub sql: SqlSource,
+}
+
+const PREAMBLE: &str = r#"// This file is generated by tapestry. Do not edit it manually.
+
+/// Query rendered by tapestry
+#[derive(Debug, Clone, Copy)]
+pub struct Query {
+    /// Name of the query as per it's name tag
+    pub name: &'static str,
+    pub sql: &'static str,
+    /// Names of the params in the order in which they are to be
+    /// passed upon executing the query
+    pub params: &'static [&'static str],
+}
+"#;
+
+/// Returns the sql as a raw string literal with enough `#` chars to
+/// not conflict with the contents
+fn raw_string_literal(s: &str) -> String {
+    let mut hashes = 1;
+    while s.contains(&format!("\"{}", "#".repeat(hashes))) {
+        hashes += 1;
+    }
+    let hashes = "#".repeat(hashes);
+    format!("r{hashes}\"{s}\"{hashes}")
+}
+
+impl RustCodegen {
+    pub fn decode(value: &Value, layout: &Layout) -> Result<Self, Error> {
+        let t = value.as_table().ok_or(parse_error!(
+            "Value of 'codegen.rust' expected to be a table"
+        ))?;
+        let output_file = decode_output_file(t, "rust")?;
+        let combined = layout.is_combined();
+        let sql = match t.get("sql").map(|v| v.as_str()) {
+            Some(Some("include_str")) if combined => {
+                return Err(parse_error!(
+                    "'codegen.rust.sql' = 'include_str' is not supported when query_output_layout = '{}'",
+                    layout.label()
+                ))
+            }
+            Some(Some("include_str")) => SqlSource::IncludeStr,
+            Some(Some("inline")) => SqlSource::Inline,
+            Some(Some(s)) => return Err(parse_error!("Invalid value for 'codegen.rust.sql': '{s}'")),
+            Some(None) => {
+                return Err(parse_error!(
+                    "Value of 'codegen.rust.sql' expected to be a string"
+                ))
+            }
+            None if combined => SqlSource::Inline,
+            None => SqlSource::IncludeStr,
+        };
+        Ok(Self { output_file, sql })
+    }
+
+    /// Returns the generated rust module with one constant per query
+    /// (refer to `constant_names` for naming of the constants)
+    pub fn generate(&self, queries: &[CodegenQuery]) -> Result<GeneratedFile, Error> {
+        let mut code = String::from(PREAMBLE);
+        let const_names = constant_names(queries, "rust")?;
+        for (cq, const_name) in queries.iter().zip(const_names) {
+            let sql = match self.sql {
+                SqlSource::IncludeStr => {
+                    // @UNWRAP: output_file is a file path and hence
+                    // must have a parent
+                    let dir = self.output_file.parent().unwrap();
+                    let path = relative_path(dir, &cq.query.output)?;
+                    format!("include_str!({:?})", path.display().to_string())
+                }
+                SqlSource::Inline => raw_string_literal(&cq.sql),
+            };
+            let params = cq
+                .params
+                .iter()
+                .map(|p| format!("{:?}", p.name))
+                .collect::<Vec<String>>()
+                .join(", ");
+            // @UNWRAP: Writing to a String doesn't fail
+            write!(
+                code,
+                "\n/// `{}`\npub const {const_name}: Query = Query {{\n    name: {:?},\n    sql: {sql},\n    params: &[{params}],\n}};\n",
+                cq.query.id, cq.name,
+            )
+            .unwrap();
+        }
+        Ok(GeneratedFile::new(self.output_file.clone(), code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::codegen::test_util::{setup_codegen_queries, setup_queries};
+
+    #[test]
+    fn test_raw_string_literal() {
+        assert_eq!("r#\"SELECT 1;\"#", raw_string_literal("SELECT 1;"));
+        assert_eq!(
+            "r##\"SELECT '\"#';\"##",
+            raw_string_literal("SELECT '\"#';")
+        );
+    }
+
+    #[test]
+    fn test_generate() {
+        let queries = setup_queries();
+        let cqs = setup_codegen_queries(&queries);
+
+        let rc = RustCodegen {
+            output_file: PathBuf::from("src/queries.rs"),
+            sql: SqlSource::Inline,
+        };
+        let generated = rc.generate(&cqs).unwrap();
+        let expected = r####"
+/// `artists_long_songs@genre*limit`
+pub const ARTISTS_LONG_SONGS_GENRE_LIMIT: Query = Query {
+    name: "artists_long_songs-genre-limit",
+    sql: r#"SELECT 1;"#,
+    params: &["genre", "limit"],
+};
+
+/// `artists_long_songs`
+pub const ARTISTS_LONG_SONGS: Query = Query {
+    name: "artists_long_songs",
+    sql: r#"SELECT 1;"#,
+    params: &[],
+};
+"####;
+        assert_eq!(format!("{PREAMBLE}{expected}"), generated.contents);
+
+        let rc = RustCodegen {
+            output_file: PathBuf::from("src/queries.rs"),
+            sql: SqlSource::IncludeStr,
+        };
+        let generated = rc.generate(&cqs[1..]).unwrap();
+        assert!(generated
+            .contents
+            .contains("sql: include_str!(\"../output/queries/artists_long_songs.sql\"),"));
+    }
+}
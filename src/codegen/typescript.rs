@@ -0,0 +1,175 @@
+use super::{constant_names, decode_output_file, CodegenQuery};
+use crate::error::{parse_error, Error};
+use crate::output::GeneratedFile;
+use std::fmt::Write;
+use std::path::PathBuf;
+use toml::Value;
+
+/// Config for generating a typescript module with a constant for
+/// every query
+#[derive(Debug)]
+pub struct TypeScriptCodegen {
+    pub output_file: PathBuf,
+}
+
+const PREAMBLE: &str = r#"// This file is generated by tapestry. Do not edit it manually.
+
+/** Query rendered by tapestry */
+export interface Query {
+  /** Name of the query as per it's name tag */
+  readonly name: string;
+  readonly sql: string;
+  /**
+   * Names of the params in the order in which they are to be passed
+   * upon executing the query
+   */
+  readonly params: readonly string[];
+}
+"#;
+
+/// Returns the sql as a template literal with backslashes, backticks
+/// and `${` escaped
+///
+/// Control chars other than newlines and tabs are escaped too as
+/// javascript would otherwise normalize `\r` to `\n`.
+fn template_literal(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('`');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '`' => result.push_str("\\`"),
+            '$' if chars.peek() == Some(&'{') => result.push_str("\\$"),
+            '\n' | '\t' => result.push(c),
+            c if c.is_control() => result.push_str(&char_escape(c)),
+            _ => result.push(c),
+        }
+    }
+    result.push('`');
+    result
+}
+
+/// Returns a double quoted typescript string literal
+///
+/// Unlike rust's `Debug` formatting, the escape sequences used are
+/// the ones that typescript understands.
+fn quoted_string_literal(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            c if c.is_control() || c == '\u{2028}' || c == '\u{2029}' => {
+                result.push_str(&char_escape(c))
+            }
+            _ => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Returns the typescript escape sequence for a control char or a
+/// line/paragraph separator
+fn char_escape(c: char) -> String {
+    match c {
+        '\n' => String::from("\\n"),
+        '\r' => String::from("\\r"),
+        '\t' => String::from("\\t"),
+        _ => format!("\\u{:04x}", c as u32),
+    }
+}
+
+impl TypeScriptCodegen {
+    pub fn decode(value: &Value) -> Result<Self, Error> {
+        let t = value.as_table().ok_or(parse_error!(
+            "Value of 'codegen.typescript' expected to be a table"
+        ))?;
+        let output_file = decode_output_file(t, "typescript")?;
+        Ok(Self { output_file })
+    }
+
+    /// Returns the generated typescript module with one exported
+    /// constant per query (refer to `constant_names` for naming of
+    /// the constants)
+    pub fn generate(&self, queries: &[CodegenQuery]) -> Result<GeneratedFile, Error> {
+        let mut code = String::from(PREAMBLE);
+        let const_names = constant_names(queries, "typescript")?;
+        for (cq, const_name) in queries.iter().zip(const_names) {
+            let params = cq
+                .params
+                .iter()
+                .map(|p| quoted_string_literal(&p.name))
+                .collect::<Vec<String>>()
+                .join(", ");
+            // @UNWRAP: Writing to a String doesn't fail
+            write!(
+                code,
+                "\n/** `{}` */\nexport const {const_name}: Query = {{\n  name: {},\n  sql: {},\n  params: [{params}],\n}};\n",
+                cq.query.id,
+                quoted_string_literal(&cq.name),
+                template_literal(&cq.sql),
+            )
+            .unwrap();
+        }
+        Ok(GeneratedFile::new(self.output_file.clone(), code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::codegen::test_util::{setup_codegen_queries, setup_queries};
+
+    #[test]
+    fn test_template_literal() {
+        assert_eq!("`SELECT 1;`", template_literal("SELECT 1;"));
+        assert_eq!(
+            r#"`SELECT '\\', '\`', '\${x}';`"#,
+            template_literal(r#"SELECT '\', '`', '${x}';"#)
+        );
+        assert_eq!(
+            "`SELECT '$x', '\\r', '\\u0000';\n\t`",
+            template_literal("SELECT '$x', '\r', '\0';\n\t")
+        );
+    }
+
+    #[test]
+    fn test_quoted_string_literal() {
+        assert_eq!(r#""limit""#, quoted_string_literal("limit"));
+        assert_eq!(
+            r#""a\"b\\c\n\t\u001b\u2028""#,
+            quoted_string_literal("a\"b\\c\n\t\u{1b}\u{2028}")
+        );
+        assert_eq!(r#""émoji 🎉""#, quoted_string_literal("émoji 🎉"));
+    }
+
+    #[test]
+    fn test_generate() {
+        let queries = setup_queries();
+        let cqs = setup_codegen_queries(&queries);
+        let tc = TypeScriptCodegen {
+            output_file: PathBuf::from("src/queries.ts"),
+        };
+        let generated = tc.generate(&cqs).unwrap();
+        let expected = r#"
+/** `artists_long_songs@genre*limit` */
+export const ARTISTS_LONG_SONGS_GENRE_LIMIT: Query = {
+  name: "artists_long_songs-genre-limit",
+  sql: `SELECT 1;`,
+  params: ["genre", "limit"],
+};
+
+/** `artists_long_songs` */
+export const ARTISTS_LONG_SONGS: Query = {
+  name: "artists_long_songs",
+  sql: `SELECT 1;`,
+  params: [],
+};
+"#;
+        assert_eq!(format!("{PREAMBLE}{expected}"), generated.contents);
+    }
+}
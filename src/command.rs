@@ -1,92 +1,474 @@
+use crate::cache::{self, Cache};
+use crate::diff::{self, OutputDiff};
 use crate::error::Error;
+use crate::export::{self, Exports};
+use crate::formatters::BATCH_DIR;
 use crate::metadata::Metadata;
 use crate::output::{self, QueryOutputReader};
+use crate::params;
 use crate::placeholder::Placeholder;
-use crate::render::Engine;
+use crate::query::Query;
+use crate::render::{Engine, RenderedQuery};
+use crate::report::{self, OutputFormat, QueryRecord, Report, TestRecord};
 use crate::scaffolding;
+use crate::selection::Selection;
+use crate::test_template::TestTemplate;
+use crate::util;
+use crate::validation::ManifestMistake;
+use crate::watch::{TemplateIndex, Watcher};
 // use crate::tagging::{NameTagStyle, NameTagger};
-use crate::util::ls_files;
 use comfy_table::Table;
+use serde_json::json;
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-pub fn validate() -> Result<i32, Error> {
+/// Returns the query and test output files that have been edited
+/// manually after they were last rendered, as per the hash in the
+/// generated header (if enabled in the manifest)
+///
+/// In case of combined layouts, the query sections are checked
+/// individually and the query id is returned along with the path.
+fn manually_edited_outputs(metadata: &Metadata) -> Result<Vec<(&Path, Option<&str>)>, Error> {
+    let mut result = vec![];
+    if !metadata.generated_header {
+        return Ok(result);
+    }
+    let query_reader = QueryOutputReader::new(metadata)?;
+    let combined = metadata.query_output_layout.is_combined();
+    for query in metadata.queries.iter() {
+        if query_reader.exists(&query.id)? {
+            let contents = query_reader.read(&query.id)?;
+            if output::is_manually_edited(&String::from_utf8_lossy(&contents)) {
+                let id = combined.then_some(query.id.as_str());
+                result.push((query.output.as_path(), id));
+            }
+        }
+    }
+    for tt in metadata.test_templates.iter() {
+        if let Some(contents) = output::read_testfile(&tt.output)? {
+            if output::is_manually_edited(&String::from_utf8_lossy(&contents)) {
+                result.push((tt.output.as_path(), None));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Returns mistakes for the params that are declared with different
+/// types across the queries (see `export::conflicting_param_types`)
+///
+/// The params of all the queries are required for this, hence the
+/// exports in the cache are used for the queries whose inputs haven't
+/// changed since they were last rendered and only the rest of the
+/// queries are rendered.
+fn conflicting_param_types(metadata: &Metadata) -> Result<Vec<ManifestMistake<'_>>, Error> {
+    let engine = Engine::from(metadata);
+    let inputs = cache::Inputs::compute(metadata)?;
+    let cache = Cache::load(Path::new(cache::CACHE_FILE));
+    let exports = export::query_exports(
+        metadata,
+        &engine,
+        Exports::params_only(),
+        &cache,
+        &inputs,
+        util::default_jobs(),
+    )?;
+    Ok(export::conflicting_param_types(&exports))
+}
+
+pub fn validate(format: OutputFormat) -> Result<i32, Error> {
     let path = Path::new("tapestry.toml");
     let metadata = Metadata::try_from(path)?;
-    let mistakes = metadata.validate();
+    let mut mistakes = metadata.validate();
+    // The queries can be rendered only if the manifest is otherwise
+    // valid
     if mistakes.is_empty() {
-        println!("All Ok: Manifest file '{}' is valid", path.display());
+        mistakes.append(&mut conflicting_param_types(&metadata)?);
+    }
+    if mistakes.is_empty() {
+        let edited = manually_edited_outputs(&metadata)?;
+        if format.is_text() {
+            println!("All Ok: Manifest file '{}' is valid", path.display());
+            for (output, id) in &edited {
+                match id {
+                    Some(id) => println!("Manually edited: {} ({id})", output.display()),
+                    None => println!("Manually edited: {}", output.display()),
+                }
+            }
+        } else {
+            let mut report = Report::new("validate");
+            report.push("validation", &json!({"manifest": path, "valid": true}))?;
+            for (output, id) in &edited {
+                let record = match id {
+                    Some(id) => json!({"path": output, "query": id}),
+                    None => json!({"path": output}),
+                };
+                report.push("manually_edited", &record)?;
+            }
+            report.print(format)?;
+        }
+        // Manually edited outputs are reported for information only,
+        // i.e. they don't make the manifest invalid
         Ok(0)
     } else {
-        println!("Invalid manifest file: '{}'", path.display());
-        for mistake in mistakes {
-            println!("{}", mistake.err_msg())
-        }
+        report::print_mistakes("validate", path, &mistakes, format)?;
         Ok(1)
     }
 }
 
-pub fn render() -> Result<i32, Error> {
-    let path = Path::new("tapestry.toml");
-    let metadata = Metadata::try_from(path)?;
-    let mistakes = metadata.validate();
-    if mistakes.is_empty() {
-        let engine = Engine::from(&metadata);
-        let formatter = &metadata.formatter;
-        output::ensure_output_dirs(&metadata.queries_output_dir, &metadata.tests_output_dir)?;
-        let mut queries_to_write: Vec<output::SqlToWrite> =
-            Vec::with_capacity(metadata.queries.len());
-        let mut tests_to_write: Vec<output::SqlToWrite> = Vec::new();
-        for query in metadata.queries.iter() {
-            // render query output and collect in a vec
-            let query_output = engine.render_query(&query.id, None)?;
+/// Returns ids of queries that are missing from the combined output
+/// files in case of the combined layouts (e.g. `OneFileAllQueries`)
+///
+/// If a combined output file doesn't exist yet, ids of all queries
+/// to be written to it are returned. In case of `OneFileOneQuery`
+/// layout, the result is empty.
+fn missing_from_combined_output(metadata: &Metadata) -> Result<HashSet<&str>, Error> {
+    match metadata.query_output_layout {
+        output::Layout::OneFileOneQuery => Ok(HashSet::new()),
+        _ => {
+            let reader = QueryOutputReader::new(metadata)?;
+            let mut result = HashSet::new();
+            for query in metadata.queries.iter() {
+                if !reader.exists(&query.id)? {
+                    result.insert(query.id.as_str());
+                }
+            }
+            Ok(result)
+        }
+    }
+}
+
+/// Returns the query output before name tagging i.e. the rendered
+/// sql along with the params comment if enabled in the manifest
+fn untagged_query_output<'a>(metadata: &Metadata, rendered: &'a RenderedQuery) -> Cow<'a, str> {
+    match &metadata.query_params {
+        Some(pe) if pe.comment => params::add_params_comment(&rendered.sql, &rendered.params),
+        _ => Cow::from(&rendered.sql),
+    }
+}
+
+/// Returns the query output tagged with the name tag (along with the
+/// description) if name_tagger is configured
+///
+/// Note that the generated marker is not included.
+fn tagged_query_output<'a>(
+    metadata: &Metadata,
+    engine: &Engine,
+    query: &Query,
+    rendered: &'a RenderedQuery,
+) -> Result<Cow<'a, str>, Error> {
+    let q_output = untagged_query_output(metadata, rendered);
+    match &metadata.name_tagger {
+        Some(t) => {
+            let description = engine.render_description(&query.id)?;
+            let tagged = t.ensure_name_tag(
+                &q_output,
+                &query.name_tag,
+                metadata.query_result(query),
+                description.as_deref(),
+            );
+            Ok(Cow::from(tagged.into_owned()))
+        }
+        None => Ok(q_output),
+    }
+}
+
+/// Query to be processed by the `render`, `status` and `diff`
+/// commands as per the selection, along with the selected tests for
+/// it
+struct SelectedQuery<'a> {
+    query: &'a Query,
+    /// Whether the query itself is selected. If false, only the
+    /// `tests` are to be processed.
+    query_selected: bool,
+    tests: Vec<&'a Arc<TestTemplate>>,
+}
 
-            // process and render tests output, then collect in a vec
-            let prep_stmt = match metadata.placeholder {
-                Placeholder::PosArgs => Some(query_output.as_str()),
-                Placeholder::Variables => None,
+/// Returns the queries to be processed as per the `selection` (in
+/// the same order as they are defined in the manifest)
+///
+/// Queries whose ids are in `include` are considered as selected
+/// irrespective of the `selection`.
+fn selected_queries<'a>(
+    metadata: &'a Metadata,
+    selection: &Selection,
+    include: &HashSet<&str>,
+) -> Vec<SelectedQuery<'a>> {
+    let mut result = vec![];
+    for query in metadata.queries.iter() {
+        let query_selected =
+            selection.includes_query(query, metadata) || include.contains(query.id.as_str());
+        let tests = metadata
+            .test_templates
+            .find_by_query(&query.id)
+            .into_iter()
+            .filter(|tt| selection.includes_test(tt, metadata))
+            .collect::<Vec<_>>();
+        if query_selected || !tests.is_empty() {
+            result.push(SelectedQuery {
+                query,
+                query_selected,
+                tests,
+            });
+        }
+    }
+    result
+}
+
+/// Excludes the queries and tests that are fresh as per the cache
+/// from the selected ones
+fn skip_fresh(selected: &mut Vec<SelectedQuery>, fresh: &cache::Fresh) {
+    for sq in selected.iter_mut() {
+        if fresh.has_query(&sq.query.id) {
+            sq.query_selected = false;
+        }
+        sq.tests.retain(|tt| !fresh.has_test(&tt.path));
+    }
+    selected.retain(|sq| sq.query_selected || !sq.tests.is_empty());
+}
+
+/// Renders the selected queries and tests and writes them to the
+/// output files
+///
+/// The output files are written all at once i.e. if an error is
+/// encountered, none of them are modified (see
+/// `output::write_atomically`).
+///
+/// If `use_cache` is true, the queries and tests that are fresh as
+/// per the cache are skipped. The cache is updated irrespective of
+/// it.
+///
+/// Returns the status of every file that's written i.e. the status
+/// as it was before writing the file.
+fn render_selection(
+    metadata: &Metadata,
+    selection: &Selection,
+    jobs: usize,
+    use_cache: bool,
+) -> Result<Vec<(PathBuf, output::Status)>, Error> {
+    let engine = Engine::from(metadata);
+    let formatter = &metadata.formatter;
+    let cache_path = Path::new(cache::CACHE_FILE);
+    let mut cache = Cache::load(cache_path);
+    let inputs = cache::Inputs::compute(metadata)?;
+    let fresh = if use_cache {
+        cache.fresh(metadata, &inputs)?
+    } else {
+        cache::Fresh::default()
+    };
+
+    // In case of 'one-file-all-queries' layout, the queries that are
+    // not selected but are missing from the combined output file
+    // need to be rendered as well.
+    let missing = if selection.is_all() {
+        HashSet::new()
+    } else {
+        missing_from_combined_output(metadata)?
+    };
+
+    let mut selected = selected_queries(metadata, selection, &missing);
+    skip_fresh(&mut selected, &fresh);
+    let rendered = util::parallel_map(&selected, jobs, |sq| {
+        let query = sq.query;
+        let rendered = engine.render_query(&query.id, None)?;
+
+        // process and render tests output
+        let prep_stmt = match metadata.query_placeholder(query) {
+            Placeholder::PosArgs => Some(&rendered),
+            _ => None,
+        };
+        let mut tests_to_write = Vec::with_capacity(sq.tests.len());
+        for tt in &sq.tests {
+            let test_output = engine.render_test(&tt.path, prep_stmt)?;
+            let test_output = if metadata.generated_marker {
+                output::mark_generated(Cow::from(test_output)).into_owned()
+            } else {
+                test_output
             };
-            for tt in metadata.test_templates.find_by_query(&query.id) {
-                let test_output = engine.render_test(&tt.path, prep_stmt)?;
-                let ttw = output::SqlToWrite {
-                    path: &tt.output,
-                    sql: test_output,
-                    name_tag: None,
-                };
-                tests_to_write.push(ttw);
-            }
+            tests_to_write.push(output::SqlToWrite {
+                query_id: None,
+                path: &tt.output,
+                sql: test_output,
+                name_tag: None,
+                result: None,
+                description: None,
+                header: metadata.test_header(tt),
+            });
+        }
 
-            let qtw = output::SqlToWrite {
+        let query_to_write = if sq.query_selected {
+            let query_output = untagged_query_output(metadata, &rendered);
+            let query_output = if metadata.marks_query_outputs() {
+                output::mark_generated(query_output).into_owned()
+            } else {
+                query_output.into_owned()
+            };
+            Some(output::SqlToWrite {
+                query_id: Some(&query.id),
                 path: &query.output,
                 sql: query_output,
                 name_tag: Some(&query.name_tag),
-            };
-            queries_to_write.push(qtw);
-        }
+                result: metadata.query_result(query),
+                description: engine.render_description(&query.id)?,
+                header: metadata.query_header(query),
+            })
+        } else {
+            None
+        };
+        Ok((query_to_write, tests_to_write, rendered))
+    })?;
+
+    let mut queries_to_write: Vec<output::SqlToWrite> = Vec::with_capacity(rendered.len());
+    let mut tests_to_write: Vec<output::SqlToWrite> = Vec::new();
+    // The exports of all the queries are required for checking the
+    // param types across them, even if no files are generated from
+    // the exports
+    let mut exports = Exports::new(metadata).require();
+    for (sq, (qtw, mut ttws, rq)) in selected.iter().zip(rendered) {
+        queries_to_write.extend(qtw);
+        tests_to_write.append(&mut ttws);
+        exports.add(metadata, sq.query, rq);
+    }
 
-        // Write all queries, in a single file or separate files based
-        // on the layout
-        match metadata.query_output_layout {
-            output::Layout::OneFileOneQuery => {
-                output::write_separately(
+    // Collect all queries, in a single file or separate files based
+    // on the layout
+    let mut outputs = match metadata.query_output_layout {
+        output::Layout::OneFileOneQuery => {
+            output::separate_outputs(&queries_to_write, metadata.name_tagger.as_ref())
+        }
+        _ => {
+            let mut outputs = vec![];
+            for filepath in metadata.combined_output_files()? {
+                outputs.extend(output::combined_output(
+                    filepath,
                     &queries_to_write,
-                    formatter.as_ref(),
+                    &metadata.queries.by_output(filepath),
                     metadata.name_tagger.as_ref(),
-                )?;
+                )?);
             }
-            output::Layout::OneFileAllQueries(_) => {
-                output::write_combined(
-                    &queries_to_write,
-                    formatter.as_ref(),
-                    metadata.name_tagger.as_ref(),
-                )?;
+            outputs
+        }
+    };
+    outputs.append(&mut output::separate_outputs(&tests_to_write, None));
+    let num_outputs = outputs.len();
+    outputs.append(&mut exports.take_unformatted());
+
+    // Format all queries, tests and the sql for client code in a
+    // single batch
+    let mut files =
+        output::format_outputs(formatter.as_ref(), outputs, jobs, Path::new(BATCH_DIR))?;
+    exports.complete(files.split_off(num_outputs));
+    // The exports are taken from the cache only if it's in use
+    let no_cache = Cache::default();
+    let exports_cache = if use_cache { &cache } else { &no_cache };
+    let exports = export::query_exports(metadata, &engine, exports, exports_cache, &inputs, jobs)?;
+    let conflicts = export::conflicting_param_types(&exports);
+    if !conflicts.is_empty() {
+        let msgs = conflicts
+            .iter()
+            .map(|m| m.err_msg())
+            .collect::<Vec<String>>();
+        return Err(Error::ParamType(msgs.join("\n")));
+    }
+    files.append(&mut export::generated_files(metadata, &exports)?);
+
+    // Everything is rendered and formatted in memory before writing
+    // any of the files, so that the previous outputs stay as they are
+    // in case of an error
+    let mut stats = Vec::with_capacity(files.len());
+    for file in files.iter() {
+        stats.push((file.path.clone(), file.status()?));
+    }
+    output::ensure_output_dirs(&metadata.queries_output_dir, &metadata.tests_output_dir)?;
+    output::write_atomically(&files)?;
+
+    let rendered = cache::Rendered {
+        queries: selected
+            .iter()
+            .filter(|sq| sq.query_selected)
+            .map(|sq| sq.query.id.as_str())
+            .collect(),
+        tests: selected
+            .iter()
+            .flat_map(|sq| sq.tests.iter().map(|tt| tt.path.as_path()))
+            .collect(),
+    };
+    cache.update(metadata, &inputs, &fresh, &rendered, &files, &exports);
+    cache.save(cache_path)?;
+    Ok(stats)
+}
+
+/// Removes orphaned output files i.e. files inside the output dirs
+/// that are not defined in the manifest
+///
+/// As a safety measure, only the files generated by tapestry are
+/// removed (see `output::is_generated`). In case of the combined
+/// layouts (e.g. `one-file-all-queries`), sections in the combined
+/// output files whose name tags don't match any query are also
+/// removed. If `dry_run` is true, nothing is removed and the
+/// files/sections that would get removed are only printed.
+fn prune(metadata: &Metadata, dry_run: bool) -> Result<(), Error> {
+    let action = if dry_run { "Would remove" } else { "Removed" };
+    let mut orphans = metadata.undefined_query_files()?;
+    orphans.append(&mut metadata.undefined_test_files()?);
+    for path in orphans {
+        if output::prune_file(&path, dry_run)? {
+            println!("{action}: {}", path.display());
+        } else {
+            println!("Skipped (not generated by tapestry): {}", path.display());
+        }
+    }
+
+    for filepath in metadata.combined_output_files()? {
+        if filepath.try_exists().map_err(Error::Io)? {
+            let tagger = metadata.name_tagger.as_ref().ok_or(Error::Layout(
+                "name_tagger is required in case of combined output files".to_string(),
+            ))?;
+            let queries = metadata.queries.by_output(filepath);
+            let removed = output::prune_combined_sql(filepath, tagger, &queries, dry_run)?;
+            for tag in removed {
+                println!("{action}: section '{tag}' in {}", filepath.display());
             }
         }
+    }
+    Ok(())
+}
 
-        // Write all tests
-        output::write_separately(&tests_to_write, formatter.as_ref(), None)?;
+pub fn render(
+    selection: &Selection,
+    prune_orphans: bool,
+    jobs: usize,
+    use_cache: bool,
+) -> Result<i32, Error> {
+    let path = Path::new("tapestry.toml");
+    let metadata = Metadata::try_from(path)?;
+    let mistakes = metadata.validate();
+    if mistakes.is_empty() {
+        selection.ensure_non_empty(&metadata)?;
+        render_selection(&metadata, selection, jobs, use_cache)?;
+        if prune_orphans {
+            prune(&metadata, false)?;
+        }
+        Ok(0)
+    } else {
+        println!("Invalid manifest file: '{}'", path.display());
+        for mistake in mistakes {
+            println!("{}", mistake.err_msg())
+        }
+        Ok(1)
+    }
+}
 
+pub fn clean(dry_run: bool) -> Result<i32, Error> {
+    let path = Path::new("tapestry.toml");
+    let metadata = Metadata::try_from(path)?;
+    let mistakes = metadata.validate();
+    if mistakes.is_empty() {
+        prune(&metadata, dry_run)?;
         Ok(0)
     } else {
         println!("Invalid manifest file: '{}'", path.display());
@@ -104,26 +486,50 @@ pub fn init(dir: &Path) -> Result<i32, Error> {
     })
 }
 
-pub fn summary(include_all: bool) -> Result<i32, Error> {
+pub fn summary(
+    include_all: bool,
+    selection: &Selection,
+    format: OutputFormat,
+) -> Result<i32, Error> {
     let path = Path::new("tapestry.toml");
     let metadata = Metadata::try_from(path)?;
     let mistakes = metadata.validate();
     if mistakes.is_empty() {
-        let header = vec!["Id", "Query", "Template", "Tests"];
+        selection.ensure_non_empty(&metadata)?;
+        let engine = Engine::from(&metadata);
+        let header = vec!["Id", "Description", "Query", "Template", "Tests"];
         let mut rows: Vec<Vec<String>> = Vec::with_capacity(metadata.queries.len());
         let mut qt_used: HashSet<&Path> = HashSet::new();
+        let mut report = Report::new("summary");
         for query in metadata.queries.iter() {
+            let tts = metadata
+                .test_templates
+                .find_by_query(&query.id)
+                .into_iter()
+                .filter(|tt| selection.includes_test(tt, &metadata))
+                .collect::<Vec<_>>();
+            if !selection.includes_query(query, &metadata) && tts.is_empty() {
+                continue;
+            }
             let id = query.id.clone();
+            let description = engine.render_description(&query.id)?;
             let path = query.output.display().to_string();
             let template_path = query.template.display().to_string();
-            let tests = metadata
-                .test_templates
-                .find_by_query(&id)
+            let tests = tts
                 .iter()
                 .map(|t| t.output.display().to_string())
                 .collect::<Vec<String>>()
                 .join("\n");
-            rows.push(vec![id, path, template_path, tests]);
+            rows.push(vec![
+                id,
+                description.clone().unwrap_or_else(|| "-".to_owned()),
+                path,
+                template_path,
+                tests,
+            ]);
+            let test_records = tts.iter().map(|tt| TestRecord::new(tt, None)).collect();
+            let record = QueryRecord::new(query, None, test_records).with_description(description);
+            report.push("query", &record)?;
 
             qt_used.insert(query.template.as_ref());
         }
@@ -131,19 +537,11 @@ pub fn summary(include_all: bool) -> Result<i32, Error> {
         if include_all {
             // Add undefined query files i.e. the query files that
             // exist in the `queries_output_dir` but are not defined
-            // in the manifest. This is a legit use case where the
-            // user has some query files which are not generated by
-            // tapestry (likely when gradually migrating from manually
-            // managed queries to tapestry)
-            let query_paths_defined: HashSet<&Path> =
-                metadata.queries.iter().map(|q| q.output.as_ref()).collect();
-            let query_output_files =
-                ls_files(&metadata.queries_output_dir, false).map_err(Error::Io)?;
-            let query_paths_actual: HashSet<&Path> =
-                query_output_files.iter().map(|p| p.as_ref()).collect();
-            let query_paths_undefined = query_paths_actual.difference(&query_paths_defined);
-            for qp in query_paths_undefined {
+            // in the manifest.
+            for qp in metadata.undefined_query_files()? {
+                report.push("undefined_file", &json!({"kind": "query", "path": qp}))?;
                 rows.push(vec![
+                    "-".to_owned(),
                     "-".to_owned(),
                     format!("{}\n(not defined in manifest)", qp.display()),
                     "-".to_owned(),
@@ -153,84 +551,250 @@ pub fn summary(include_all: bool) -> Result<i32, Error> {
 
             // Add undefined test files i.e. the test files that exist
             // in `tests_output_dir` but are not defined in the
-            // manifest. This is a legit use case where the user has
-            // written pgTAP tests for queries that are not generated
-            // by tapestry. Could also be useful for gradually
-            // migrating from manually managed queries to tapestry.
-            let test_paths_defined: HashSet<&Path> = metadata
-                .test_templates
-                .iter()
-                .map(|tt| tt.output.as_ref())
-                .collect();
-            let test_output_files =
-                ls_files(&metadata.tests_output_dir, false).map_err(Error::Io)?;
-            let test_paths_actual: HashSet<&Path> =
-                test_output_files.iter().map(|p| p.as_ref()).collect();
-            let test_paths_undefined = test_paths_actual.difference(&test_paths_defined);
-            for tp in test_paths_undefined {
+            // manifest.
+            for tp in metadata.undefined_test_files()? {
+                report.push("undefined_file", &json!({"kind": "test", "path": tp}))?;
                 rows.push(vec![
                     "-".to_owned(),
                     "-".to_owned(),
                     "-".to_owned(),
+                    "-".to_owned(),
                     format!("{}\n(not defined in manifest)", tp.display()),
                 ]);
             }
         }
 
-        let mut table = Table::new();
-        table.set_header(header).add_rows(rows);
-        println!("{table}");
+        if format.is_text() {
+            let mut table = Table::new();
+            table.set_header(header).add_rows(rows);
+            println!("{table}");
+        } else {
+            report.print(format)?;
+        }
         Ok(0)
     } else {
-        println!("Invalid manifest file: '{}'", path.display());
-        for mistake in mistakes {
-            println!("{}", mistake.err_msg())
-        }
+        report::print_mistakes("summary", path, &mistakes, format)?;
         Ok(1)
     }
 }
 
-pub fn status(assert_no_changes: bool) -> Result<i32, Error> {
-    let path = Path::new("tapestry.toml");
-    let metadata = Metadata::try_from(path)?;
-    let mistakes = metadata.validate();
-    if mistakes.is_empty() {
-        let engine = Engine::from(&metadata);
-        let formatter = &metadata.formatter;
-        let name_tagger = &metadata.name_tagger;
-        let mut stats: HashMap<&Path, output::Status> = HashMap::new();
-        let query_reader = QueryOutputReader::new(&metadata)?;
-        for query in metadata.queries.iter() {
-            // query output sql (not tagged)
-            let q_output_sql = engine.render_query(&query.id, None)?;
+/// Prints the status of a query output (as printed by the `status`
+/// and `watch` commands)
+fn print_query_status(query: &Query, stat: output::Status) {
+    println!(
+        "Query: {}: {} ({})",
+        stat.label(),
+        query.id,
+        query.output.display()
+    );
+}
+
+/// Prints the status of a test output (as printed by the `status` and
+/// `watch` commands)
+fn print_test_status(tt: &TestTemplate, stat: output::Status) {
+    println!("  Test: {}: {}", stat.label(), tt.output.display());
+}
+
+/// Prints the status of a file generated from the exports of the
+/// queries (as printed by the `status` and `watch` commands)
+fn print_generated_status(path: &Path, stat: output::Status) {
+    println!("Generated: {}: {}", stat.label(), path.display());
+}
 
-            // query output (tagged if name_tagger is configured)
-            let q_output = match name_tagger {
-                Some(t) => t.ensure_name_tag(&q_output_sql, &query.name_tag),
-                None => Cow::from(&q_output_sql),
+/// Prints status of the selected queries and tests i.e. whether the
+/// output files would get added, modified or remain unchanged upon
+/// rendering. Returns the status of every output file.
+///
+/// If a `report` is passed, the status is added to it as records
+/// instead of being printed as text.
+fn print_status(
+    metadata: &Metadata,
+    selection: &Selection,
+    jobs: usize,
+    use_cache: bool,
+    mut report: Option<&mut Report>,
+) -> Result<HashMap<PathBuf, output::Status>, Error> {
+    let engine = Engine::from(metadata);
+    let formatter = &metadata.formatter;
+    let mut stats: HashMap<PathBuf, output::Status> = HashMap::new();
+    let query_reader = QueryOutputReader::new(metadata)?;
+    // Queries and tests that are fresh as per the cache are reported
+    // as unchanged without rendering them. The inputs are not computed
+    // if the cache is not to be used, as that requires running the
+    // external formatter (see `cache::Inputs::compute`).
+    let (inputs, cache) = if use_cache {
+        let inputs = cache::Inputs::compute(metadata)?;
+        (inputs, Cache::load(Path::new(cache::CACHE_FILE)))
+    } else {
+        (cache::Inputs::default(), Cache::default())
+    };
+    let fresh = cache.fresh(metadata, &inputs)?;
+    let selected = selected_queries(metadata, selection, &HashSet::new());
+    // The queries and tests are rendered in parallel. `None` implies
+    // that the output is fresh as per the cache and need not be
+    // checked.
+    let rendered = util::parallel_map(&selected, jobs, |sq| {
+        let query = sq.query;
+        let query_fresh = fresh.has_query(&query.id);
+        let all_fresh = (query_fresh || !sq.query_selected)
+            && sq.tests.iter().all(|tt| fresh.has_test(&tt.path));
+        if all_fresh {
+            let q_output = sq.query_selected.then_some(None);
+            return Ok((q_output, sq.tests.iter().map(|_| None).collect(), None));
+        }
+
+        // query output sql (not tagged)
+        let rendered = engine.render_query(&query.id, None)?;
+
+        let q_output = if sq.query_selected && query_fresh {
+            Some(None)
+        } else if sq.query_selected {
+            let q_output = tagged_query_output(metadata, &engine, query, &rendered)?;
+            let q_output = if metadata.marks_query_outputs() {
+                output::mark_generated(q_output)
+            } else {
+                q_output
             };
+            Some(Some(output::Unformatted::query(
+                formatter.as_ref(),
+                query,
+                &q_output,
+                metadata.query_header(query),
+            )))
+        } else {
+            None
+        };
 
-            let q_stat = output::query_status(query, &query_reader, formatter.as_ref(), &q_output)?;
-            println!(
-                "Query: {}: {} ({})",
-                &q_stat.label(),
-                query.id,
-                query.output.display()
-            );
-            stats.insert(&query.output, q_stat);
+        // render and process tests
+        let prep_stmt = match metadata.query_placeholder(query) {
+            Placeholder::PosArgs => Some(&rendered),
+            _ => None,
+        };
+        let mut t_outputs = Vec::with_capacity(sq.tests.len());
+        for tt in &sq.tests {
+            if fresh.has_test(&tt.path) {
+                t_outputs.push(None);
+                continue;
+            }
+            let t_output = engine.render_test(&tt.path, prep_stmt)?;
+            let t_output = if metadata.generated_marker {
+                output::mark_generated(Cow::from(t_output)).into_owned()
+            } else {
+                t_output
+            };
+            t_outputs.push(Some(output::Unformatted::test(
+                &tt.output,
+                t_output,
+                metadata.test_header(tt),
+            )));
+        }
+        Ok((q_output, t_outputs, Some(rendered)))
+    })?;
 
-            // render and process tests
-            let prep_stmt = match metadata.placeholder {
-                Placeholder::PosArgs => Some(q_output_sql.as_str()),
-                Placeholder::Variables => None,
+    // All the rendered outputs are formatted in a single batch.
+    // `pending` keeps track of which of them are to be checked.
+    let mut outputs = vec![];
+    let mut pending = Vec::with_capacity(rendered.len());
+    let mut exports = Exports::new(metadata);
+    for (sq, (q_output, t_outputs, rq)) in selected.iter().zip(rendered) {
+        if let Some(rq) = rq {
+            exports.add(metadata, sq.query, rq);
+        }
+        let q_pending = q_output.map(|o| match o {
+            Some(o) => {
+                outputs.push(o);
+                true
+            }
+            None => false,
+        });
+        let mut t_pending = Vec::with_capacity(t_outputs.len());
+        for o in t_outputs.into_iter() {
+            t_pending.push(o.is_some());
+            outputs.extend(o);
+        }
+        pending.push((q_pending, t_pending));
+    }
+    let num_outputs = outputs.len();
+    outputs.append(&mut exports.take_unformatted());
+    let mut formatted =
+        output::format_outputs(formatter.as_ref(), outputs, jobs, Path::new(BATCH_DIR))?;
+    exports.complete(formatted.split_off(num_outputs));
+    let mut formatted = formatted.into_iter();
+
+    for (sq, (q_pending, t_pending)) in selected.iter().zip(pending) {
+        let query = sq.query;
+        // @UNWRAP: There's a formatted output for every pending
+        // query and test
+        let q_stat = match q_pending {
+            Some(true) => Some(output::query_status(
+                query,
+                &query_reader,
+                formatted.next().unwrap().contents.as_bytes(),
+            )?),
+            Some(false) => Some(output::Status::Unchanged),
+            None => None,
+        };
+        if let Some(stat) = q_stat {
+            if report.is_none() {
+                print_query_status(query, stat);
+            }
+            stats.insert(query.output.clone(), stat);
+        }
+
+        let mut test_records = Vec::with_capacity(t_pending.len());
+        for (tt, pending) in sq.tests.iter().zip(t_pending) {
+            let t_stat = if pending {
+                output::testfile_status(&tt.output, formatted.next().unwrap().contents.as_bytes())?
+            } else {
+                output::Status::Unchanged
             };
-            for tt in metadata.test_templates.find_by_query(&query.id) {
-                let t_output = engine.render_test(&tt.path, prep_stmt)?;
-                let t_stat = output::testfile_status(&tt.output, formatter.as_ref(), &t_output)?;
-                println!("  Test: {}: {}", &t_stat.label(), &tt.output.display());
-                stats.insert(&tt.output, t_stat);
+            if report.is_none() {
+                print_test_status(tt, t_stat);
             }
+            stats.insert(tt.output.clone(), t_stat);
+            test_records.push(TestRecord::new(tt, Some(t_stat)));
+        }
+
+        if let Some(r) = report.as_deref_mut() {
+            r.push("query", &QueryRecord::new(query, q_stat, test_records))?;
         }
+    }
+
+    let exports = export::query_exports(metadata, &engine, exports, &cache, &inputs, jobs)?;
+    for file in export::generated_files(metadata, &exports)? {
+        let stat = file.status()?;
+        match report.as_deref_mut() {
+            Some(r) => r.push(
+                "generated_file",
+                &json!({"path": file.path, "status": stat}),
+            )?,
+            None => print_generated_status(&file.path, stat),
+        }
+        stats.insert(file.path, stat);
+    }
+    Ok(stats)
+}
+
+pub fn status(
+    assert_no_changes: bool,
+    selection: &Selection,
+    jobs: usize,
+    use_cache: bool,
+    format: OutputFormat,
+) -> Result<i32, Error> {
+    let path = Path::new("tapestry.toml");
+    let metadata = Metadata::try_from(path)?;
+    let mistakes = metadata.validate();
+    if mistakes.is_empty() {
+        selection.ensure_non_empty(&metadata)?;
+        let stats = if format.is_text() {
+            print_status(&metadata, selection, jobs, use_cache, None)?
+        } else {
+            let mut report = Report::new("status");
+            let stats = print_status(&metadata, selection, jobs, use_cache, Some(&mut report))?;
+            report.print(format)?;
+            stats
+        };
         let exit_code = if assert_no_changes {
             let no_changes = stats
                 .values()
@@ -244,6 +808,121 @@ pub fn status(assert_no_changes: bool) -> Result<i32, Error> {
             0
         };
         Ok(exit_code)
+    } else {
+        report::print_mistakes("status", path, &mistakes, format)?;
+        Ok(1)
+    }
+}
+
+pub fn diff(selection: &Selection, color: bool, stat: bool, jobs: usize) -> Result<i32, Error> {
+    let path = Path::new("tapestry.toml");
+    let metadata = Metadata::try_from(path)?;
+    let mistakes = metadata.validate();
+    if mistakes.is_empty() {
+        selection.ensure_non_empty(&metadata)?;
+        let engine = Engine::from(&metadata);
+        let formatter = metadata.formatter.as_ref();
+        let query_reader = QueryOutputReader::new(&metadata)?;
+        let combined = metadata.query_output_layout.is_combined();
+        let selected = selected_queries(&metadata, selection, &HashSet::new());
+        let diffs = util::parallel_map(&selected, jobs, |sq| {
+            let query = sq.query;
+            let mut diffs = Vec::with_capacity(sq.tests.len() + 1);
+
+            // query output sql (not tagged)
+            let rendered = engine.render_query(&query.id, None)?;
+
+            if sq.query_selected {
+                let q_output = tagged_query_output(&metadata, &engine, query, &rendered)?;
+                let q_output = if metadata.marks_query_outputs() {
+                    output::mark_generated(q_output)
+                } else {
+                    q_output
+                };
+                let current = if query_reader.exists(&query.id)? {
+                    Some(query_reader.read(&query.id)?)
+                } else {
+                    None
+                };
+                // In case of combined layout, the diff is per query
+                // section in the combined output file
+                let label = if combined {
+                    format!("{} ({})", query.output.display(), query.id)
+                } else {
+                    query.output.display().to_string()
+                };
+                let unformatted = output::Unformatted::query(
+                    formatter,
+                    query,
+                    &q_output,
+                    metadata.query_header(query),
+                );
+                diffs.push((label, current, unformatted));
+            }
+
+            let prep_stmt = match metadata.query_placeholder(query) {
+                Placeholder::PosArgs => Some(&rendered),
+                _ => None,
+            };
+            for tt in &sq.tests {
+                let t_output = engine.render_test(&tt.path, prep_stmt)?;
+                let t_output = if metadata.generated_marker {
+                    output::mark_generated(Cow::from(t_output)).into_owned()
+                } else {
+                    t_output
+                };
+                let current = output::read_testfile(&tt.output)?;
+                let unformatted =
+                    output::Unformatted::test(&tt.output, t_output, metadata.test_header(tt));
+                diffs.push((tt.output.display().to_string(), current, unformatted));
+            }
+            Ok((diffs, rendered))
+        })?;
+        // The rendered outputs are formatted in a single batch
+        let mut outputs = vec![];
+        let mut labels = vec![];
+        let mut exports = Exports::new(&metadata);
+        for (sq, (q_diffs, rq)) in selected.iter().zip(diffs) {
+            exports.add(&metadata, sq.query, rq);
+            for (label, current, unformatted) in q_diffs {
+                labels.push((label, current));
+                outputs.push(unformatted);
+            }
+        }
+        let num_outputs = outputs.len();
+        outputs.append(&mut exports.take_unformatted());
+        let mut formatted = output::format_outputs(formatter, outputs, jobs, Path::new(BATCH_DIR))?;
+        exports.complete(formatted.split_off(num_outputs));
+        let mut diffs = labels
+            .into_iter()
+            .zip(formatted)
+            .map(|((label, current), file)| {
+                OutputDiff::new(label, current, file.contents.into_bytes())
+            })
+            .collect::<Vec<OutputDiff>>();
+
+        // Exports of the queries that are not selected are taken from
+        // the cache (if their inputs haven't changed)
+        let inputs = cache::Inputs::compute(&metadata)?;
+        let cache = Cache::load(Path::new(cache::CACHE_FILE));
+        let exports = export::query_exports(&metadata, &engine, exports, &cache, &inputs, jobs)?;
+        for file in export::generated_files(&metadata, &exports)? {
+            let current = file.current()?;
+            diffs.push(OutputDiff::new(
+                file.path.display().to_string(),
+                current,
+                file.contents.into_bytes(),
+            ));
+        }
+
+        if stat {
+            print!("{}", diff::stat_summary(&diffs, color));
+        } else {
+            for d in diffs.iter().filter(|d| d.has_changes()) {
+                print!("{}", d.unified(color));
+            }
+        }
+        Ok(0)
     } else {
         println!("Invalid manifest file: '{}'", path.display());
         for mistake in mistakes {
@@ -262,7 +941,7 @@ pub fn cov_threshold_parser(value: &str) -> Result<u8, String> {
     }
 }
 
-pub fn coverage(fail_under: Option<u8>) -> Result<i32, Error> {
+pub fn coverage(fail_under: Option<u8>, format: OutputFormat) -> Result<i32, Error> {
     let path = Path::new("tapestry.toml");
     let metadata = Metadata::try_from(path)?;
     let mistakes = metadata.validate();
@@ -271,8 +950,13 @@ pub fn coverage(fail_under: Option<u8>) -> Result<i32, Error> {
         let mut untested: Vec<&str> = Vec::new();
         let header = vec!["Query", "Has tests?"];
         let mut rows: Vec<Vec<String>> = Vec::with_capacity(num_queries + 1);
+        let mut report = Report::new("coverage");
         for query in metadata.queries.iter() {
             let tts = metadata.test_templates.find_by_query(&query.id);
+            report.push(
+                "query_coverage",
+                &json!({"id": query.id, "num_tests": tts.len()}),
+            )?;
             if tts.is_empty() {
                 untested.push(&query.id);
             }
@@ -293,27 +977,129 @@ pub fn coverage(fail_under: Option<u8>) -> Result<i32, Error> {
             format!("{pcent_cov:.02}%\n({num_tested}/{num_queries} queries have at least 1 test)"),
         ]);
 
+        report.push(
+            "coverage",
+            &json!({
+                "num_queries": num_queries,
+                "num_tested": num_tested,
+                // Rounded to 2 decimal places, same as the text output
+                "percentage": (pcent_cov as f64 * 100.0).round() / 100.0,
+                "fail_under": fail_under,
+            }),
+        )?;
+
         // Print table
-        let mut table = Table::new();
-        table.set_header(header).add_rows(rows);
-        println!("{table}");
+        if format.is_text() {
+            let mut table = Table::new();
+            table.set_header(header).add_rows(rows);
+            println!("{table}");
+        } else {
+            report.print(format)?;
+        }
 
         let exit_code = match fail_under {
-            Some(threshold) => {
-                if pcent_cov < (threshold as f32) {
-                    1
-                } else {
-                    0
-                }
-            }
-            None => 0,
+            Some(threshold) if pcent_cov < (threshold as f32) => 1,
+            _ => 0,
         };
         Ok(exit_code)
     } else {
-        println!("Invalid manifest file: '{}'", path.display());
+        report::print_mistakes("coverage", path, &mistakes, format)?;
+        Ok(1)
+    }
+}
+
+/// Re-renders the queries and tests affected by the `changed` paths
+///
+/// The paths watched by the `watcher` are updated as per the
+/// (possibly modified) manifest file.
+fn rerender(
+    manifest: &Path,
+    changed: &[PathBuf],
+    watcher: &mut Watcher,
+    jobs: usize,
+) -> Result<(), Error> {
+    let metadata = Metadata::try_from(manifest)?;
+    watcher.set_paths(vec![
+        manifest.to_path_buf(),
+        metadata.query_templates_dir.clone(),
+        metadata.test_templates_dir.clone(),
+    ]);
+    let mistakes = metadata.validate();
+    if mistakes.is_empty() {
+        let selection = TemplateIndex::from(&metadata).affected(changed);
+        // The status is derived from the rendered files instead of
+        // rendering them once again. In case of the combined layouts,
+        // the status of the files doesn't tell which queries changed,
+        // hence the sections are compared with the ones read before
+        // rendering.
+        let before = QueryOutputReader::new(&metadata)?;
+        let rendered = render_selection(&metadata, &selection, jobs, true)?;
+        let stats = rendered
+            .iter()
+            .map(|(path, stat)| (path.as_path(), *stat))
+            .collect::<HashMap<&Path, output::Status>>();
+        let after = QueryOutputReader::new(&metadata)?;
+        let combined = metadata.query_output_layout.is_combined();
+        // Queries and tests that are fresh as per the cache are not
+        // rendered, hence they are unchanged
+        let unchanged = output::Status::Unchanged;
+        for sq in selected_queries(&metadata, &selection, &HashSet::new()) {
+            let query = sq.query;
+            if sq.query_selected {
+                let stat = if combined {
+                    output::query_status(query, &before, &after.read(&query.id)?)?
+                } else {
+                    stats
+                        .get(query.output.as_path())
+                        .copied()
+                        .unwrap_or(unchanged)
+                };
+                print_query_status(query, stat);
+            }
+            for tt in sq.tests {
+                let stat = stats.get(tt.output.as_path()).copied();
+                print_test_status(tt, stat.unwrap_or(unchanged));
+            }
+        }
+        let outputs = metadata
+            .queries
+            .output_files()
+            .chain(metadata.test_templates.iter().map(|tt| tt.output.as_path()))
+            .collect::<HashSet<&Path>>();
+        for (path, stat) in &rendered {
+            if !outputs.contains(path.as_path()) {
+                print_generated_status(path, *stat);
+            }
+        }
+    } else {
+        println!("Invalid manifest file: '{}'", manifest.display());
         for mistake in mistakes {
             println!("{}", mistake.err_msg())
         }
-        Ok(1)
+    }
+    Ok(())
+}
+
+pub fn watch(interval: u64, jobs: usize) -> Result<i32, Error> {
+    let path = Path::new("tapestry.toml");
+    // Fail early if the manifest is not found. Once the watcher has
+    // started, errors are reported and the watcher keeps running.
+    let _ = Metadata::try_from(path)?;
+    let mut watcher = Watcher::new(vec![path.to_path_buf()]);
+    // Initially, render everything i.e. as if the manifest file has
+    // changed
+    let mut changed = vec![path.to_path_buf()];
+    loop {
+        if !changed.is_empty() {
+            for p in changed.iter() {
+                println!("Changed: {}", p.display());
+            }
+            if let Err(e) = rerender(path, &changed, &mut watcher, jobs) {
+                eprintln!("{e}");
+            }
+            println!("Watching for changes...");
+        }
+        thread::sleep(Duration::from_millis(interval));
+        changed = watcher.changes();
     }
 }
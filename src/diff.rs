@@ -0,0 +1,188 @@
+use similar::{ChangeTag, TextDiff};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Max width of the +/- bar in the `--stat` summary
+const STAT_BAR_WIDTH: usize = 50;
+
+/// Difference between the contents of an existing output file (or a
+/// section of it in case of the combined layout) and the freshly
+/// rendered output
+pub struct OutputDiff {
+    /// Label to identify the output (typically the path)
+    pub label: String,
+    /// Current contents. `None` if the output doesn't exist yet
+    pub current: Option<String>,
+    /// Rendered contents (after formatting if applicable)
+    pub rendered: String,
+}
+
+impl OutputDiff {
+    pub fn new(label: String, current: Option<Vec<u8>>, rendered: Vec<u8>) -> Self {
+        Self {
+            label,
+            current: current.map(|bs| String::from_utf8_lossy(&bs).into_owned()),
+            rendered: String::from_utf8_lossy(&rendered).into_owned(),
+        }
+    }
+
+    pub fn has_changes(&self) -> bool {
+        self.current.as_deref() != Some(self.rendered.as_str())
+    }
+
+    /// Returns the diff in unified format, optionally with ANSI color
+    /// codes
+    pub fn unified(&self, color: bool) -> String {
+        let current = self.current.as_deref().unwrap_or("");
+        let old_header = match self.current {
+            Some(_) => format!("a/{}", self.label),
+            None => "/dev/null".to_owned(),
+        };
+        let new_header = format!("b/{}", self.label);
+        let text_diff = TextDiff::from_lines(current, self.rendered.as_str());
+        let udiff = text_diff
+            .unified_diff()
+            .header(&old_header, &new_header)
+            .to_string();
+        if color {
+            colorize(&udiff)
+        } else {
+            udiff
+        }
+    }
+
+    /// Returns the no. of inserted and deleted lines
+    pub fn stat(&self) -> (usize, usize) {
+        let current = self.current.as_deref().unwrap_or("");
+        let text_diff = TextDiff::from_lines(current, self.rendered.as_str());
+        let mut insertions = 0;
+        let mut deletions = 0;
+        for change in text_diff.iter_all_changes() {
+            match change.tag() {
+                ChangeTag::Insert => insertions += 1,
+                ChangeTag::Delete => deletions += 1,
+                ChangeTag::Equal => {}
+            }
+        }
+        (insertions, deletions)
+    }
+}
+
+fn colorize(udiff: &str) -> String {
+    let mut result = String::with_capacity(udiff.len());
+    for line in udiff.lines() {
+        let style = if line.starts_with("---") || line.starts_with("+++") {
+            Some(BOLD)
+        } else if line.starts_with("@@") {
+            Some(CYAN)
+        } else if line.starts_with('-') {
+            Some(RED)
+        } else if line.starts_with('+') {
+            Some(GREEN)
+        } else {
+            None
+        };
+        match style {
+            Some(s) => {
+                result.push_str(s);
+                result.push_str(line);
+                result.push_str(RESET);
+            }
+            None => result.push_str(line),
+        }
+        result.push('\n');
+    }
+    result
+}
+
+/// Formats the `--stat` summary of multiple diffs similar to `git
+/// diff --stat`
+pub fn stat_summary(diffs: &[OutputDiff], color: bool) -> String {
+    let changed = diffs.iter().filter(|d| d.has_changes()).collect::<Vec<_>>();
+    let width = changed.iter().map(|d| d.label.len()).max().unwrap_or(0);
+    let mut result = String::new();
+    let mut total_ins = 0;
+    let mut total_del = 0;
+    for d in changed.iter() {
+        let (ins, del) = d.stat();
+        total_ins += ins;
+        total_del += del;
+        // Scale down the bar if it doesn't fit in the max width
+        let total = ins + del;
+        let (n_plus, n_minus) = if total > STAT_BAR_WIDTH {
+            let n_plus = ins * STAT_BAR_WIDTH / total;
+            (n_plus, STAT_BAR_WIDTH - n_plus)
+        } else {
+            (ins, del)
+        };
+        let (plus, minus) = ("+".repeat(n_plus), "-".repeat(n_minus));
+        let bar = if color {
+            format!("{GREEN}{plus}{RESET}{RED}{minus}{RESET}")
+        } else {
+            format!("{plus}{minus}")
+        };
+        result.push_str(&format!(
+            " {:width$} | {:>4} {bar}\n",
+            d.label,
+            total,
+            width = width
+        ));
+    }
+    result.push_str(&format!(
+        " {} output(s) changed, {total_ins} insertion(s)(+), {total_del} deletion(s)(-)\n",
+        changed.len()
+    ));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_output_diff() {
+        let current = "SELECT\n    *\nFROM\n    foo;\n";
+        let rendered = "SELECT\n    *\nFROM\n    bar;\n";
+        let d = OutputDiff::new(
+            "output/queries/foo.sql".to_owned(),
+            Some(current.as_bytes().to_vec()),
+            rendered.as_bytes().to_vec(),
+        );
+        assert!(d.has_changes());
+        assert_eq!((1, 1), d.stat());
+        let expected = r#"--- a/output/queries/foo.sql
++++ b/output/queries/foo.sql
+@@ -1,4 +1,4 @@
+ SELECT
+     *
+ FROM
+-    foo;
++    bar;
+"#;
+        assert_eq!(expected, d.unified(false));
+
+        // When the output file doesn't exist
+        let d = OutputDiff::new(
+            "output/queries/foo.sql".to_owned(),
+            None,
+            rendered.as_bytes().to_vec(),
+        );
+        assert!(d.has_changes());
+        assert_eq!((4, 0), d.stat());
+        assert!(d.unified(false).starts_with("--- /dev/null\n"));
+
+        // When there are no changes
+        let d = OutputDiff::new(
+            "output/queries/foo.sql".to_owned(),
+            Some(current.as_bytes().to_vec()),
+            current.as_bytes().to_vec(),
+        );
+        assert!(!d.has_changes());
+        assert_eq!((0, 0), d.stat());
+    }
+}
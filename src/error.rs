@@ -5,6 +5,7 @@ use std::io;
 pub enum Error {
     Io(io::Error),
     Toml(toml::de::Error),
+    TomlSer(toml::ser::Error),
     Parsing(String),
     UndefinedQuery(String),
     UndefinedQueryTemplate(String),
@@ -15,6 +16,20 @@ pub enum Error {
     QueryOutputParsing(String),
     Layout(String),
     Cli(String),
+    Json(serde_json::Error),
+    ParamType(String),
+    Codegen(String),
+    /// Failure of an external formatter along with it's exit code
+    /// (`None` if it couldn't be run or was killed upon timeout) and
+    /// the output on stderr
+    Formatter {
+        exec: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// An external formatter exited successfully but returned empty
+    /// output for non-empty sql
+    FormatterEmptyOutput(String),
 }
 
 impl Display for Error {
@@ -29,6 +44,7 @@ impl Display for Error {
             }
             Self::Io(e) => write!(f, "I/O Error: {e:?}"),
             Self::Toml(e) => write!(f, "TOML Error: {e:?}"),
+            Self::TomlSer(e) => write!(f, "TOML Error: {e:?}"),
             Self::Parsing(msg) => write!(f, "Error parsing manifest file: {msg}"),
             Self::UndefinedQuery(id) => write!(f, "Lookup for query failed: id={id}"),
             Self::UndefinedQueryTemplate(path) => {
@@ -42,6 +58,24 @@ impl Display for Error {
                 write!(f, "Failed to parse the query output file: {msg}")
             }
             Self::Layout(msg) => write!(f, "Layout error: {msg}"),
+            Self::Json(e) => write!(f, "JSON Error: {e:?}"),
+            Self::ParamType(msg) => write!(f, "Param type error: {msg}"),
+            Self::Codegen(msg) => write!(f, "Codegen error: {msg}"),
+            Self::Formatter { exec, code, stderr } => {
+                match code {
+                    Some(c) => write!(f, "Formatter error: '{exec}' exited with code {c}")?,
+                    None => write!(f, "Formatter error: '{exec}' failed")?,
+                }
+                let stderr = stderr.trim_end();
+                if !stderr.is_empty() {
+                    write!(f, "\n{stderr}")?;
+                }
+                Ok(())
+            }
+            Self::FormatterEmptyOutput(exec) => write!(
+                f,
+                "Formatter error: '{exec}' returned empty output for non-empty SQL"
+            ),
         }
     }
 }
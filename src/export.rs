@@ -0,0 +1,431 @@
+use crate::cache::{Cache, Inputs};
+use crate::codegen;
+use crate::error::Error;
+use crate::formatters::BATCH_DIR;
+use crate::metadata::Metadata;
+use crate::output::{self, GeneratedFile, Unformatted};
+use crate::params;
+use crate::query::Query;
+use crate::render::{Engine, Param, RenderedQuery};
+use crate::tagging::extract_name_tag;
+use crate::util::parallel_map;
+use crate::validation::ManifestMistake;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Outcome of rendering a query that's required for generating the
+/// files that depend on all the queries i.e. the query params sidecar
+/// files and the client code
+///
+/// It's stored in the cache along with the hash of the inputs of the
+/// query, so that the queries that are not rendered in the current
+/// run need not be rendered again just for the sake of these files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryExport {
+    /// Name in the name tag that the query template begins with (if
+    /// any)
+    pub name_tag: Option<String>,
+    /// Formatted sql without the name tag. It's set only if client
+    /// code is to be generated.
+    pub sql: Option<String>,
+    pub params: Vec<Param>,
+}
+
+/// Returns whether any files are to be generated from the exports of
+/// the queries as per the manifest
+pub fn is_required(metadata: &Metadata) -> bool {
+    metadata.query_params.is_some() || !metadata.codegen.is_empty()
+}
+
+/// Exports of the queries rendered in the current run
+///
+/// If client code is to be generated, the exports are complete only
+/// after the sql of the queries is formatted. To avoid invoking the
+/// formatter separately, the sql is meant to be formatted along with
+/// the other outputs in a single batch (refer to `take_unformatted`
+/// and `complete`).
+pub struct Exports<'a> {
+    required: bool,
+    codegen: bool,
+    inner: HashMap<&'a str, QueryExport>,
+    /// Ids of the queries whose sql is to be formatted, in the same
+    /// order as `unformatted`
+    pending: Vec<&'a str>,
+    unformatted: Vec<Unformatted>,
+}
+
+impl<'a> Exports<'a> {
+    pub fn new(metadata: &Metadata) -> Self {
+        Self {
+            required: is_required(metadata),
+            codegen: !metadata.codegen.is_empty(),
+            inner: HashMap::new(),
+            pending: vec![],
+            unformatted: vec![],
+        }
+    }
+
+    /// Adds the export of the rendered query (unless not required)
+    pub fn add(&mut self, metadata: &Metadata, query: &'a Query, rendered: RenderedQuery) {
+        if !self.required {
+            return;
+        }
+        if self.codegen {
+            let formatter = metadata.formatter.as_ref();
+            let unformatted = Unformatted::query(formatter, query, &rendered.sql, None);
+            self.pending.push(query.id.as_str());
+            self.unformatted.push(unformatted);
+        }
+        let export = QueryExport {
+            name_tag: extract_name_tag(&rendered.sql).map(|s| s.to_owned()),
+            sql: None,
+            params: rendered.params,
+        };
+        self.inner.insert(query.id.as_str(), export);
+    }
+
+    /// Exports that only consist of the params of the queries,
+    /// irrespective of the manifest, for checking the param types
+    /// across all the queries (see `conflicting_param_types`)
+    pub fn params_only() -> Self {
+        Self {
+            required: true,
+            codegen: false,
+            inner: HashMap::new(),
+            pending: vec![],
+            unformatted: vec![],
+        }
+    }
+
+    /// Makes the exports required irrespective of the manifest, so
+    /// that the param types can be checked across all the queries
+    /// upon rendering (see `conflicting_param_types`)
+    pub fn require(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// Returns the sql of the queries that's pending formatting
+    pub fn take_unformatted(&mut self) -> Vec<Unformatted> {
+        std::mem::take(&mut self.unformatted)
+    }
+
+    /// Completes the exports with the formatted sql i.e. the result of
+    /// formatting the outputs returned by `take_unformatted` (in the
+    /// same order)
+    pub fn complete(&mut self, formatted: Vec<GeneratedFile>) {
+        for (id, file) in self.pending.drain(..).zip(formatted) {
+            if let Some(export) = self.inner.get_mut(id) {
+                export.sql = Some(codegen::strip_name_tag(file.contents.trim()).to_owned());
+            }
+        }
+    }
+}
+
+/// Returns the exports of all the queries in the same order as they
+/// are defined in the manifest
+///
+/// The exports of the queries rendered in the current run are to be
+/// passed as `rendered`. For the rest of the queries, they are taken
+/// from the `cache` if the inputs of the query haven't changed since
+/// it was cached. Only the remaining ones are rendered (in parallel
+/// using upto `jobs` threads). The result is empty if the exports are
+/// not required at all (see `is_required`).
+pub fn query_exports<'a>(
+    metadata: &'a Metadata,
+    engine: &Engine,
+    rendered: Exports<'a>,
+    cache: &Cache,
+    inputs: &Inputs,
+    jobs: usize,
+) -> Result<Vec<(&'a Query, QueryExport)>, Error> {
+    if !rendered.required {
+        return Ok(vec![]);
+    }
+    let codegen = rendered.codegen;
+    let mut exports = rendered.inner;
+    let mut missing = vec![];
+    for query in metadata.queries.iter() {
+        if exports.contains_key(query.id.as_str()) {
+            continue;
+        }
+        // The cached export is not usable for client code if the sql
+        // wasn't exported at the time of caching
+        match cache.export(&query.id, inputs) {
+            Some(e) if !codegen || e.sql.is_some() => {
+                exports.insert(query.id.as_str(), e.clone());
+            }
+            _ => missing.push(query.as_ref()),
+        }
+    }
+    let mut remaining = Exports {
+        required: true,
+        codegen,
+        inner: HashMap::new(),
+        pending: vec![],
+        unformatted: vec![],
+    };
+    let rendered = parallel_map(&missing, jobs, |query| engine.render_query(&query.id, None))?;
+    for (query, rq) in missing.into_iter().zip(rendered) {
+        remaining.add(metadata, query, rq);
+    }
+    let unformatted = remaining.take_unformatted();
+    if !unformatted.is_empty() {
+        let formatter = metadata.formatter.as_ref();
+        remaining.complete(output::format_outputs(
+            formatter,
+            unformatted,
+            jobs,
+            Path::new(BATCH_DIR),
+        )?);
+    }
+    exports.extend(remaining.inner);
+
+    let mut result = Vec::with_capacity(exports.len());
+    for query in metadata.queries.iter() {
+        // @UNWRAP: Exports of all the queries are collected above
+        let export = exports.remove(query.id.as_str()).unwrap();
+        result.push((query.as_ref(), export));
+    }
+    Ok(result)
+}
+
+/// Returns mistakes for the params that are declared with different
+/// types in different queries
+///
+/// The params are matched by name across all the queries,
+/// irrespective of the query templates they are defined in. Params
+/// without a type are not considered. Conflicts within a query are
+/// reported upon rendering it (see `Engine::render_query`).
+pub fn conflicting_param_types<'a>(
+    exports: &[(&'a Query, QueryExport)],
+) -> Vec<ManifestMistake<'a>> {
+    let mut names: Vec<&str> = vec![];
+    // Types of every param along with the ids of the queries in which
+    // it's declared with a type (in order of occurrence)
+    let mut types_by_name: HashMap<&str, (Vec<&str>, Vec<&'a str>)> = HashMap::new();
+    for (query, export) in exports {
+        let query_id: &'a str = query.id.as_str();
+        for param in &export.params {
+            let sql_type = match param.sql_type.as_deref() {
+                Some(t) => t,
+                None => continue,
+            };
+            let (types, ids) = types_by_name.entry(param.name.as_str()).or_insert_with(|| {
+                names.push(param.name.as_str());
+                (vec![], vec![])
+            });
+            if !types.contains(&sql_type) {
+                types.push(sql_type);
+            }
+            if !ids.contains(&query_id) {
+                ids.push(query_id);
+            }
+        }
+    }
+    let mut mistakes = vec![];
+    for name in names {
+        // @UNWRAP: All names are inserted in the map above
+        let (types, query_ids) = types_by_name.remove(name).unwrap();
+        if types.len() > 1 {
+            mistakes.push(ManifestMistake::ConflictingParamTypes {
+                param: name.to_owned(),
+                types: types.into_iter().map(String::from).collect(),
+                query_ids,
+            });
+        }
+    }
+    mistakes
+}
+
+/// Returns the output files other than queries and tests that are
+/// generated from the manifest (e.g. query params sidecar files,
+/// client code)
+///
+/// These files depend on all the queries, hence they are always
+/// generated in entirety irrespective of any selection (refer to
+/// `query_exports`).
+pub fn generated_files(
+    metadata: &Metadata,
+    exports: &[(&Query, QueryExport)],
+) -> Result<Vec<GeneratedFile>, Error> {
+    let mut result = params::generated_files(metadata, exports)?;
+    result.append(&mut codegen::generated_files(metadata, exports)?);
+    Ok(result)
+}
+
+#[cfg(test)]
+pub mod test_util {
+
+    use super::*;
+    use crate::query::test_util::setup_queries;
+    use crate::query_template::test_util::setup_query_templates;
+    use std::path::PathBuf;
+
+    // Setup metadata with queries for the `artists_long_songs.sql.j2`
+    // template of the chinook example
+    pub fn setup_metadata() -> Metadata {
+        let dir = "examples/chinook/templates/queries";
+        let template = format!("{dir}/artists_long_songs.sql.j2");
+        let mut metadata = Metadata::default();
+        metadata.query_templates_dir = PathBuf::from(dir);
+        metadata.query_templates = setup_query_templates(vec![(&template, vec!["genre", "limit"])]);
+        metadata.queries = setup_queries(vec![
+            (
+                "artists_long_songs",
+                &template,
+                vec![],
+                "output/queries/artists_long_songs.sql",
+                None,
+            ),
+            (
+                "artists_long_songs@genre*limit",
+                &template,
+                vec!["genre", "limit"],
+                "output/queries/artists_long_songs-genre-limit.sql",
+                None,
+            ),
+        ]);
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::test_util::*;
+    use super::*;
+    use crate::params::ParamsExport;
+    use crate::query::test_util::setup_queries;
+    use std::path::Path;
+    use toml::Value;
+
+    #[test]
+    fn test_query_exports() {
+        let cache = Cache::default();
+
+        // When the exports are not required
+        let metadata = setup_metadata();
+        let inputs = Inputs::compute(&metadata).unwrap();
+        let engine = Engine::from(&metadata);
+        let exports = Exports::new(&metadata);
+        assert!(
+            query_exports(&metadata, &engine, exports, &cache, &inputs, 1)
+                .unwrap()
+                .is_empty()
+        );
+
+        let mut metadata = setup_metadata();
+        metadata.query_params = Some(
+            ParamsExport::decode(
+                &Value::Table(toml::Table::new()),
+                Path::new("output/queries"),
+            )
+            .unwrap(),
+        );
+        let inputs = Inputs::compute(&metadata).unwrap();
+        let engine = Engine::from(&metadata);
+
+        // Exports of the queries rendered in the current run are used
+        // as they are and the rest of the queries are rendered
+        let mut exports = Exports::new(&metadata);
+        let query = metadata.queries.get("artists_long_songs").unwrap();
+        let rendered = RenderedQuery {
+            sql: String::from("SELECT 1;"),
+            params: vec![Param {
+                name: "dummy".to_owned(),
+                placeholder: "$1".to_owned(),
+                sql_type: None,
+            }],
+        };
+        exports.add(&metadata, query, rendered);
+        let result = query_exports(&metadata, &engine, exports, &cache, &inputs, 1).unwrap();
+        let summary = result
+            .iter()
+            .map(|(q, e)| {
+                let params = e
+                    .params
+                    .iter()
+                    .map(|p| format!("{}:{}", p.name, p.sql_type.as_deref().unwrap_or("-")))
+                    .collect::<Vec<String>>();
+                (q.id.as_str(), params)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("artists_long_songs", vec!["dummy:-".to_owned()]),
+                (
+                    "artists_long_songs@genre*limit",
+                    vec!["genre:varchar".to_owned(), "limit:int".to_owned()]
+                ),
+            ],
+            summary
+        );
+    }
+
+    #[test]
+    fn test_conflicting_param_types() {
+        let queries = setup_queries(vec![
+            ("songs", "songs.sql.j2", vec![], "output/songs.sql", None),
+            ("albums", "albums.sql.j2", vec![], "output/albums.sql", None),
+            (
+                "artists",
+                "artists.sql.j2",
+                vec![],
+                "output/artists.sql",
+                None,
+            ),
+            ("genres", "genres.sql.j2", vec![], "output/genres.sql", None),
+        ]);
+        let export = |params: Vec<(&str, Option<&str>)>| QueryExport {
+            name_tag: None,
+            sql: None,
+            params: params
+                .into_iter()
+                .enumerate()
+                .map(|(i, (name, sql_type))| Param {
+                    name: name.to_owned(),
+                    placeholder: format!("${}", i + 1),
+                    sql_type: sql_type.map(String::from),
+                })
+                .collect(),
+        };
+        let query = |id: &str| queries.get(id).unwrap().as_ref();
+
+        // Same param name with different types in queries generated
+        // from different templates. Params without type are ignored.
+        let exports = vec![
+            (
+                query("songs"),
+                export(vec![("genre", Some("varchar")), ("limit", Some("int"))]),
+            ),
+            (query("albums"), export(vec![("limit", Some("bigint"))])),
+            (
+                query("artists"),
+                export(vec![("genre", Some("varchar")), ("limit", None)]),
+            ),
+            (query("genres"), export(vec![("limit", Some("int"))])),
+        ];
+        match &conflicting_param_types(&exports)[..] {
+            [ManifestMistake::ConflictingParamTypes {
+                param,
+                types,
+                query_ids,
+            }] => {
+                assert_eq!("limit", param);
+                assert_eq!(vec!["int", "bigint"], *types);
+                assert_eq!(vec!["songs", "albums", "genres"], *query_ids);
+            }
+            _ => assert!(false),
+        }
+
+        // No conflicts
+        let exports = vec![
+            (query("songs"), export(vec![("limit", Some("int"))])),
+            (query("genres"), export(vec![("limit", Some("int"))])),
+            (query("albums"), export(vec![("limit", None)])),
+        ];
+        assert!(conflicting_param_types(&exports).is_empty());
+    }
+}
@@ -1,11 +1,27 @@
-use std::io::Write;
-use std::path::Path;
-use std::process::{Command, Stdio};
-use std::thread;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
 
 use toml::Value;
 
 use super::config::Configurable;
+use super::util::Cmd;
+use crate::error::Error;
+use crate::util::parallel_map;
+
+/// Returns `Error::FormatterEmptyOutput` if the formatted `output` is
+/// empty even though the `input` isn't
+fn ensure_non_empty(exec: &Path, input: &str, output: &[u8]) -> Result<(), Error> {
+    if output.iter().all(u8::is_ascii_whitespace) && !input.trim().is_empty() {
+        Err(Error::FormatterEmptyOutput(exec.display().to_string()))
+    } else {
+        Ok(())
+    }
+}
 
 /// Trait for formatters that are installed as external programs.
 #[allow(unused)]
@@ -21,30 +37,131 @@ pub trait ExternalFormatter<'a>: TryFrom<&'a Value> + Configurable {
     /// with to check that the program is installed
     fn check_args(&self) -> Vec<&str>;
 
+    /// Returns the time after which the program is killed if it
+    /// hasn't finished formatting
+    ///
+    /// It applies to every invocation of the program i.e. to every
+    /// chunk of files in case of `format_batch`, so that the time
+    /// allowed for formatting scales with the no. of inputs.
+    fn timeout(&self) -> Duration;
+
+    /// Returns the exit codes with which the program exits upon
+    /// successful formatting
+    fn success_codes(&self) -> &[i32] {
+        &[0]
+    }
+
     /// Returns a byte array of formatted input.
     ///
     /// The default implementation calls shell's out to the executable
     /// with by passing the input string through `stdin` along with
     /// the result of `self.format_args` as arguments.
-    fn format(&self, input: &str) -> Vec<u8> {
-        let mut child = Command::new(self.executable())
-            .args(self.format_args())
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("Failed to run formatter as a child process");
-        let mut stdin = child.stdin.take().expect("Failed to open stdin");
-        // @TODO: Check if it's possible to avoid allocating for an
-        // owned String here
-        let input = input.to_owned();
-        thread::spawn(move || {
-            stdin
-                .write_all(input.as_bytes())
-                .expect("Failed to write to stdin");
-        });
-
-        let output = child.wait_with_output().expect("Failed to read stdout");
-        output.stdout
+    ///
+    /// Returns `Error::Formatter` if the program fails (refer to
+    /// `Cmd::execute`) or `Error::FormatterEmptyOutput` if it's
+    /// output is empty for a non-empty input, so that the output
+    /// files are never overwritten with such output.
+    fn format(&self, input: &str) -> Result<Vec<u8>, Error> {
+        let cmd = Cmd {
+            exec: self.executable(),
+            args: self.format_args(),
+            timeout: self.timeout(),
+            success_codes: self.success_codes(),
+        };
+        let output = cmd.execute(Some(input))?;
+        ensure_non_empty(self.executable(), input, &output)?;
+        Ok(output)
+    }
+
+    /// Returns a vector of arguments to be specified when calling the
+    /// program for formatting multiple files in place, in which case
+    /// the paths of the files are appended to them
+    ///
+    /// The default implementation returns `None` i.e. the program
+    /// doesn't support it.
+    fn batch_format_args(&self) -> Option<Vec<&str>> {
+        None
+    }
+
+    /// Returns the formatted inputs (in the same order)
+    ///
+    /// If the program supports formatting multiple files (see
+    /// `batch_format_args`), the inputs are written to temporary files
+    /// inside `batch_dir` (refer to `BATCH_DIR`), which are formatted
+    /// in place by calling the program once for every chunk of upto
+    /// `BATCH_CHUNK_SIZE` files. The chunks are formatted in parallel
+    /// using upto `jobs` threads. Otherwise, the program is called
+    /// once for every input.
+    fn format_batch(
+        &self,
+        inputs: &[&str],
+        jobs: usize,
+        batch_dir: &Path,
+    ) -> Result<Vec<Vec<u8>>, Error>
+    where
+        Self: Sync,
+    {
+        let args = match self.batch_format_args() {
+            Some(args) if inputs.len() > 1 => args,
+            _ => return inputs.iter().map(|input| self.format(input)).collect(),
+        };
+        let dir = BatchDir::create(batch_dir).map_err(Error::Io)?;
+        let mut paths = Vec::with_capacity(inputs.len());
+        for (i, input) in inputs.iter().enumerate() {
+            // @UNWRAP: The path is made up of ascii chars only
+            let path = dir
+                .path
+                .join(format!("{i}.sql"))
+                .to_str()
+                .unwrap()
+                .to_owned();
+            fs::write(&path, input).map_err(Error::Io)?;
+            paths.push(path);
+        }
+        let chunks = paths.chunks(BATCH_CHUNK_SIZE).collect::<Vec<&[String]>>();
+        parallel_map(&chunks, jobs, |chunk| {
+            let mut cmd = Cmd {
+                exec: self.executable(),
+                args: args.clone(),
+                timeout: self.timeout(),
+                success_codes: self.success_codes(),
+            };
+            cmd.args.extend(chunk.iter().map(|p| p.as_str()));
+            cmd.execute(None)
+        })?;
+        let mut result = Vec::with_capacity(inputs.len());
+        for (input, path) in inputs.iter().zip(&paths) {
+            let output = fs::read(path).map_err(Error::Io)?;
+            ensure_non_empty(self.executable(), input, &output)?;
+            result.push(output);
+        }
+        Ok(result)
+    }
+
+    /// Returns the cell in which the version of the program is
+    /// memoized (see `version`)
+    fn version_cell(&self) -> &OnceLock<Option<String>>;
+
+    /// Returns the version of the program as printed by it upon
+    /// running it with the return value of `self.check_args` as
+    /// arguments, or `None` if that fails
+    ///
+    /// The program is run only the first time, as it may take a while
+    /// to start up (e.g. sqlfluff).
+    fn version(&self) -> Option<String> {
+        self.version_cell()
+            .get_or_init(|| {
+                let output = Command::new(self.executable())
+                    .args(self.check_args())
+                    .stdin(Stdio::null())
+                    .output()
+                    .ok()?;
+                output
+                    .status
+                    .success()
+                    .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+            })
+            .clone()
     }
 
     /// Checks whether or not the executable exists (i.e. the
@@ -58,6 +175,202 @@ pub trait ExternalFormatter<'a>: TryFrom<&'a Value> + Configurable {
             .stdout(Stdio::null())
             .stderr(Stdio::null())
             .status()
-            .map_or(false, |s| s.success())
+            .is_ok_and(|s| s.success())
+    }
+}
+
+/// Max no. of files that are passed to the program in a single
+/// invocation when formatting multiple files, so that the command
+/// line never exceeds the limit imposed by the OS (`ARG_MAX`)
+const BATCH_CHUNK_SIZE: usize = 100;
+
+/// Temporary dir inside the `parent` dir for formatting multiple files
+/// in a single batch, which is removed upon drop
+struct BatchDir {
+    parent: PathBuf,
+    path: PathBuf,
+}
+
+impl BatchDir {
+    fn create(parent: &Path) -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = parent.join(format!("{}-{n}", process::id()));
+        fs::create_dir_all(&path)?;
+        Ok(Self {
+            parent: parent.to_path_buf(),
+            path,
+        })
+    }
+}
+
+impl Drop for BatchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+        // Succeeds only if no other batch is in progress
+        let _ = fs::remove_dir(&self.parent);
+    }
+}
+
+// The tests use stub executables (see `stub_executable`)
+#[cfg(all(test, unix))]
+mod tests {
+
+    use super::*;
+    use crate::formatters::{PgFormatter, SqlFluff};
+    use crate::util::test_util::{stub_executable, TempDir};
+
+    // Stub for pg_format that prefixes the input with a comment, either
+    // formatting the files in place or stdin, and logs the no. of
+    // files formatted in every invocation
+    fn stub_pg_format(dir: &Path) -> PgFormatter {
+        let exec = dir.join("pg_format");
+        let log = dir.join("invocations.log");
+        let script = format!(
+            r#"n=0
+for f in "$@"; do
+  case "$f" in
+    *.sql) {{ echo "-- formatted"; cat "$f"; }} > "$f.tmp" && mv "$f.tmp" "$f"; n=$((n+1));;
+  esac
+done
+if [ "$n" -eq 0 ]; then echo "-- formatted"; cat; fi
+echo "$n" >> {}"#,
+            log.display()
+        );
+        stub_executable(&exec, &script);
+        PgFormatter::new(exec, None)
+    }
+
+    fn invocations(dir: &Path) -> Vec<usize> {
+        fs::read_to_string(dir.join("invocations.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(|l| l.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_format_batch() {
+        let tmp = TempDir::new("format-batch");
+        let dir = tmp.path();
+        let formatter = stub_pg_format(dir);
+        let batch_dir = dir.join("format");
+
+        // Multiple inputs are formatted in chunks of files and the
+        // outputs are returned in the same order as the inputs
+        let inputs = (0..250)
+            .map(|i| format!("SELECT {i};\n"))
+            .collect::<Vec<String>>();
+        let inputs = inputs.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+        let outputs = formatter.format_batch(&inputs, 4, &batch_dir).unwrap();
+        assert_eq!(250, outputs.len());
+        for (i, output) in outputs.iter().enumerate() {
+            assert_eq!(
+                format!("-- formatted\nSELECT {i};\n"),
+                String::from_utf8_lossy(output)
+            );
+        }
+        let mut counts = invocations(dir);
+        counts.sort();
+        assert_eq!(vec![50, 100, 100], counts);
+
+        // A single input is formatted via stdin
+        fs::remove_file(dir.join("invocations.log")).unwrap();
+        let outputs = formatter
+            .format_batch(&["SELECT 1;\n"], 4, &batch_dir)
+            .unwrap();
+        assert_eq!(vec![b"-- formatted\nSELECT 1;\n".to_vec()], outputs);
+        assert_eq!(vec![0], invocations(dir));
+
+        // No inputs
+        fs::remove_file(dir.join("invocations.log")).unwrap();
+        assert!(formatter
+            .format_batch(&[], 4, &batch_dir)
+            .unwrap()
+            .is_empty());
+        assert!(invocations(dir).is_empty());
+    }
+
+    #[test]
+    fn test_format_errors() {
+        let tmp = TempDir::new("format-errors");
+        let dir = tmp.path();
+        let exec = dir.join("pg_format");
+        let batch_dir = dir.join("format");
+        let formatter = PgFormatter::new(exec.clone(), None);
+        let inputs = ["SELECT 1;\n", "SELECT 2;\n"];
+
+        // Non-zero exit code along with stderr, both when formatting a
+        // single input and a batch
+        stub_executable(
+            &exec,
+            "cat > /dev/null; echo 'syntax error at line 1' >&2; exit 3",
+        );
+        match formatter.format("SELECT 1;\n") {
+            Err(Error::Formatter { code, stderr, .. }) => {
+                assert_eq!(Some(3), code);
+                assert_eq!("syntax error at line 1\n", stderr);
+            }
+            _ => assert!(false),
+        }
+        match formatter.format_batch(&inputs, 2, &batch_dir) {
+            Err(e @ Error::Formatter { .. }) => assert_eq!(
+                format!(
+                    "Formatter error: '{}' exited with code 3\nsyntax error at line 1",
+                    exec.display()
+                ),
+                e.to_string()
+            ),
+            _ => assert!(false),
+        }
+
+        // Empty output for non-empty input
+        stub_executable(&exec, "cat > /dev/null");
+        match formatter.format("SELECT 1;\n") {
+            Err(Error::FormatterEmptyOutput(e)) => assert_eq!(exec.display().to_string(), e),
+            _ => assert!(false),
+        }
+        assert_eq!(b"".to_vec(), formatter.format("  \n").unwrap());
+        stub_executable(
+            &exec,
+            r#"for f in "$@"; do case "$f" in *.sql) : > "$f";; esac; done"#,
+        );
+        match formatter.format_batch(&inputs, 2, &batch_dir) {
+            Err(Error::FormatterEmptyOutput(_)) => assert!(true),
+            _ => assert!(false),
+        }
+
+        // Timeout
+        stub_executable(&exec, "sleep 5");
+        let mut formatter = PgFormatter::new(exec.clone(), None);
+        formatter.timeout = Some(Duration::from_millis(100));
+        match formatter.format("SELECT 1;\n") {
+            Err(Error::Formatter { code, stderr, .. }) => {
+                assert_eq!(None, code);
+                assert_eq!("Timed out after 100ms", stderr);
+            }
+            _ => assert!(false),
+        }
+        match formatter.format_batch(&inputs, 2, &batch_dir) {
+            Err(Error::Formatter { code, .. }) => assert_eq!(None, code),
+            _ => assert!(false),
+        }
+
+        // sqlfluff exits with code 1 if it couldn't fix all the
+        // violations, which is also considered a failure
+        let exec = dir.join("sqlfluff");
+        stub_executable(&exec, "cat; echo 'Unfixable violations' >&2; exit 1");
+        let value: Value = format!("exec_path = '{}'", exec.display())
+            .parse::<toml::Table>()
+            .unwrap()
+            .into();
+        let formatter = SqlFluff::try_from(&value).unwrap();
+        match formatter.format("SELECT 1;\n") {
+            Err(Error::Formatter { code, stderr, .. }) => {
+                assert_eq!(Some(1), code);
+                assert_eq!("Unfixable violations\n", stderr);
+            }
+            _ => assert!(false),
+        }
     }
 }
@@ -2,12 +2,23 @@ use self::config::Configurable;
 use self::external::ExternalFormatter;
 use self::sql_formatter::SqlFormatter;
 use self::sqlfluff::SqlFluff;
+use crate::util::parallel_map;
 use crate::{error::Error, toml::SerializableTomlTable};
 pub use pg_format::PgFormatter;
 use sqlformat_rs::SqlFormat;
 use std::path::Path;
 use toml::Value;
 
+/// Parent dir of the temporary dirs used by the external formatters
+/// for formatting multiple files in a single batch (see
+/// `Formatter::format_batch`)
+///
+/// It's inside the current dir (i.e. the dir containing the manifest
+/// file) so that the formatters pick up the config files from it in
+/// the same way as when formatting the input from stdin
+/// (e.g. `.sqlfluff`).
+pub const BATCH_DIR: &str = ".tapestry/format";
+
 mod config;
 mod external;
 mod pg_format;
@@ -52,12 +63,42 @@ impl Formatter {
         }
     }
 
-    pub fn format(&self, sql: &str) -> Vec<u8> {
+    /// Formats the sql
+    ///
+    /// Returns `Error::Formatter` or `Error::FormatterEmptyOutput` in
+    /// case an external formatter fails (refer to
+    /// `ExternalFormatter::format`).
+    pub fn format(&self, sql: &str) -> Result<Vec<u8>, Error> {
         match self {
             Self::PgFormatter(p) => p.format(sql),
             Self::SqlFormatter(f) => f.format(sql),
             Self::SqlFluff(f) => f.format(sql),
-            Self::SqlFormatRs(f) => f.format(sql),
+            Self::SqlFormatRs(f) => Ok(f.format(sql)),
+        }
+    }
+
+    /// Formats multiple inputs, returning the formatted outputs in
+    /// the same order
+    ///
+    /// pgFormatter and sqlfluff are called once for every chunk of
+    /// inputs (refer to `ExternalFormatter::format_batch`) as their
+    /// startup time dominates the time taken for formatting. The
+    /// other formatters format the inputs individually. In both
+    /// cases, upto `jobs` threads are used. The temporary files for
+    /// the batches are created inside `batch_dir` (`BATCH_DIR` unless
+    /// in tests).
+    pub fn format_batch(
+        &self,
+        inputs: &[&str],
+        jobs: usize,
+        batch_dir: &Path,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        match self {
+            Self::PgFormatter(p) => p.format_batch(inputs, jobs, batch_dir),
+            Self::SqlFluff(f) => f.format_batch(inputs, jobs, batch_dir),
+            Self::SqlFormatter(_) | Self::SqlFormatRs(_) => {
+                parallel_map(inputs, jobs, |input| self.format(input))
+            }
         }
     }
 
@@ -79,6 +120,31 @@ impl Formatter {
         }
     }
 
+    /// Returns the version of the external formatter (refer to
+    /// `ExternalFormatter::version`)
+    ///
+    /// It's `None` in case of the builtin formatter as it's version
+    /// is tied to that of tapestry.
+    pub fn version(&self) -> Option<String> {
+        match self {
+            Self::PgFormatter(p) => p.version(),
+            Self::SqlFormatter(f) => f.version(),
+            Self::SqlFluff(f) => f.version(),
+            Self::SqlFormatRs(_) => None,
+        }
+    }
+
+    /// Returns path to the config file of the formatter (if any)
+    pub fn config_file_path(&self) -> Option<&Path> {
+        let res = match self {
+            Self::PgFormatter(p) => p.config_file(),
+            Self::SqlFormatter(f) => f.config_file(),
+            Self::SqlFormatRs(f) => f.config_file(),
+            Self::SqlFluff(f) => f.config_file(),
+        };
+        res.map(|(path, _)| path)
+    }
+
     pub fn generate_config_file(&self, dir: &Path) -> Result<(), Error> {
         let res = match self {
             Self::PgFormatter(p) => p.generate_config_file(dir),
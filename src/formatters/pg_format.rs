@@ -1,11 +1,13 @@
 use crate::error::{parse_error, Error};
 use crate::toml::{decode_pathbuf, SerializableTomlTable};
-use std::cell::OnceCell;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 use toml::Value;
 
 use super::config::Configurable;
 use super::external::ExternalFormatter;
+use super::util::{decode_timeout, DEFAULT_TIMEOUT};
 
 /// Provides an abstraction for formatting sql using the `pgFormatter`
 /// (a.k.a `pg_format`) tool
@@ -13,7 +15,10 @@ use super::external::ExternalFormatter;
 pub struct PgFormatter {
     pub exec_path: PathBuf,
     pub conf_path: Option<PathBuf>,
-    args: OnceCell<Vec<String>>,
+    /// Overrides the `DEFAULT_TIMEOUT`
+    pub timeout: Option<Duration>,
+    args: OnceLock<Vec<String>>,
+    version: OnceLock<Option<String>>,
 }
 
 fn pg_format_args(conf_path: Option<&Path>) -> Vec<String> {
@@ -40,7 +45,11 @@ impl TryFrom<&Value> for PgFormatter {
                     Some(cp) => Some(decode_pathbuf(cp, None, "formatter.pgFormatter.conf_path")?),
                     None => None,
                 };
-                Ok(Self::new(exec_path, conf_path))
+                let timeout = decode_timeout(t, "pgFormatter")?;
+                Ok(Self {
+                    timeout,
+                    ..Self::new(exec_path, conf_path)
+                })
             }
             None => Err(parse_error!(
                 "Value of 'formatter.pgFormatter' must be a toml table"
@@ -54,7 +63,9 @@ impl PgFormatter {
         Self {
             exec_path,
             conf_path,
-            args: OnceCell::new(),
+            timeout: None,
+            args: OnceLock::new(),
+            version: OnceLock::new(),
         }
     }
 
@@ -82,6 +93,11 @@ impl Configurable for PgFormatter {
             let conf_path = &p.display().to_string();
             t.push_entry_string("conf_path", conf_path);
         }
+        t.push_comment("(optional) Time (in seconds) after which formatting is aborted");
+        match &self.timeout {
+            Some(d) => t.push_entry_i64("timeout", d.as_secs() as i64),
+            None => t.push_comment(&format!("timeout = {}", DEFAULT_TIMEOUT.as_secs())),
+        }
         t
     }
 
@@ -104,6 +120,21 @@ impl ExternalFormatter<'_> for PgFormatter {
         args.iter().map(|a| a.as_str()).collect()
     }
 
+    fn timeout(&self) -> Duration {
+        self.timeout.unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    fn batch_format_args(&self) -> Option<Vec<&str>> {
+        let mut args = self.format_args();
+        args.retain(|a| *a != "-");
+        args.push("--inplace");
+        Some(args)
+    }
+
+    fn version_cell(&self) -> &OnceLock<Option<String>> {
+        &self.version
+    }
+
     fn check_args(&self) -> Vec<&str> {
         vec!["-v"]
     }
@@ -112,8 +143,9 @@ impl ExternalFormatter<'_> for PgFormatter {
 #[cfg(test)]
 mod tests {
 
-    use super::pg_format_args;
-    use std::path::Path;
+    use super::{pg_format_args, PgFormatter};
+    use crate::formatters::external::ExternalFormatter;
+    use std::path::{Path, PathBuf};
 
     #[test]
     fn test_pg_format_args() {
@@ -138,4 +170,18 @@ mod tests {
             args
         );
     }
+
+    #[test]
+    fn test_batch_format_args() {
+        let f = PgFormatter::new(PathBuf::from("pg_format"), None);
+        assert_eq!(
+            Some(vec![
+                "-M",
+                "-p",
+                "start\\(noformat\\).+end\\(noformat\\)",
+                "--inplace"
+            ]),
+            f.batch_format_args()
+        );
+    }
 }
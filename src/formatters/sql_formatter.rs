@@ -1,17 +1,22 @@
 use crate::error::{parse_error, Error};
 use crate::toml::{decode_pathbuf, SerializableTomlTable};
-use std::cell::OnceCell;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 use toml::Value;
 
 use super::config::Configurable;
 use super::external::ExternalFormatter;
+use super::util::{decode_timeout, DEFAULT_TIMEOUT};
 
 #[derive(Debug)]
 pub struct SqlFormatter {
     exec_path: PathBuf,
     conf_path: Option<PathBuf>,
-    args: OnceCell<Vec<String>>,
+    /// Overrides the `DEFAULT_TIMEOUT`
+    timeout: Option<Duration>,
+    args: OnceLock<Vec<String>>,
+    version: OnceLock<Option<String>>,
 }
 
 impl TryFrom<&Value> for SqlFormatter {
@@ -34,7 +39,11 @@ impl TryFrom<&Value> for SqlFormatter {
                     )?),
                     None => None,
                 };
-                Ok(Self::new(exec_path, conf_path))
+                let timeout = decode_timeout(t, "sql-formatter")?;
+                Ok(Self {
+                    timeout,
+                    ..Self::new(exec_path, conf_path)
+                })
             }
             None => Err(parse_error!(
                 "Value of 'formatter.sql-formatter' must be a toml table"
@@ -48,7 +57,9 @@ impl SqlFormatter {
         Self {
             exec_path,
             conf_path,
-            args: OnceCell::new(),
+            timeout: None,
+            args: OnceLock::new(),
+            version: OnceLock::new(),
         }
     }
 
@@ -76,6 +87,11 @@ impl Configurable for SqlFormatter {
             let conf_path = &p.display().to_string();
             t.push_entry_string("conf_path", conf_path);
         }
+        t.push_comment("(optional) Time (in seconds) after which formatting is aborted");
+        match &self.timeout {
+            Some(d) => t.push_entry_i64("timeout", d.as_secs() as i64),
+            None => t.push_comment(&format!("timeout = {}", DEFAULT_TIMEOUT.as_secs())),
+        }
         t
     }
 
@@ -106,6 +122,14 @@ impl ExternalFormatter<'_> for SqlFormatter {
         args.iter().map(|a| a.as_str()).collect()
     }
 
+    fn timeout(&self) -> Duration {
+        self.timeout.unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    fn version_cell(&self) -> &OnceLock<Option<String>> {
+        &self.version
+    }
+
     fn check_args(&self) -> Vec<&str> {
         vec!["--version"]
     }
@@ -1,13 +1,19 @@
 use super::config::Configurable;
 use super::external::ExternalFormatter;
+use super::util::{decode_timeout, DEFAULT_TIMEOUT};
 use crate::error::{parse_error, Error};
 use crate::toml::{decode_pathbuf, SerializableTomlTable};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 use toml::Value;
 
 #[derive(Debug)]
 pub struct SqlFluff {
     exec_path: PathBuf,
+    /// Overrides the `DEFAULT_TIMEOUT`
+    timeout: Option<Duration>,
+    version: OnceLock<Option<String>>,
 }
 
 impl TryFrom<&Value> for SqlFluff {
@@ -20,7 +26,12 @@ impl TryFrom<&Value> for SqlFluff {
                     .get("exec_path")
                     .ok_or(parse_error!("Missing 'exec_path' in 'formatter.sqlfluff"))
                     .map(|v| decode_pathbuf(v, None, "formatter.sqlfluff.exec_path"))??;
-                Ok(Self { exec_path })
+                let timeout = decode_timeout(t, "sqlfluff")?;
+                Ok(Self {
+                    exec_path,
+                    timeout,
+                    version: OnceLock::new(),
+                })
             }
             None => Err(parse_error!(
                 "Value of 'formatter.sqlfluff' must be a toml table"
@@ -35,6 +46,11 @@ impl Configurable for SqlFluff {
         t.push_comment("(required) Location of the sqlfluff executable");
         let exec_path = self.exec_path.display().to_string();
         t.push_entry_string("exec_path", &exec_path);
+        t.push_comment("(optional) Time (in seconds) after which formatting is aborted");
+        match &self.timeout {
+            Some(d) => t.push_entry_i64("timeout", d.as_secs() as i64),
+            None => t.push_comment(&format!("timeout = {}", DEFAULT_TIMEOUT.as_secs())),
+        }
         t
     }
 
@@ -55,6 +71,18 @@ impl ExternalFormatter<'_> for SqlFluff {
         vec!["format", "--nocolor", "--disable-progress-bar", "-"]
     }
 
+    fn timeout(&self) -> Duration {
+        self.timeout.unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    fn batch_format_args(&self) -> Option<Vec<&str>> {
+        Some(vec!["format", "--nocolor", "--disable-progress-bar"])
+    }
+
+    fn version_cell(&self) -> &OnceLock<Option<String>> {
+        &self.version
+    }
+
     fn check_args(&self) -> Vec<&str> {
         vec!["--version"]
     }
@@ -64,6 +92,8 @@ impl SqlFluff {
     pub fn discover() -> Option<Self> {
         let f = Self {
             exec_path: PathBuf::from("sqlfluff"),
+            timeout: None,
+            version: OnceLock::new(),
         };
         if f.check() {
             Some(f)
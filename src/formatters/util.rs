@@ -1,38 +1,198 @@
-use std::io::Write;
+use crate::error::{parse_error, Error};
+use std::io::{Read, Write};
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Stdio};
 use std::thread;
+use std::time::{Duration, Instant};
+use toml::Table;
+
+/// Time after which an external formatter is killed unless configured
+/// otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Decodes the optional `timeout` (in seconds) from the config table
+/// of an external formatter
+pub fn decode_timeout(t: &Table, formatter: &str) -> Result<Option<Duration>, Error> {
+    match t.get("timeout") {
+        Some(v) => match v.as_integer() {
+            Some(n) if n > 0 => Ok(Some(Duration::from_secs(n as u64))),
+            _ => Err(parse_error!(
+                "Value of 'formatter.{formatter}.timeout' expected to be a positive integer"
+            )),
+        },
+        None => Ok(None),
+    }
+}
 
 /// Provides an abstraction for running an executable (i.e. shelling
 /// out) to format sql. It passes raw unformatted sql as input to the
-/// executable via `stdin`.
-#[allow(dead_code)]
+/// executable via `stdin` (unless the files to be formatted are
+/// passed as args).
 pub struct Cmd<'a> {
     pub exec: &'a Path,
     pub args: Vec<&'a str>,
+    pub timeout: Duration,
+    /// Exit codes that indicate successful formatting
+    pub success_codes: &'a [i32],
+}
+
+/// Spawns a thread that reads the pipe until EOF, so that the child
+/// process doesn't block on a full pipe
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(mut p) = pipe {
+            let _ = p.read_to_end(&mut buf);
+        }
+        buf
+    })
 }
 
-#[allow(dead_code)]
 impl<'a> Cmd<'a> {
-    pub fn execute(&self, input: &str) -> Vec<u8> {
+    fn error(&self, code: Option<i32>, stderr: String) -> Error {
+        Error::Formatter {
+            exec: self.exec.display().to_string(),
+            code,
+            stderr,
+        }
+    }
+
+    /// Waits for the child process to exit within the timeout, killing
+    /// it otherwise
+    fn wait(&self, child: &mut Child) -> Result<Option<i32>, Error> {
+        let start = Instant::now();
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => return Ok(status.code()),
+                Ok(None) if start.elapsed() >= self.timeout => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(self.error(None, format!("Timed out after {:?}", self.timeout)));
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(10)),
+                Err(e) => return Err(self.error(None, e.to_string())),
+            }
+        }
+    }
+
+    /// Runs the executable with `input` passed via `stdin` (if any)
+    /// and returns it's output on `stdout`
+    ///
+    /// Returns `Error::Formatter` if the executable couldn't be run,
+    /// exits with a code other than `success_codes` or doesn't exit
+    /// within the timeout.
+    pub fn execute(&self, input: Option<&str>) -> Result<Vec<u8>, Error> {
         let mut child = Command::new(self.exec)
             .args(&self.args)
-            .stdin(Stdio::piped())
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()
-            .expect("Failed to spawn child process");
-
-        let mut stdin = child.stdin.take().expect("Failed to open stdin");
-        // @TODO: Check if it's possible to avoid allocating for an
-        // owned String here
-        let input = input.to_owned();
-        thread::spawn(move || {
-            stdin
-                .write_all(input.as_bytes())
-                .expect("Failed to write to stdin");
-        });
-
-        let output = child.wait_with_output().expect("Failed to read stdout");
-        output.stdout
+            .map_err(|e| self.error(None, format!("Failed to run the executable: {e}")))?;
+
+        if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+            // @TODO: Check if it's possible to avoid allocating for an
+            // owned String here
+            let input = input.to_owned();
+            // @NOTE: Errors are ignored as the formatter may exit
+            // without reading the entire input, in which case the exit
+            // code is reported
+            thread::spawn(move || {
+                let _ = stdin.write_all(input.as_bytes());
+            });
+        }
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+
+        let code = self.wait(&mut child)?;
+        // @UNWRAP: The reader threads don't panic
+        let stdout = stdout.join().unwrap();
+        let stderr = String::from_utf8_lossy(&stderr.join().unwrap()).into_owned();
+        if !code.is_some_and(|c| self.success_codes.contains(&c)) {
+            return Err(self.error(code, stderr));
+        }
+        Ok(stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_cmd_execute() {
+        let timeout = Duration::from_secs(5);
+        let cmd = Cmd {
+            exec: Path::new("cat"),
+            args: vec![],
+            timeout,
+            success_codes: &[0],
+        };
+        assert_eq!(
+            b"SELECT 1;\n".to_vec(),
+            cmd.execute(Some("SELECT 1;\n")).unwrap()
+        );
+
+        // Non-zero exit code
+        let cmd = Cmd {
+            exec: Path::new("sh"),
+            args: vec!["-c", "echo 'syntax error' >&2; exit 2"],
+            timeout,
+            success_codes: &[0, 1],
+        };
+        match cmd.execute(Some("SELECT 1;")) {
+            Err(Error::Formatter { code, stderr, .. }) => {
+                assert_eq!(Some(2), code);
+                assert_eq!("syntax error\n", stderr);
+            }
+            _ => assert!(false),
+        }
+
+        // Timeout
+        let cmd = Cmd {
+            exec: Path::new("sleep"),
+            args: vec!["5"],
+            timeout: Duration::from_millis(100),
+            success_codes: &[0],
+        };
+        match cmd.execute(None) {
+            Err(Error::Formatter { code, .. }) => assert_eq!(None, code),
+            _ => assert!(false),
+        }
+
+        // Missing executable
+        let cmd = Cmd {
+            exec: Path::new("tapestry-nonexistent-formatter"),
+            args: vec![],
+            timeout,
+            success_codes: &[0],
+        };
+        match cmd.execute(Some("SELECT 1;")) {
+            Err(Error::Formatter { code, .. }) => assert_eq!(None, code),
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_decode_timeout() {
+        let t = "timeout = 10".parse::<Table>().unwrap();
+        assert_eq!(
+            Some(Duration::from_secs(10)),
+            decode_timeout(&t, "sqlfluff").unwrap()
+        );
+        assert_eq!(None, decode_timeout(&Table::new(), "sqlfluff").unwrap());
+        let t = "timeout = 0".parse::<Table>().unwrap();
+        match decode_timeout(&t, "sqlfluff") {
+            Err(Error::Parsing(msg)) => assert_eq!(
+                "Value of 'formatter.sqlfluff.timeout' expected to be a positive integer",
+                msg
+            ),
+            _ => assert!(false),
+        }
     }
 }
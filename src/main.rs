@@ -1,24 +1,80 @@
 use crate::error::Error;
-use clap::{Parser, Subcommand};
+use crate::report::OutputFormat;
+use crate::selection::Selection;
+use clap::{Args, Parser, Subcommand};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::process;
 
+mod cache;
+mod codegen;
 mod command;
+mod diff;
 mod error;
+mod export;
 mod formatters;
 mod logging;
 mod metadata;
 mod output;
+mod params;
 mod placeholder;
 mod query;
 mod query_template;
 mod render;
+mod report;
 mod scaffolding;
+mod selection;
 mod tagging;
 mod test_template;
 mod toml;
 mod util;
 mod validation;
+mod watch;
+
+#[derive(Args)]
+struct Selectors {
+    #[arg(
+        long = "query",
+        value_name = "ID",
+        help = "Select queries by id (glob patterns supported; can be specified multiple times)"
+    )]
+    queries: Vec<String>,
+    #[arg(
+        long = "template",
+        value_name = "PATH",
+        help = "Select queries by query template path (can be specified multiple times)"
+    )]
+    query_templates: Vec<PathBuf>,
+    #[arg(
+        long = "test",
+        value_name = "PATH",
+        help = "Select tests by test template path (can be specified multiple times)"
+    )]
+    test_templates: Vec<PathBuf>,
+}
+
+impl Selectors {
+    fn selection(&self) -> Selection {
+        Selection::new(&self.queries, &self.query_templates, &self.test_templates)
+    }
+}
+
+#[derive(Args)]
+struct Jobs {
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        value_name = "N",
+        help = "Number of queries and tests to render and format in parallel (defaults to the number of CPUs)"
+    )]
+    jobs: Option<NonZeroUsize>,
+}
+
+impl Jobs {
+    fn count(&self) -> usize {
+        self.jobs.map_or_else(util::default_jobs, NonZeroUsize::get)
+    }
+}
 
 #[derive(Subcommand)]
 enum Command {
@@ -27,7 +83,33 @@ enum Command {
     #[command(about = "Validate manifest and template files")]
     Validate,
     #[command(about = "Render templates into SQL files")]
-    Render,
+    Render {
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Remove orphaned output files after rendering (same as the clean command)"
+        )]
+        prune: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Render all the selected queries and tests ignoring the render cache"
+        )]
+        no_cache: bool,
+        #[command(flatten)]
+        selectors: Selectors,
+        #[command(flatten)]
+        jobs: Jobs,
+    },
+    #[command(about = "Remove orphaned output files not defined in the manifest")]
+    Clean {
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Only list the files that would get removed"
+        )]
+        dry_run: bool,
+    },
     #[command(about = "Print tabular summary of queries and tests")]
     Summary {
         #[arg(
@@ -36,6 +118,8 @@ enum Command {
             help = "Include queries and tests not defined in manifest"
         )]
         all: bool,
+        #[command(flatten)]
+        selectors: Selectors,
     },
     #[command(about = "Preview changes without rendering")]
     Status {
@@ -45,6 +129,47 @@ enum Command {
             help = "Exit with non-zero code if any templates have unrendered changes"
         )]
         assert_no_changes: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Check all the selected queries and tests ignoring the render cache"
+        )]
+        no_cache: bool,
+        #[command(flatten)]
+        selectors: Selectors,
+        #[command(flatten)]
+        jobs: Jobs,
+    },
+    #[command(about = "Watch manifest and template files and re-render upon changes")]
+    Watch {
+        #[arg(
+            long,
+            default_value_t = 500,
+            value_name = "MILLIS",
+            help = "Interval (in milliseconds) for polling the files for changes"
+        )]
+        interval: u64,
+        #[command(flatten)]
+        jobs: Jobs,
+    },
+    #[command(about = "Show diff between output files and the rendered templates")]
+    Diff {
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Highlight the diff using ANSI color codes"
+        )]
+        color: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Print a summary of changed lines per output file instead of the diff"
+        )]
+        stat: bool,
+        #[command(flatten)]
+        selectors: Selectors,
+        #[command(flatten)]
+        jobs: Jobs,
     },
     #[command(about = "Print a summary of test coverage")]
     Coverage {
@@ -57,11 +182,30 @@ enum Command {
     },
 }
 
+impl Command {
+    /// Returns true if the command supports machine-readable output
+    /// formats i.e. `--format json|ndjson`
+    fn supports_format(&self) -> bool {
+        matches!(
+            self,
+            Self::Validate | Self::Summary { .. } | Self::Status { .. } | Self::Coverage { .. }
+        )
+    }
+}
+
 #[derive(Parser)]
 #[command(version, about)]
 struct Cli {
     #[arg(short, global = true, action = clap::ArgAction::Count, help = "Verbosity level (can be specified multiple times)")]
     verbosity: u8,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Output format (json and ndjson are supported by validate, summary, status and coverage)"
+    )]
+    format: OutputFormat,
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -70,13 +214,46 @@ impl Cli {
     fn execute(&self) -> Result<i32, Error> {
         // Initialize logging based on verbosity flag
         logging::init(self.verbosity);
+        let format = self.format;
+        if !format.is_text() && !self.command.as_ref().is_some_and(Command::supports_format) {
+            return Err(Error::Cli(
+                "--format is supported only by the validate, summary, status and coverage commands"
+                    .to_owned(),
+            ));
+        }
         match &self.command {
             Some(Command::Init { path }) => command::init(path),
-            Some(Command::Validate) => command::validate(),
-            Some(Command::Render) => command::render(),
-            Some(Command::Summary { all }) => command::summary(*all),
-            Some(Command::Status { assert_no_changes }) => command::status(*assert_no_changes),
-            Some(Command::Coverage { fail_under }) => command::coverage(*fail_under),
+            Some(Command::Validate) => command::validate(format),
+            Some(Command::Render {
+                prune,
+                no_cache,
+                selectors,
+                jobs,
+            }) => command::render(&selectors.selection(), *prune, jobs.count(), !no_cache),
+            Some(Command::Clean { dry_run }) => command::clean(*dry_run),
+            Some(Command::Summary { all, selectors }) => {
+                command::summary(*all, &selectors.selection(), format)
+            }
+            Some(Command::Status {
+                assert_no_changes,
+                no_cache,
+                selectors,
+                jobs,
+            }) => command::status(
+                *assert_no_changes,
+                &selectors.selection(),
+                jobs.count(),
+                !no_cache,
+                format,
+            ),
+            Some(Command::Diff {
+                color,
+                stat,
+                selectors,
+                jobs,
+            }) => command::diff(&selectors.selection(), *color, *stat, jobs.count()),
+            Some(Command::Watch { interval, jobs }) => command::watch(*interval, jobs.count()),
+            Some(Command::Coverage { fail_under }) => command::coverage(*fail_under, format),
             None => Err(Error::Cli("Please specify the command".to_owned())),
         }
     }
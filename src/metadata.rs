@@ -1,20 +1,43 @@
+use crate::codegen::Codegen;
 use crate::error::{parse_error, Error};
 use crate::formatters::Formatter;
-use crate::output::Layout;
+use crate::output::{GeneratedHeader, Layout, OutputPattern};
+use crate::params::ParamsExport;
 use crate::placeholder::Placeholder;
-use crate::query::Queries;
+use crate::query::{Queries, Query};
 use crate::query_template::QueryTemplates;
-use crate::tagging::{NameTagStyle, NameTagger};
-use crate::test_template::TestTemplates;
-use crate::toml::decode_pathbuf;
-use crate::util::ls_files;
+use crate::tagging::{NameTagFlavor, NameTagStyle, NameTagger, QueryResult};
+use crate::test_template::{TestTemplate, TestTemplates};
+use crate::toml::{decode_bool, decode_pathbuf};
+use crate::util::{ls_files, ls_files_recursive};
 use crate::validation::{validate_path, ManifestMistake};
 use log::{error, info, warn};
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
 use toml::Table;
 
+/// Returns files inside `dir` (including it's subdirectories other
+/// than `skip_dir`) that are not in the `defined` set. If the dir
+/// doesn't exist, an empty vec is returned.
+fn undefined_files(
+    dir: &Path,
+    defined: &HashSet<&Path>,
+    skip_dir: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    if !dir.try_exists().map_err(Error::Io)? {
+        return Ok(vec![]);
+    }
+    let mut result = ls_files_recursive(dir, false, &[skip_dir])
+        .map_err(Error::Io)?
+        .into_iter()
+        .filter(|p| !defined.contains(p.as_path()))
+        .collect::<Vec<PathBuf>>();
+    result.sort();
+    Ok(result)
+}
+
 #[derive(Debug)]
 pub struct Metadata {
     pub placeholder: Placeholder,
@@ -24,7 +47,11 @@ pub struct Metadata {
     pub queries_output_dir: PathBuf,
     pub tests_output_dir: PathBuf,
     pub query_output_layout: Layout,
+    pub generated_marker: bool,
+    pub generated_header: bool,
     pub name_tagger: Option<NameTagger>,
+    pub query_params: Option<ParamsExport>,
+    pub codegen: Codegen,
     pub query_templates: QueryTemplates,
     pub queries: Queries,
     pub test_templates: TestTemplates,
@@ -75,11 +102,47 @@ impl TryFrom<&Path> for Metadata {
             }
         };
 
+        let query_output_pattern = table
+            .get("query_output_pattern")
+            .map(|v| OutputPattern::decode(v, "query_output_pattern"))
+            .transpose()?;
+        if query_output_pattern.is_some() && query_output_layout.is_combined() {
+            warn!(
+                "Key 'query_output_pattern' is ignored in case of '{}' layout",
+                query_output_layout.label()
+            );
+        }
+
+        let test_output_pattern = table
+            .get("test_output_pattern")
+            .map(|v| OutputPattern::decode(v, "test_output_pattern"))
+            .transpose()?;
+
+        let generated_marker = match table.get("generated_marker") {
+            Some(v) => decode_bool(v, "generated_marker")?,
+            None => false,
+        };
+
+        let generated_header = match table.get("generated_header") {
+            Some(v) => decode_bool(v, "generated_header")?,
+            None => false,
+        };
+
         let name_tagger = match table.get("name_tagger") {
             Some(v) => NameTagger::decode(v)?,
             None => None,
         };
 
+        let query_params = match table.get("query_params") {
+            Some(v) => Some(ParamsExport::decode(v, &queries_output_dir)?),
+            None => None,
+        };
+
+        let codegen = match table.get("codegen") {
+            Some(v) => Codegen::decode(v, &query_output_layout)?,
+            None => Codegen::default(),
+        };
+
         let query_templates = match table.get("query_templates") {
             Some(v) => QueryTemplates::decode(&query_templates_dir, v)?,
             None => {
@@ -88,11 +151,14 @@ impl TryFrom<&Path> for Metadata {
             }
         };
 
-        let queries = match table.get("queries") {
+        let mut queries = match table.get("queries") {
             Some(v) => Queries::decode(
                 &query_templates_dir,
                 &queries_output_dir,
                 &query_output_layout,
+                query_output_pattern.as_ref(),
+                name_tagger.as_ref(),
+                &query_templates,
                 v,
             )?,
             None => {
@@ -100,9 +166,22 @@ impl TryFrom<&Path> for Metadata {
                 Queries::new()
             }
         };
+        queries.expand(
+            &query_templates,
+            &queries_output_dir,
+            &query_output_layout,
+            query_output_pattern.as_ref(),
+            name_tagger.as_ref(),
+        )?;
 
         let test_templates = match table.get("test_templates") {
-            Some(v) => TestTemplates::decode(&test_templates_dir, &tests_output_dir, v)?,
+            Some(v) => TestTemplates::decode(
+                &test_templates_dir,
+                &tests_output_dir,
+                test_output_pattern.as_ref(),
+                &queries,
+                v,
+            )?,
             None => {
                 warn!("TOML key 'test_templates' not found in manifest");
                 TestTemplates::new()
@@ -117,7 +196,11 @@ impl TryFrom<&Path> for Metadata {
             tests_output_dir,
             formatter,
             query_output_layout,
+            generated_marker,
+            generated_header,
             name_tagger,
+            query_params,
+            codegen,
             query_templates,
             queries,
             test_templates,
@@ -128,6 +211,66 @@ impl TryFrom<&Path> for Metadata {
 }
 
 impl Metadata {
+    /// Returns the placeholder style to be used for the query
+    ///
+    /// The `placeholder` set for the query takes precedence over the
+    /// one set for it's query template, which in turn takes precedence
+    /// over the one set globally.
+    pub fn query_placeholder<'a>(&'a self, query: &'a Query) -> &'a Placeholder {
+        query
+            .placeholder
+            .as_ref()
+            .or_else(|| {
+                self.query_templates
+                    .get(&query.template)
+                    .and_then(|qt| qt.placeholder.as_ref())
+            })
+            .unwrap_or(&self.placeholder)
+    }
+
+    /// Returns the kind of result of the query if specified
+    ///
+    /// The `result` set for the query takes precedence over the one
+    /// set for it's query template.
+    pub fn query_result<'a>(&'a self, query: &'a Query) -> Option<&'a QueryResult> {
+        query.result.as_ref().or_else(|| {
+            self.query_templates
+                .get(&query.template)
+                .and_then(|qt| qt.result.as_ref())
+        })
+    }
+
+    /// Returns the description of the query if specified
+    ///
+    /// The `description` set for the query takes precedence over the
+    /// one set for it's query template.
+    pub fn query_description<'a>(&'a self, query: &'a Query) -> Option<&'a str> {
+        query.description.as_deref().or_else(|| {
+            self.query_templates
+                .get(&query.template)
+                .and_then(|qt| qt.description.as_deref())
+        })
+    }
+
+    /// Returns the generated header for the query output if enabled
+    /// in the manifest
+    pub fn query_header(&self, query: &Query) -> Option<GeneratedHeader> {
+        self.generated_header
+            .then(|| GeneratedHeader::new(&query.template, &query.conds))
+    }
+
+    /// Returns the generated header for the test output if enabled in
+    /// the manifest
+    ///
+    /// The conds mentioned in the header are of the query that the
+    /// test is for.
+    pub fn test_header(&self, tt: &TestTemplate) -> Option<GeneratedHeader> {
+        let empty = HashSet::new();
+        let conds = self.queries.get(&tt.query).map_or(&empty, |q| &q.conds);
+        self.generated_header
+            .then(|| GeneratedHeader::new(&tt.path, conds))
+    }
+
     pub fn default() -> Self {
         Self {
             placeholder: Placeholder::PosArgs,
@@ -137,9 +280,15 @@ impl Metadata {
             queries_output_dir: PathBuf::from("output/queries"),
             tests_output_dir: PathBuf::from("output/tests"),
             query_output_layout: Layout::default(),
+            generated_marker: true,
+            generated_header: false,
             name_tagger: Some(NameTagger {
                 style: NameTagStyle::KebabCase,
+                flavor: NameTagFlavor::Default,
+                template: None,
             }),
+            query_params: None,
+            codegen: Codegen::default(),
             query_templates: QueryTemplates::new(),
             queries: Queries::new(),
             test_templates: TestTemplates::new(),
@@ -199,12 +348,13 @@ impl Metadata {
             );
         }
 
-        // Warn when `query_output_layout` is `one-file-all-queries`
-        // and `name_tagger` is not set.
-        if let Layout::OneFileAllQueries(_) = self.query_output_layout {
-            if self.name_tagger.is_none() {
-                warn!("Name tagging is recommended in case of 'one-file-all-queries' layout")
-            }
+        // Warn when `query_output_layout` is a combined one
+        // (e.g. `one-file-all-queries`) and `name_tagger` is not set.
+        if self.query_output_layout.is_combined() && self.name_tagger.is_none() {
+            warn!(
+                "Name tagging is recommended in case of '{}' layout",
+                self.query_output_layout.label()
+            )
         }
 
         // Warn if the provided formatter is not found or installed on
@@ -222,7 +372,7 @@ impl Metadata {
         Ok(())
     }
 
-    pub fn validate(&self) -> Vec<ManifestMistake> {
+    pub fn validate(&self) -> Vec<ManifestMistake<'_>> {
         let mut mistakes = vec![];
         match validate_path(&self.query_templates_dir, "query_templates_dir") {
             Ok(()) => {}
@@ -266,41 +416,141 @@ impl Metadata {
         );
         mistakes.append(&mut self.test_templates.validate(&self.queries));
 
-        if let Layout::OneFileAllQueries(_) = self.query_output_layout {
-            if self.name_tagger.is_none() {
-                mistakes.push(ManifestMistake::NameTaggingRequired(
-                    "query_output_layout = 'one-file-all-queries'".to_owned(),
-                ))
-            }
+        if self.query_output_layout.is_combined() && self.name_tagger.is_none() {
+            mistakes.push(ManifestMistake::NameTaggingRequired {
+                reason: format!(
+                    "query_output_layout = '{}'",
+                    self.query_output_layout.label()
+                ),
+            })
         }
 
+        mistakes.append(&mut self.duplicate_name_tags());
+
         // Log warnings if any
         let _ = self.warnings();
 
         mistakes
     }
 
-    /// Returns the combined output file in case layout =
-    /// `OneFileAllQueries`
+    /// Returns mistakes for the name tags that are shared by more
+    /// than one query
+    ///
+    /// The final name tags of all queries are considered i.e. the
+    /// ones derived as per `name_tagger.style` and
+    /// `name_tagger.template` as well as the custom ones. Collisions
+    /// are possible because the style maps multiple chars to the same
+    /// one (e.g. `a_b` and `a-b` are both tagged as `a_b` in case of
+    /// snake_case), the template may not use all the fields that make
+    /// a query unique, and a custom name tag may be the same as a
+    /// derived one. As the sections in the combined output file are
+    /// identified by the name tags, such queries can't be told apart.
+    fn duplicate_name_tags(&self) -> Vec<ManifestMistake<'_>> {
+        let tagger = match &self.name_tagger {
+            Some(t) => t,
+            None => return vec![],
+        };
+        let mut tags: Vec<Cow<str>> = vec![];
+        let mut ids_by_tag: HashMap<Cow<str>, Vec<&str>> =
+            HashMap::with_capacity(self.queries.len());
+        for query in self.queries.iter() {
+            let tag = tagger.tag(&query.name_tag);
+            let ids = ids_by_tag.entry(tag.clone()).or_insert_with(|| {
+                tags.push(tag);
+                vec![]
+            });
+            // Duplicate ids are reported separately
+            if !ids.contains(&query.id.as_str()) {
+                ids.push(&query.id);
+            }
+        }
+        let mut mistakes = vec![];
+        for tag in tags {
+            // @UNWRAP: All tags are inserted in the map above
+            let ids = ids_by_tag.remove(&tag).unwrap();
+            if ids.len() > 1 {
+                mistakes.push(ManifestMistake::DuplicateNameTag {
+                    name_tag: tag.into_owned(),
+                    query_ids: ids,
+                });
+            }
+        }
+        mistakes
+    }
+
+    /// Returns files inside the `queries_output_dir` that are not
+    /// defined in the manifest (sorted by path)
     ///
-    /// If layout is not `OneFileAllQueries`, then `None` is returned
+    /// This is a legit use case where the user has some query files
+    /// which are not generated by tapestry (likely when gradually
+    /// migrating from manually managed queries to tapestry). But it
+    /// may also happen that the query was renamed or deleted from the
+    /// manifest, in which case the file is an orphan.
+    pub fn undefined_query_files(&self) -> Result<Vec<PathBuf>, Error> {
+        let params_files = match &self.query_params {
+            Some(qp) => qp.output_paths(&self.queries),
+            None => vec![],
+        };
+        let defined: HashSet<&Path> = self
+            .queries
+            .output_files()
+            .chain(params_files.iter().map(|p| p.as_path()))
+            .chain(self.codegen.output_paths())
+            .collect();
+        undefined_files(&self.queries_output_dir, &defined, &self.tests_output_dir)
+    }
+
+    /// Returns files inside the `tests_output_dir` that are not
+    /// defined in the manifest (sorted by path)
+    ///
+    /// This is a legit use case where the user has written pgTAP
+    /// tests for queries that are not generated by tapestry. But it
+    /// may also happen that the test template was renamed or deleted
+    /// from the manifest, in which case the file is an orphan.
+    pub fn undefined_test_files(&self) -> Result<Vec<PathBuf>, Error> {
+        let defined: HashSet<&Path> = self
+            .test_templates
+            .iter()
+            .map(|tt| tt.output.as_ref())
+            .collect();
+        undefined_files(&self.tests_output_dir, &defined, &self.queries_output_dir)
+    }
+
+    /// Returns whether the query output files are to be marked as
+    /// generated by tapestry
+    ///
+    /// In case of combined layouts (e.g. `OneFileAllQueries`), the
+    /// individual query sections are not marked. The queries in the
+    /// combined output files are identified by the name tags instead.
+    pub fn marks_query_outputs(&self) -> bool {
+        self.generated_marker && !self.query_output_layout.is_combined()
+    }
+
+    /// Returns the combined output files in case of the combined
+    /// layouts i.e. the files to which multiple queries are written
     ///
-    /// Results in an error if all queries don't have the same output
-    /// path.
+    /// In case of `OneFileAllQueries` layout, there's exactly one
+    /// such file. In case of `OneFilePerTemplate` and `Grouped`
+    /// layouts, the files are in the order in which they are first
+    /// referred to by the queries. If layout is `OneFileOneQuery`, the
+    /// result is empty.
+    ///
+    /// In case of `OneFileAllQueries` layout, results in an error if
+    /// all queries don't have the same output path.
     ///
     /// @TODO: Add tests
-    pub fn combined_output_file(&self) -> Result<Option<&Path>, Error> {
+    pub fn combined_output_files(&self) -> Result<Vec<&Path>, Error> {
         match &self.query_output_layout {
-            Layout::OneFileOneQuery => Ok(None),
+            Layout::OneFileOneQuery => Ok(vec![]),
             Layout::OneFileAllQueries(output_file) => {
                 match output_file {
-                    Some(filepath) => Ok(Some(filepath)),
+                    Some(filepath) => Ok(vec![filepath]),
                     None => {
                         let mut output_paths =
                             self.queries.output_files().collect::<HashSet<&Path>>();
                         if output_paths.len() == 1 {
                             // Unwrap is acceptable as the length is known to be 1
-                            Ok(Some(output_paths.drain().next().unwrap()))
+                            Ok(vec![output_paths.drain().next().unwrap()])
                         } else {
                             Err(Error::Layout(
                                 "Common output file is required when layout = one-file-all-queries"
@@ -310,6 +560,133 @@ impl Metadata {
                     }
                 }
             }
+            Layout::OneFilePerTemplate | Layout::Grouped => {
+                let mut result: Vec<&Path> = vec![];
+                for path in self.queries.output_files() {
+                    if !result.contains(&path) {
+                        result.push(path);
+                    }
+                }
+                Ok(result)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::query::test_util::setup_queries;
+
+    #[test]
+    fn test_query_placeholder() {
+        let mut metadata = Metadata::default();
+        let table = r#"
+[[query_templates]]
+path = 'artists_long_songs.sql.j2'
+placeholder = 'variables'
+
+[[query_templates]]
+path = 'songs_formats.sql.j2'
+"#
+        .parse::<Table>()
+        .unwrap();
+        metadata.query_templates =
+            QueryTemplates::decode("templates/queries", &table["query_templates"]).unwrap();
+        metadata.queries = setup_queries(vec![
+            (
+                "artists_long_songs",
+                "templates/queries/artists_long_songs.sql.j2",
+                vec![],
+                "output/queries/artists_long_songs.sql",
+                None,
+            ),
+            (
+                "songs_formats",
+                "templates/queries/songs_formats.sql.j2",
+                vec![],
+                "output/queries/songs_formats.sql",
+                None,
+            ),
+        ]);
+
+        // Placeholder set for the query template takes precedence
+        // over the global one
+        let q = metadata.queries.get("artists_long_songs").unwrap();
+        assert_eq!("variables", metadata.query_placeholder(q).label());
+
+        // Falls back to the global placeholder
+        let q = metadata.queries.get("songs_formats").unwrap();
+        assert_eq!("posargs", metadata.query_placeholder(q).label());
+    }
+
+    #[test]
+    fn test_duplicate_name_tags() {
+        let mut metadata = Metadata::default();
+        metadata.queries = setup_queries(vec![
+            (
+                "artists_long_songs",
+                "templates/queries/artists_long_songs.sql.j2",
+                vec![],
+                "output/queries/artists_long_songs.sql",
+                None,
+            ),
+            (
+                "songs_formats",
+                "templates/queries/songs_formats.sql.j2",
+                vec![],
+                "output/queries/songs_formats.sql",
+                None,
+            ),
+            (
+                "artists-long-songs",
+                "templates/queries/artists_long_songs.sql.j2",
+                vec![],
+                "output/queries/artists-long-songs.sql",
+                None,
+            ),
+            (
+                "songs_formats@artist",
+                "templates/queries/songs_formats.sql.j2",
+                vec!["artist"],
+                "output/queries/songs_formats-artist.sql",
+                Some("artists-long-songs"),
+            ),
+        ]);
+        let mistakes = metadata.duplicate_name_tags();
+        assert_eq!(1, mistakes.len());
+        match &mistakes[0] {
+            ManifestMistake::DuplicateNameTag {
+                name_tag,
+                query_ids,
+            } => {
+                assert_eq!("artists-long-songs", name_tag);
+                assert_eq!(
+                    vec![
+                        "artists_long_songs",
+                        "artists-long-songs",
+                        "songs_formats@artist"
+                    ],
+                    *query_ids
+                );
+            }
+            _ => assert!(false),
+        }
+
+        // Only the custom name tag collides in case of the `exact`
+        // style
+        metadata.name_tagger = Some(NameTagger {
+            style: NameTagStyle::Exact,
+            flavor: NameTagFlavor::Default,
+            template: None,
+        });
+        match &metadata.duplicate_name_tags()[..] {
+            [ManifestMistake::DuplicateNameTag { query_ids, .. }] => assert_eq!(
+                vec!["artists-long-songs", "songs_formats@artist"],
+                *query_ids
+            ),
+            _ => assert!(false),
         }
     }
 }
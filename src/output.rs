@@ -1,17 +1,19 @@
 use log::warn;
+use regex::Regex;
+use serde::Serialize;
 use toml::Value;
 
 use crate::error::{parse_error, Error};
 use crate::formatters::Formatter;
 use crate::metadata::Metadata;
-use crate::query::{Queries, Query};
-use crate::tagging::{NameTag, NameTagger};
-use crate::toml::decode_pathbuf;
+use crate::query::{slugify_id, template_stem, Query};
+use crate::tagging::{parse_name_tag_line, NameTag, NameTagger, QueryResult};
+use crate::toml::{decode_pathbuf, decode_string};
+use crate::util::fnv1a_hex;
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use std::fs::{self, File};
-use std::io::{self, BufRead};
-use std::path::{Path, PathBuf};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 
 pub fn ensure_output_dirs<P: AsRef<Path>>(queries_dir: P, tests_dir: P) -> Result<(), Error> {
     fs::create_dir_all(queries_dir.as_ref()).map_err(Error::Io)?;
@@ -19,10 +21,191 @@ pub fn ensure_output_dirs<P: AsRef<Path>>(queries_dir: P, tests_dir: P) -> Resul
     Ok(())
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Comment line that's added to the output files to mark them as
+/// generated by tapestry (if enabled in the manifest)
+pub const GENERATED_MARKER: &str = "-- @generated by tapestry. Do not edit manually.";
+
+/// Returns true if any line in `contents` is the generated marker
+fn has_generated_marker(contents: &str) -> bool {
+    contents.lines().any(|line| line.trim() == GENERATED_MARKER)
+}
+
+/// Appends the generated marker as the last line of `sql` if it
+/// doesn't already have one
+///
+/// The marker is added at the end so that name tags (if any) remain
+/// the first line of the file.
+pub fn mark_generated(sql: Cow<'_, str>) -> Cow<'_, str> {
+    if has_generated_marker(&sql) {
+        sql
+    } else {
+        let mut s = sql.into_owned();
+        if !s.is_empty() && !s.ends_with('\n') {
+            s.push('\n');
+        }
+        s.push_str(GENERATED_MARKER);
+        s.push('\n');
+        Cow::Owned(s)
+    }
+}
+
+/// Prefix of the first line of the generated header
+const HEADER_SOURCE_PREFIX: &str = "-- Generated by tapestry from ";
+
+/// Prefix of the line of the generated header containing the hash of
+/// the contents
+const HEADER_HASH_PREFIX: &str = "-- tapestry-hash: ";
+
+fn is_header_line(line: &str) -> bool {
+    line.starts_with(HEADER_SOURCE_PREFIX) || line.starts_with(HEADER_HASH_PREFIX)
+}
+
+/// Returns true if the `contents` are of a file generated by tapestry
+/// i.e. it has either the generated marker or the generated header
+///
+/// Only such files are considered safe to be deleted when pruning
+/// orphaned output files.
+pub fn is_generated(contents: &str) -> bool {
+    contents
+        .lines()
+        .any(|line| line.trim() == GENERATED_MARKER || line.starts_with(HEADER_HASH_PREFIX))
+}
+
+/// Removes the orphaned output file at `path` only if it's generated
+/// by tapestry (see `is_generated`)
+///
+/// Returns whether the file is (or would be, if `dry_run` is true)
+/// removed.
+pub fn prune_file(path: &Path, dry_run: bool) -> Result<bool, Error> {
+    let contents = fs::read(path).map_err(Error::Io)?;
+    if !is_generated(&String::from_utf8_lossy(&contents)) {
+        return Ok(false);
+    }
+    if !dry_run {
+        fs::remove_file(path).map_err(Error::Io)?;
+    }
+    Ok(true)
+}
+
+/// Returns hash of the contents excluding the generated header lines
+/// (if any) and the trailing whitespace
+fn content_hash(contents: &str) -> String {
+    let lines = contents
+        .lines()
+        .filter(|line| !is_header_line(line))
+        .collect::<Vec<&str>>();
+    fnv1a_hex(lines.join("\n").trim_end())
+}
+
+/// Returns true if `contents` have a generated header but the hash in
+/// it doesn't match the actual contents i.e. the file (or the section
+/// of the combined output file) has been edited manually after it was
+/// last rendered
+pub fn is_manually_edited(contents: &str) -> bool {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix(HEADER_HASH_PREFIX))
+        .is_some_and(|hash| hash.trim() != content_hash(contents))
+}
+
+/// Header comment that's added to the query and test output files
+/// (if enabled in the manifest) naming the source template and conds
+/// along with the hash of the contents
+#[derive(Clone, Debug)]
+pub struct GeneratedHeader {
+    source: String,
+}
+
+impl GeneratedHeader {
+    pub fn new(template: &Path, conds: &HashSet<String>) -> Self {
+        let mut conds = conds.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+        conds.sort();
+        let source = if conds.is_empty() {
+            format!("'{}'", template.display())
+        } else {
+            format!("'{}' with conds: {}", template.display(), conds.join(", "))
+        };
+        Self { source }
+    }
+
+    /// Adds the header to the beginning of the (formatted) `contents`
+    ///
+    /// The header is added before the name tag (if any) and not after
+    /// it, as libraries such as aiosql and yesql treat the comment
+    /// lines following the name tag as the docstring of the query. In
+    /// case of combined output files, the header lines right before a
+    /// name tag are considered part of it's section (refer to
+    /// `split_sections`).
+    pub fn add(&self, contents: &str) -> String {
+        let hash = content_hash(contents);
+        format!(
+            "{HEADER_SOURCE_PREFIX}{}. Do not edit manually.\n{HEADER_HASH_PREFIX}{hash}\n{contents}",
+            self.source
+        )
+    }
+}
+
+/// Splits the contents of a combined output file into sections
+///
+/// A section begins at a name tag line, or at the generated header
+/// lines right before it, and extends until the next section (or the
+/// end of the file). Returns the names in the name tags along with
+/// the sections (including the trailing newline chars). Lines before
+/// the first section, if any, are returned with `None` as the name.
+fn split_sections(contents: &str) -> Vec<(Option<&str>, &str)> {
+    let mut result = vec![];
+    let mut name: Option<&str> = None;
+    let mut start = 0;
+    let mut offset = 0;
+    let mut header_start: Option<usize> = None;
+    for line in contents.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let line = line.trim_end();
+        if let Some(tag) = parse_name_tag_line(line) {
+            let section_start = header_start.take().unwrap_or(line_start);
+            if section_start > start {
+                result.push((name, &contents[start..section_start]));
+            }
+            name = Some(tag);
+            start = section_start;
+        } else if is_header_line(line) {
+            header_start.get_or_insert(line_start);
+        } else {
+            header_start = None;
+        }
+    }
+    if offset > start {
+        result.push((name, &contents[start..offset]));
+    }
+    result
+}
+
+/// Adds headers to the sections of the (formatted) contents of a
+/// combined output file
+///
+/// `headers` is a mapping of the names in the name tags to the
+/// headers. Sections that are not in the mapping (e.g. the ones
+/// retained from the existing file) are kept as they are.
+fn add_section_headers(contents: &str, headers: &HashMap<String, GeneratedHeader>) -> String {
+    let mut result = String::with_capacity(contents.len());
+    for (name, section) in split_sections(contents) {
+        match name.and_then(|n| headers.get(n)) {
+            Some(h) => result.push_str(&h.add(section)),
+            None => result.push_str(section),
+        }
+    }
+    result
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Status {
     Added,
     Modified,
+    /// Modified and the existing output file has been edited manually
+    /// after it was last rendered (as per the generated header)
+    ManuallyEdited,
     Unchanged,
 }
 
@@ -31,9 +214,21 @@ impl Status {
         match &self {
             Self::Added => "added",
             Self::Modified => "modified",
+            Self::ManuallyEdited => "manually edited",
             Self::Unchanged => "unchanged",
         }
     }
+
+    /// Returns status by comparing the `current` contents of an output
+    /// file (if it exists) with the `expected` contents
+    fn compare(current: Option<&[u8]>, expected: &[u8]) -> Self {
+        match current {
+            Some(bs) if bs == expected => Self::Unchanged,
+            Some(bs) if is_manually_edited(&String::from_utf8_lossy(bs)) => Self::ManuallyEdited,
+            Some(_) => Self::Modified,
+            None => Self::Added,
+        }
+    }
 }
 
 fn ensure_trailing_newline(sql: &str) -> Cow<'_, str> {
@@ -48,22 +243,26 @@ fn ensure_trailing_newline(sql: &str) -> Cow<'_, str> {
 
 /// Returns status of a query output file without modifying it
 ///
-/// This function compares the `rendered_output` (after formatting if
-/// applicable) with the contents of the output file obtained using
-/// the `QueryOutputReader`. It returns the appropriate `Status` enum
-/// variant as follows,
+/// This function compares the `expected` output (i.e. the rendered
+/// output after formatting and adding the header if applicable, refer
+/// to `Unformatted::query`) with the contents of the output file. In
+/// case of combined layouts, it's compared with the section of the
+/// query in the combined output file. Status is returned as follows:
+///
+///   Status::Added - if the output file (or the section) doesn't
+///   exist
 ///
-///   Status::Added - if the output file doesn't already exist i.e. it
-///   would get added upon calling the `render` command
+///   Status::Modified - if the `expected` output is different from
+///   the existing output i.e. the file would get modified upon
+///   calling the `render` command.
 ///
-///   Status::Modified - if the `rendered_output` (after formatting if
-///   applicable) is different from the contents of the existing
-///   output file i.e. the file would get modified upon calling the
-///   `render` command.
+///   Status::ManuallyEdited - same as `Status::Modified` but the
+///   contents of the existing output file don't match the hash in
+///   it's generated header i.e. the manual edits would get
+///   overwritten upon calling the `render` command.
 ///
-///   Status::Unchanged - if the `rendered_output` (after formatting
-///   if applicable) is exactly the same as that contents of the
-///   existing output file
+///   Status::Unchanged - if the `expected` output is exactly the same
+///   as that contents of the existing output
 ///
 /// Returns `Error::Io` error if an error is encountered while reading
 /// the output file.
@@ -71,86 +270,150 @@ fn ensure_trailing_newline(sql: &str) -> Cow<'_, str> {
 pub fn query_status(
     query: &Query,
     reader: &QueryOutputReader,
-    formatter: Option<&Formatter>,
-    rendered_output: &str,
+    expected: &[u8],
 ) -> Result<Status, Error> {
-    let exists = reader.exists(&query.id)?;
-    if exists {
-        let contents = reader.read(&query.id)?;
-        // @NOTE: The code duplication below is intentional. It
-        // prevents unnecessary conversion of byte array into vec
-        match formatter {
-            Some(f) => {
-                if f.format(rendered_output) != contents {
-                    Ok(Status::Modified)
-                } else {
-                    Ok(Status::Unchanged)
-                }
-            }
-            None => {
-                let output = ensure_trailing_newline(rendered_output);
-                if output.as_bytes() != contents {
-                    Ok(Status::Modified)
-                } else {
-                    Ok(Status::Unchanged)
-                }
-            }
-        }
+    let current = if reader.exists(&query.id)? {
+        Some(reader.read(&query.id)?)
     } else {
-        Ok(Status::Added)
-    }
+        None
+    };
+    Ok(Status::compare(current.as_deref(), expected))
 }
 
 /// Returns status of a test output file without modifying it
 ///
-/// This function compares the `rendered_output` (after formatting if
-/// applicable) with the contents of the output file at location
-/// `path`. It returns the appropriate `Status` enum variant as
-/// follows,
-///
-///   Status::Added - if the output file doesn't already exist i.e. it
-///   would get added upon calling the `render` command
-///
-///   Status::Modified - if the `rendered_output` (after formatting if
-///   applicable) is different from the contents of the existing
-///   output file i.e. the file would get modified upon calling the
-///   `render` command.
-///
-///   Status::Unchanged - if the `rendered_output` (after formatting
-///   if applicable) is exactly the same as that contents of the
-///   existing output file
+/// This function compares the `expected` output (refer to
+/// `Unformatted::test`) with the contents of the output file at
+/// location `path`. The status is determined in the same way as
+/// `query_status`.
 ///
 /// Returns `Error::Io` error if an error is encountered while reading
 /// the output file.
 ///
-pub fn testfile_status<P: AsRef<Path>>(
-    path: P,
+pub fn testfile_status<P: AsRef<Path>>(path: P, expected: &[u8]) -> Result<Status, Error> {
+    let current = read_testfile(path)?;
+    Ok(Status::compare(current.as_deref(), expected))
+}
+
+/// Header(s) to be added to an output after formatting, so that the
+/// hash in it is of the contents as written to the file
+#[derive(Debug)]
+pub enum Headers {
+    None,
+    /// Header for the entire output
+    Whole(GeneratedHeader),
+    /// Headers for the sections of a combined output file, keyed by
+    /// the names in the name tags (refer to `add_section_headers`)
+    Sections(HashMap<String, GeneratedHeader>),
+}
+
+impl From<Option<GeneratedHeader>> for Headers {
+    fn from(header: Option<GeneratedHeader>) -> Self {
+        match header {
+            Some(h) => Self::Whole(h),
+            None => Self::None,
+        }
+    }
+}
+
+impl Headers {
+    fn add(&self, contents: String) -> String {
+        match self {
+            Self::None => contents,
+            Self::Whole(h) => h.add(&contents),
+            Self::Sections(hs) => add_section_headers(&contents, hs),
+        }
+    }
+}
+
+/// Contents of an output file (or the section of a combined output
+/// file) before formatting
+///
+/// The outputs are first collected and then formatted all at once
+/// (see `format_outputs`) as it's much faster in case of external
+/// formatters.
+#[derive(Debug)]
+pub struct Unformatted {
+    pub path: PathBuf,
+    pub content: String,
+    pub headers: Headers,
+}
+
+impl Unformatted {
+    /// Returns the query output that is compared with the output file
+    /// (or the section of the combined output file) by
+    /// `query_status`
+    pub fn query(
+        formatter: Option<&Formatter>,
+        query: &Query,
+        rendered_output: &str,
+        header: Option<GeneratedHeader>,
+    ) -> Self {
+        // The formatter adds a trailing newline to the output
+        let content = match formatter {
+            Some(_) => rendered_output.to_owned(),
+            None => ensure_trailing_newline(rendered_output).into_owned(),
+        };
+        Self {
+            path: query.output.clone(),
+            content,
+            headers: Headers::from(header),
+        }
+    }
+
+    /// Returns the test output that is compared with the output file
+    /// by `testfile_status`
+    pub fn test(path: &Path, rendered_output: String, header: Option<GeneratedHeader>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            content: rendered_output,
+            headers: Headers::from(header),
+        }
+    }
+}
+
+/// Formats the contents of all the `outputs` in a single batch (refer
+/// to `Formatter::format_batch`) and adds the headers to them
+///
+/// The resulting files are in the same order as the `outputs`.
+pub fn format_outputs(
     formatter: Option<&Formatter>,
-    rendered_output: &str,
-) -> Result<Status, Error> {
+    outputs: Vec<Unformatted>,
+    jobs: usize,
+    batch_dir: &Path,
+) -> Result<Vec<GeneratedFile>, Error> {
+    let formatted = match formatter {
+        Some(f) => {
+            let inputs = outputs
+                .iter()
+                .map(|o| o.content.as_str())
+                .collect::<Vec<&str>>();
+            let formatted = f.format_batch(&inputs, jobs, batch_dir)?;
+            outputs
+                .into_iter()
+                .zip(formatted)
+                .map(|(o, bs)| (o.path, String::from_utf8_lossy(&bs).into_owned(), o.headers))
+                .collect::<Vec<_>>()
+        }
+        None => outputs
+            .into_iter()
+            .map(|o| (o.path, o.content, o.headers))
+            .collect::<Vec<_>>(),
+    };
+    Ok(formatted
+        .into_iter()
+        .map(|(path, contents, headers)| GeneratedFile::new(path, headers.add(contents)))
+        .collect())
+}
+
+/// Returns current contents of the test output file or `None` if it
+/// doesn't exist
+pub fn read_testfile<P: AsRef<Path>>(path: P) -> Result<Option<Vec<u8>>, Error> {
     let exists = path.as_ref().try_exists().map_err(Error::Io)?;
     if exists {
-        let contents = fs::read(path).map_err(Error::Io)?;
-        // @NOTE: The code duplication below is intentional. It
-        // prevents unnecessary conversion of byte array into vec
-        match formatter {
-            Some(f) => {
-                if f.format(rendered_output) != contents {
-                    Ok(Status::Modified)
-                } else {
-                    Ok(Status::Unchanged)
-                }
-            }
-            None => {
-                if rendered_output.as_bytes() != contents {
-                    Ok(Status::Modified)
-                } else {
-                    Ok(Status::Unchanged)
-                }
-            }
-        }
+        fs::read(path).map(Some).map_err(Error::Io)
     } else {
-        Ok(Status::Added)
+        Ok(None)
     }
 }
 
@@ -158,6 +421,11 @@ pub fn testfile_status<P: AsRef<Path>>(
 pub enum Layout {
     OneFileOneQuery,
     OneFileAllQueries(Option<PathBuf>),
+    /// One combined output file for all queries of a query template
+    OneFilePerTemplate,
+    /// One combined output file for all queries with the same
+    /// `group` label
+    Grouped,
 }
 
 impl Layout {
@@ -165,6 +433,21 @@ impl Layout {
         Self::OneFileOneQuery
     }
 
+    /// Returns whether multiple queries are written to the same
+    /// output file in case of this layout
+    pub fn is_combined(&self) -> bool {
+        !matches!(self, Self::OneFileOneQuery)
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            Self::OneFileOneQuery => "one-file-one-query",
+            Self::OneFileAllQueries(_) => "one-file-all-queries",
+            Self::OneFilePerTemplate => "one-file-per-template",
+            Self::Grouped => "grouped",
+        }
+    }
+
     pub fn decode<P: AsRef<Path>>(
         value: &Value,
         output_file: Option<&Value>,
@@ -185,6 +468,10 @@ impl Layout {
                         None => None,
                     };
                     Ok(Self::OneFileAllQueries(filepath))
+                } else if s == "one-file-per-template" {
+                    Ok(Self::OneFilePerTemplate)
+                } else if s == "grouped" {
+                    Ok(Self::Grouped)
                 } else {
                     Err(parse_error!(
                         "Invalid value for 'query_output_layout': '{s}'"
@@ -199,11 +486,174 @@ impl Layout {
     }
 }
 
+/// Variables supported in an `OutputPattern`
+const OUTPUT_PATTERN_VARS: [&str; 4] = ["id", "slug", "template_stem", "conds"];
+
+/// Pattern for deriving the path of an output file (relative to the
+/// output dir) when it's not explicitly specified in the manifest
+///
+/// The pattern may include the following variables in curly braces
+/// e.g. `{template_stem}/{slug}.sql`,
+///
+///   - `id`: id of the query
+///   - `slug`: id of the query with special chars replaced by
+///     hyphens (see `slugify_id`)
+///   - `template_stem`: file name of the template (query template in
+///     case of queries and test template in case of tests) without
+///     the extension(s)
+///   - `conds`: conds of the query (sorted) joined by hyphens
+#[derive(Debug)]
+pub struct OutputPattern(String);
+
+impl OutputPattern {
+    pub fn decode(value: &Value, key: &str) -> Result<Self, Error> {
+        let pattern = decode_string(value, key)?;
+        // @UNWRAP: The regex pattern is known to be valid
+        let re = Regex::new(r"\{([^{}]*)\}").unwrap();
+        for caps in re.captures_iter(&pattern) {
+            let var = &caps[1];
+            if !OUTPUT_PATTERN_VARS.contains(&var) {
+                return Err(parse_error!("Unknown variable '{{{var}}}' in '{key}'"));
+            }
+        }
+        // The expanded path must be a file path inside the output
+        // dir
+        let path = Path::new(&pattern);
+        let invalid = pattern.ends_with('/')
+            || path.file_name().is_none()
+            || path
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if invalid {
+            return Err(parse_error!("Invalid value for '{key}': '{pattern}'"));
+        }
+        Ok(Self(pattern))
+    }
+
+    /// Returns the path (relative to the output dir) by substituting
+    /// the variables in the pattern
+    pub fn expand(&self, id: &str, template: &Path, conds: &HashSet<String>) -> PathBuf {
+        let mut conds = conds.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+        conds.sort();
+        let path = self
+            .0
+            .replace("{id}", id)
+            .replace("{slug}", &slugify_id(id))
+            .replace("{template_stem}", template_stem(template))
+            .replace("{conds}", &conds.join("-"));
+        PathBuf::from(path)
+    }
+}
+
+/// Output file along with the contents to be written to it
+///
+/// Besides the other files generated from the manifest (e.g. the
+/// query params sidecar files), the query and test SQL files are also
+/// represented by it once they are formatted, so that all output files
+/// can be written at once (see `write_atomically`). The contents are
+/// compared and written as they are i.e. without formatting.
+#[derive(Debug)]
+pub struct GeneratedFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+impl GeneratedFile {
+    pub fn new(path: PathBuf, contents: String) -> Self {
+        Self { path, contents }
+    }
+
+    /// Returns contents of the existing file if any
+    pub fn current(&self) -> Result<Option<Vec<u8>>, Error> {
+        read_testfile(&self.path)
+    }
+
+    pub fn status(&self) -> Result<Status, Error> {
+        match self.current()? {
+            Some(bs) if bs == self.contents.as_bytes() => Ok(Status::Unchanged),
+            Some(_) => Ok(Status::Modified),
+            None => Ok(Status::Added),
+        }
+    }
+
+    /// Writes the contents to a temporary file in the same dir as the
+    /// file (creating the parent dirs if required) and returns it's
+    /// path
+    fn stage(&self) -> Result<PathBuf, Error> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(Error::Io)?;
+        }
+        let file_name = self
+            .path
+            .file_name()
+            .map(|s| s.to_string_lossy())
+            .unwrap_or_default();
+        let tmp_path = self
+            .path
+            .with_file_name(format!(".{file_name}.tapestry-tmp"));
+        fs::write(&tmp_path, &self.contents).map_err(Error::Io)?;
+        Ok(tmp_path)
+    }
+}
+
+/// Writes all the files such that either all of them are written or
+/// none of them
+///
+/// The contents are first written to temporary files in the same dirs
+/// as the output files, which are then renamed to the output files
+/// (renaming a file is atomic on the same file system). If an error
+/// is encountered, the temporary files are removed and the existing
+/// output files are left untouched.
+pub fn write_atomically(files: &[GeneratedFile]) -> Result<(), Error> {
+    let mut staged: Vec<(PathBuf, &Path)> = Vec::with_capacity(files.len());
+    let mut result = Ok(());
+    for file in files {
+        match file.stage() {
+            Ok(tmp_path) => staged.push((tmp_path, &file.path)),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    if result.is_ok() {
+        // @NOTE: If a rename fails midway (unlikely after all the
+        // contents have been written successfully), the remaining
+        // temporary files are cleaned up below.
+        let mut renamed = 0;
+        for (tmp_path, path) in &staged {
+            if let Err(e) = fs::rename(tmp_path, path) {
+                result = Err(Error::Io(e));
+                break;
+            }
+            renamed += 1;
+        }
+        staged.drain(..renamed);
+    }
+    for (tmp_path, _) in staged {
+        if let Err(e) = fs::remove_file(&tmp_path) {
+            warn!(
+                "Couldn't remove temporary file {}: {}",
+                tmp_path.display(),
+                e
+            );
+        }
+    }
+    result
+}
+
 // Struct for representing output files that need to written
+//
+// `query_id` is set only in case of query output files. It's used for
+// identifying the query sections when writing to a combined file.
 pub struct SqlToWrite<'a> {
+    pub query_id: Option<&'a str>,
     pub path: &'a Path,
     pub sql: String,
     pub name_tag: Option<&'a NameTag>,
+    pub result: Option<&'a QueryResult>,
+    pub description: Option<String>,
+    pub header: Option<GeneratedHeader>,
 }
 
 impl<'a> SqlToWrite<'a> {
@@ -215,7 +665,9 @@ impl<'a> SqlToWrite<'a> {
     fn tagged_sql(&'a self, tagger: Option<&NameTagger>) -> Cow<'a, str> {
         match tagger {
             Some(t) => match self.name_tag {
-                Some(nt) => t.ensure_name_tag(&self.sql, nt),
+                Some(nt) => {
+                    t.ensure_name_tag(&self.sql, nt, self.result, self.description.as_deref())
+                }
                 None => Cow::from(&self.sql),
             },
             None => Cow::from(&self.sql),
@@ -223,108 +675,153 @@ impl<'a> SqlToWrite<'a> {
     }
 }
 
-fn write<P: AsRef<Path>>(
-    path: P,
-    formatter: Option<&Formatter>,
-    content: &str,
-) -> Result<(), Error> {
-    match formatter {
-        Some(f) => {
-            let text = f.format(content);
-            fs::write(path, text).map_err(Error::Io)?;
-        }
-        None => {
-            fs::write(path, content).map_err(Error::Io)?;
-        }
-    }
-    Ok(())
-}
-
-// Combines file contents and writes to a single file
+// Combines file contents into a single file i.e. the combined output
+// file at `filepath` (before formatting)
 //
-// # Panics!
-// This function is only supposed to be called when all paths in the
-// passed Vec<FileToWrite> are equal i.e. all the content is to be
-// written to the same file (Layout = OneFileAllQueries). If this
-// condition is not satisfied, this functions panics.
-//
-// @TODO: This function currently concatenates Strings in memory and
-// then writes in a single call. A more memory efficient approach
-// would be to keep the file open and write each query to it one by
-// one
-pub fn write_combined(
-    files: &Vec<SqlToWrite>,
-    formatter: Option<&Formatter>,
+// Only the `files` having the same path are considered and `queries`
+// are expected to be the ones that are written to the file (see
+// `Queries::by_output`). If the considered files don't include all
+// the `queries` (i.e. only a subset of queries has been rendered),
+// the sections for the remaining queries are parsed from the existing
+// combined file and written back as they are. The queries are written
+// in the same order as they are defined in the manifest. If none of
+// the `files` are to be written to `filepath`, `None` is returned
+// i.e. the file is to be left untouched.
+pub fn combined_output(
+    filepath: &Path,
+    files: &[SqlToWrite],
+    queries: &[&Query],
     tagger: Option<&NameTagger>,
-) -> Result<(), Error> {
-    let mut combined_output = String::new();
-    let mut paths = Vec::with_capacity(files.len());
-    for file in files {
-        let sql = file.tagged_sql(tagger);
-        combined_output.push_str(&sql);
-        combined_output.push('\n');
-        combined_output.push('\n');
-        paths.push(file.path);
+) -> Result<Option<Unformatted>, Error> {
+    let files = files
+        .iter()
+        .filter(|f| f.path == filepath)
+        .collect::<Vec<&SqlToWrite>>();
+    if files.is_empty() {
+        return Ok(None);
     }
-    let mut path_set: HashSet<&Path> = HashSet::from_iter(paths);
-    if path_set.len() > 1 {
-        panic!("write_combined function called with disparate file paths. Please report this bug");
+
+    // Existing sections are required only if a subset of queries is
+    // being written
+    let existing = if files.len() < queries.len() {
+        let t = tagger.ok_or(Error::Layout(
+            "name_tagger is required in case of combined output files".to_string(),
+        ))?;
+        // @NOTE: The formatter is not passed here as the sections
+        // are to be written as they are i.e. without any tweaks for
+        // comparison with the rendered output.
+        Some(parse_combined_sql(filepath, t, None, queries)?)
+    } else {
+        None
+    };
+
+    let mut combined_output = String::new();
+    for query in queries {
+        match files.iter().find(|f| f.query_id == Some(query.id.as_str())) {
+            Some(file) => {
+                let sql = file.tagged_sql(tagger);
+                combined_output.push_str(&sql);
+                combined_output.push('\n');
+                combined_output.push('\n');
+            }
+            None => {
+                let section = existing
+                    .as_ref()
+                    .and_then(|sections| sections.get(query.id.as_str()))
+                    .ok_or(Error::QueryOutputParsing(format!(
+                        "Query '{}' not found in combined SQL file: {}",
+                        query.id,
+                        filepath.display()
+                    )))?;
+                // @NOTE: A parsed section includes the blank lines
+                // that follow the query, except for the last newline
+                // char which gets consumed when splitting the file
+                // into lines.
+                combined_output.push_str(section);
+                combined_output.push('\n');
+            }
+        }
     }
-    let filepath = path_set.drain().next().unwrap();
-    write(filepath, formatter, &combined_output)
+
+    // Headers are added to the sections after formatting the combined
+    // output (refer to `Headers`)
+    let headers = match tagger {
+        Some(t) => files
+            .iter()
+            .filter_map(|f| match (f.name_tag, &f.header) {
+                (Some(nt), Some(h)) => Some((t.tag(nt).into_owned(), h.clone())),
+                _ => None,
+            })
+            .collect::<HashMap<String, GeneratedHeader>>(),
+        None => HashMap::new(),
+    };
+    let headers = if headers.is_empty() {
+        Headers::None
+    } else {
+        Headers::Sections(headers)
+    };
+    Ok(Some(Unformatted {
+        path: filepath.to_path_buf(),
+        content: combined_output,
+        headers,
+    }))
 }
 
-// Writes file contents to separate files in a loop
-pub fn write_separately(
-    files: &Vec<SqlToWrite>,
-    formatter: Option<&Formatter>,
-    tagger: Option<&NameTagger>,
-) -> Result<(), Error> {
-    for file in files {
-        let sql = file.tagged_sql(tagger);
-        write(file.path, formatter, &sql)?;
-    }
-    Ok(())
+// Returns separate outputs (before formatting) for the file contents
+// in the same order
+pub fn separate_outputs(files: &[SqlToWrite], tagger: Option<&NameTagger>) -> Vec<Unformatted> {
+    files
+        .iter()
+        .map(|file| Unformatted {
+            path: file.path.to_path_buf(),
+            content: file.tagged_sql(tagger).into_owned(),
+            headers: Headers::from(file.header.clone()),
+        })
+        .collect()
 }
 
-// @TODO: Add tests
 fn parse_combined_sql<'a>(
     filepath: &Path,
     tagger: &NameTagger,
     formatter: Option<&Formatter>,
-    queries: &'a Queries,
+    queries: &[&'a Query],
 ) -> Result<HashMap<&'a str, String>, Error> {
+    // @NOTE: The sections are identified by the names in the name
+    // tags irrespective of the flavor, so that the sections are
+    // recognized even if the flavor or the `result` of a query
+    // changes after the file was last written.
     let tags_to_ids = queries
         .iter()
         .map(|q| {
-            let tag = tagger.make_name_tag(&q.name_tag);
+            let tag = tagger.tag(&q.name_tag).into_owned();
             (tag, q.id.as_str())
         })
         .collect::<HashMap<String, &str>>();
     let mut curr_id: Option<&str> = None;
     let mut result: HashMap<&str, String> = HashMap::with_capacity(tags_to_ids.len());
 
-    let file = File::open(filepath).map_err(Error::Io)?;
-
-    // @NOTE: `map_while(Result::ok)` is the equivalent of flatten
-    for line in io::BufReader::new(file).lines().map_while(Result::ok) {
-        match tags_to_ids.get(&line) {
+    let contents = fs::read_to_string(filepath).map_err(Error::Io)?;
+    for (name, section) in split_sections(&contents) {
+        // @NOTE: The last newline char of a section is dropped so
+        // that it's the same as joining the lines of the section
+        let section = section.strip_suffix('\n').unwrap_or(section);
+        match name.and_then(|n| tags_to_ids.get(n)) {
             Some(id) => {
                 curr_id = Some(id);
-                result.insert(id, line);
+                result.insert(id, section.to_owned());
             }
             None => {
-                if curr_id.is_none() && line.is_empty() {
+                if curr_id.is_none() && section.trim().is_empty() {
                     continue;
                 }
+                // Sections with unknown name tags are considered
+                // part of the preceding one
                 let key =
                     curr_id.ok_or(Error::QueryOutputParsing(filepath.display().to_string()))?;
-                if let Some(qlines) = result.get_mut(key) {
-                    qlines.push('\n');
-                    qlines.push_str(line.as_str());
-                } else {
-                    warn!("Query id not identified when parsing output file. Discarding line");
-                }
+                // @UNWRAP: The section for `curr_id` is inserted above
+                let qlines = result.get_mut(key).unwrap();
+                qlines.push('\n');
+                qlines.push_str(section);
             }
         }
     }
@@ -347,11 +844,62 @@ fn parse_combined_sql<'a>(
     Ok(result)
 }
 
+// Removes sections from the contents of the combined output file
+// whose names (as per the name tags) are not in `known_tags`
+//
+// Refer to `split_sections` for what constitutes a section. Returns a
+// tuple of the updated contents and the name tag lines of the removed
+// sections.
+fn prune_sections(contents: &str, known_tags: &HashSet<String>) -> (String, Vec<String>) {
+    let mut result = String::with_capacity(contents.len());
+    let mut removed: Vec<String> = vec![];
+    for (name, section) in split_sections(contents) {
+        match name {
+            Some(n) if !known_tags.contains(n) => {
+                // @UNWRAP: A named section always has a name tag line
+                let tag_line = section
+                    .lines()
+                    .find(|line| parse_name_tag_line(line).is_some())
+                    .unwrap();
+                removed.push(tag_line.to_owned());
+            }
+            _ => result.push_str(section),
+        }
+    }
+    (result, removed)
+}
+
+/// Removes the sections from the combined output file whose name
+/// tags don't match any of the `queries` (i.e. the queries that are
+/// written to the file)
+///
+/// The remaining sections are retained as they are. The file is
+/// rewritten atomically (see `write_atomically`), so that it's never
+/// left truncated. Returns the name tags of the removed sections. If
+/// `dry_run` is true, the file is not modified.
+pub fn prune_combined_sql(
+    filepath: &Path,
+    tagger: &NameTagger,
+    queries: &[&Query],
+    dry_run: bool,
+) -> Result<Vec<String>, Error> {
+    let known_tags = queries
+        .iter()
+        .map(|q| tagger.tag(&q.name_tag).into_owned())
+        .collect::<HashSet<String>>();
+    let contents = fs::read_to_string(filepath).map_err(Error::Io)?;
+    let (pruned, removed) = prune_sections(&contents, &known_tags);
+    if !removed.is_empty() && !dry_run {
+        write_atomically(&[GeneratedFile::new(filepath.to_path_buf(), pruned)])?;
+    }
+    Ok(removed)
+}
+
 /// Abstraction for reading query output files based on the layout
 /// i.e. if layout = OneFileOneQuery, then it will be read from
-/// individual files, otherwise if layout = OneFileAllQueries, then
-/// the individual queries will be first parsed by reading the single
-/// output and cached
+/// individual files, otherwise in case of the combined layouts
+/// (e.g. OneFileAllQueries), the individual queries will be first
+/// parsed by reading the combined output files and cached
 pub struct QueryOutputReader<'a> {
     metadata: &'a Metadata,
     parsed_query_store: Option<HashMap<&'a str, String>>,
@@ -364,19 +912,26 @@ impl<'a> QueryOutputReader<'a> {
         // `OnceCell.get_or_try_init` is a nightly only feature.
         let pqs = match metadata.query_output_layout {
             Layout::OneFileOneQuery => None,
-            Layout::OneFileAllQueries(_) => {
-                // Unwrap is acceptable as None value is expected only
-                // if the layout = OneFileOneQuery
-                let filepath = metadata.combined_output_file()?.unwrap();
+            _ => {
                 let tagger = metadata.name_tagger.as_ref().ok_or(Error::Layout(
-                    "name_tagger is required when layout = one-file-all-queries".to_string(),
+                    "name_tagger is required in case of combined output files".to_string(),
                 ))?;
-                Some(parse_combined_sql(
-                    filepath,
-                    tagger,
-                    metadata.formatter.as_ref(),
-                    &metadata.queries,
-                )?)
+                let mut store = HashMap::with_capacity(metadata.queries.len());
+                for filepath in metadata.combined_output_files()? {
+                    // If the combined output file doesn't exist yet
+                    // (i.e. `render` hasn't been run even once after
+                    // adding the queries), the queries are
+                    // considered to be missing
+                    if filepath.try_exists().map_err(Error::Io)? {
+                        store.extend(parse_combined_sql(
+                            filepath,
+                            tagger,
+                            metadata.formatter.as_ref(),
+                            &metadata.queries.by_output(filepath),
+                        )?);
+                    }
+                }
+                Some(store)
             }
         };
         Ok(Self {
@@ -393,7 +948,7 @@ impl<'a> QueryOutputReader<'a> {
             .ok_or(Error::UndefinedQuery(query_id.to_owned()))?;
         match self.metadata.query_output_layout {
             Layout::OneFileOneQuery => fs::read(&query.output).map_err(Error::Io),
-            Layout::OneFileAllQueries(_) => {
+            _ => {
                 let query_store =
                     self.parsed_query_store
                         .as_ref()
@@ -420,7 +975,7 @@ impl<'a> QueryOutputReader<'a> {
             .ok_or(Error::UndefinedQuery(query_id.to_owned()))?;
         match self.metadata.query_output_layout {
             Layout::OneFileOneQuery => query.output.try_exists().map_err(Error::Io),
-            Layout::OneFileAllQueries(_) => {
+            _ => {
                 let query_store =
                     self.parsed_query_store
                         .as_ref()
@@ -437,6 +992,9 @@ impl<'a> QueryOutputReader<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::query::test_util::setup_queries;
+    use crate::tagging::{NameTagFlavor, NameTagStyle};
+    use crate::util::test_util::TempDir;
     use toml::Table;
 
     #[test]
@@ -509,4 +1067,465 @@ query_output_file = 'queries.sql'
             _ => assert!(false),
         };
     }
+
+    #[test]
+    fn test_output_pattern() {
+        let value = Value::String("{template_stem}/{slug}.sql".to_owned());
+        let pattern = OutputPattern::decode(&value, "query_output_pattern").unwrap();
+        let conds = HashSet::from(["limit".to_owned(), "genre".to_owned()]);
+        let template = Path::new("templates/queries/artists_long_songs.sql.j2");
+        assert_eq!(
+            PathBuf::from("artists_long_songs/artists_long_songs-genre-limit.sql"),
+            pattern.expand("artists_long_songs@genre*limit", template, &conds)
+        );
+
+        let value = Value::String("{id}__{conds}.sql".to_owned());
+        let pattern = OutputPattern::decode(&value, "query_output_pattern").unwrap();
+        assert_eq!(
+            PathBuf::from("artists_long_songs@genre*limit__genre-limit.sql"),
+            pattern.expand("artists_long_songs@genre*limit", template, &conds)
+        );
+
+        let value = Value::String("{template}/{id}.sql".to_owned());
+        match OutputPattern::decode(&value, "query_output_pattern") {
+            Err(Error::Parsing(msg)) => assert_eq!(
+                "Unknown variable '{template}' in 'query_output_pattern'",
+                msg
+            ),
+            _ => assert!(false),
+        }
+
+        for invalid in ["../{id}.sql", "/tmp/{id}.sql", "{id}/", ""] {
+            let value = Value::String(invalid.to_owned());
+            match OutputPattern::decode(&value, "test_output_pattern") {
+                Err(Error::Parsing(msg)) => assert_eq!(
+                    format!("Invalid value for 'test_output_pattern': '{invalid}'"),
+                    msg
+                ),
+                _ => assert!(false),
+            }
+        }
+    }
+
+    #[test]
+    fn test_prune_sections() {
+        let contents = r#"-- name: foo
+SELECT 1;
+
+-- name: old-query
+-- name: this comment is part of the query
+SELECT 2;
+
+-- name: bar
+SELECT 3;
+"#;
+        let known_tags = HashSet::from(["foo".to_owned(), "bar".to_owned()]);
+        let (result, removed) = prune_sections(contents, &known_tags);
+        let expected = r#"-- name: foo
+SELECT 1;
+
+-- name: bar
+SELECT 3;
+"#;
+        assert_eq!(expected, result);
+        assert_eq!(vec!["-- name: old-query"], removed);
+
+        // When all sections are known
+        let (result, removed) = prune_sections(expected, &known_tags);
+        assert_eq!(expected, result);
+        assert!(removed.is_empty());
+
+        // Generated headers of the sections are removed along with
+        // them
+        let header = GeneratedHeader::new(Path::new("old.sql.j2"), &HashSet::new());
+        let contents = format!(
+            "-- name: foo\nSELECT 1;\n\n{}-- name: bar\nSELECT 3;\n",
+            header.add("-- name: old-query\nSELECT 2;\n\n")
+        );
+        let (result, removed) = prune_sections(&contents, &known_tags);
+        assert_eq!(expected, result);
+        assert_eq!(vec!["-- name: old-query"], removed);
+    }
+
+    #[test]
+    fn test_prune_combined_sql() {
+        let tmp = TempDir::new("prune-combined");
+        let filepath = tmp.path().join("queries.sql");
+        let qs = setup_queries(vec![
+            (
+                "foo",
+                "foo.sql.j2",
+                vec![],
+                filepath.to_str().unwrap(),
+                None,
+            ),
+            (
+                "bar",
+                "bar.sql.j2",
+                vec![],
+                filepath.to_str().unwrap(),
+                None,
+            ),
+        ]);
+        let queries = qs.iter().map(|q| q.as_ref()).collect::<Vec<&Query>>();
+        let tagger = NameTagger {
+            style: NameTagStyle::Exact,
+            flavor: NameTagFlavor::Default,
+            template: None,
+        };
+        let contents =
+            "-- name: foo\nSELECT 1;\n\n-- name: old\nSELECT 2;\n\n-- name: bar\nSELECT 3;\n";
+        fs::write(&filepath, contents).unwrap();
+
+        // Nothing is removed in case of dry run
+        let removed = prune_combined_sql(&filepath, &tagger, &queries, true).unwrap();
+        assert_eq!(vec!["-- name: old"], removed);
+        assert_eq!(contents, fs::read_to_string(&filepath).unwrap());
+
+        // The file is rewritten without the unknown section, leaving
+        // no temporary files behind
+        let removed = prune_combined_sql(&filepath, &tagger, &queries, false).unwrap();
+        assert_eq!(vec!["-- name: old"], removed);
+        assert_eq!(
+            "-- name: foo\nSELECT 1;\n\n-- name: bar\nSELECT 3;\n",
+            fs::read_to_string(&filepath).unwrap()
+        );
+        assert_eq!(1, fs::read_dir(tmp.path()).unwrap().count());
+    }
+
+    #[test]
+    fn test_prune_file() {
+        let tmp = TempDir::new("prune");
+        let dir = tmp.path();
+        let header = GeneratedHeader::new(Path::new("a.sql.j2"), &HashSet::new());
+        let marked = dir.join("marked.sql");
+        let with_header = dir.join("with_header.sql");
+        let unmarked = dir.join("unmarked.sql");
+        fs::write(&marked, mark_generated(Cow::from("SELECT 1;")).as_ref()).unwrap();
+        fs::write(&with_header, header.add("-- name: a\nSELECT 1;\n")).unwrap();
+        fs::write(&unmarked, "SELECT 1;\n").unwrap();
+
+        // Nothing is removed in case of dry run
+        for path in [&marked, &with_header] {
+            assert!(prune_file(path, true).unwrap());
+            assert!(path.exists());
+        }
+
+        // Files having either the marker or the header (but not
+        // necessarily both) are removed
+        for path in [&marked, &with_header] {
+            assert!(prune_file(path, false).unwrap());
+            assert!(!path.exists());
+        }
+        assert!(!prune_file(&unmarked, false).unwrap());
+        assert!(unmarked.exists());
+    }
+
+    #[test]
+    fn test_mark_generated() {
+        let sql = "SELECT 1;";
+        let marked = mark_generated(Cow::from(sql));
+        assert_eq!(format!("SELECT 1;\n{GENERATED_MARKER}\n"), marked);
+        assert!(has_generated_marker(&marked));
+
+        // Marker is not added again if it already exists
+        let marked_again = mark_generated(marked.clone());
+        assert_eq!(marked, marked_again);
+
+        assert!(!has_generated_marker(sql));
+        assert!(is_generated(&marked));
+        assert!(!is_generated(sql));
+    }
+
+    #[test]
+    fn test_generated_header() {
+        let conds = HashSet::from(["limit".to_owned(), "genre".to_owned()]);
+        let header = GeneratedHeader::new(Path::new("songs.sql.j2"), &conds);
+
+        // When the contents begin with a name tag (and a docstring),
+        // the header is added before it so that it's not considered
+        // part of the docstring
+        let sql = "-- name: songs\n-- All songs\nSELECT * FROM songs;\n";
+        let with_header = header.add(sql);
+        let expected = format!(
+            "-- Generated by tapestry from 'songs.sql.j2' with conds: genre, limit. Do not edit manually.\n-- tapestry-hash: {}\n-- name: songs\n-- All songs\nSELECT * FROM songs;\n",
+            fnv1a_hex(sql.trim_end())
+        );
+        assert_eq!(expected, with_header);
+        assert!(!is_manually_edited(&with_header));
+
+        let edited = with_header.replace("songs;", "songs LIMIT 10;");
+        assert!(is_manually_edited(&edited));
+
+        // Changes in trailing whitespace are not considered as edits
+        assert!(!is_manually_edited(&format!("{with_header}\n\n")));
+
+        // When the contents don't begin with a name tag
+        let header = GeneratedHeader::new(Path::new("songs_test.sql.j2"), &HashSet::new());
+        let with_header = header.add("BEGIN;\nSELECT 1;\n");
+        assert!(with_header.starts_with(
+            "-- Generated by tapestry from 'songs_test.sql.j2'. Do not edit manually.\n-- tapestry-hash: "
+        ));
+        assert!(with_header.ends_with("\nBEGIN;\nSELECT 1;\n"));
+
+        // Files without the header are never considered as edited
+        assert!(!is_manually_edited("SELECT 1;\n"));
+    }
+
+    #[test]
+    fn test_add_section_headers() {
+        let conds = HashSet::new();
+        let header = GeneratedHeader::new(Path::new("b.sql.j2"), &conds);
+        let headers = HashMap::from([("b".to_owned(), header.clone())]);
+        let contents = "-- name: a\nSELECT 1;\n\n-- name: b\nSELECT 2;\n\n";
+        let result = add_section_headers(contents, &headers);
+        let expected = format!(
+            "-- name: a\nSELECT 1;\n\n{}",
+            header.add("-- name: b\nSELECT 2;\n\n")
+        );
+        assert_eq!(expected, result);
+        assert!(!is_manually_edited(
+            &header.add("-- name: b\nSELECT 2;\n\n")
+        ));
+
+        // Header of a section retained from the existing file (i.e.
+        // right before the name tag) is not mistaken for the end of
+        // the preceding section
+        let header_a = GeneratedHeader::new(Path::new("a.sql.j2"), &conds);
+        let headers = HashMap::from([("a".to_owned(), header_a.clone())]);
+        let result = add_section_headers(&expected, &headers);
+        assert_eq!(
+            format!(
+                "{}{}",
+                header_a.add("-- name: a\nSELECT 1;\n\n"),
+                header.add("-- name: b\nSELECT 2;\n\n")
+            ),
+            result
+        );
+    }
+
+    #[test]
+    fn test_parse_combined_sql() {
+        let tmp = TempDir::new("parse-combined");
+        let filepath = tmp.path().join("queries.sql");
+        let qs = setup_queries(vec![
+            ("a", "a.sql.j2", vec![], filepath.to_str().unwrap(), None),
+            ("b", "b.sql.j2", vec![], filepath.to_str().unwrap(), None),
+        ]);
+        let queries = qs.iter().map(|q| q.as_ref()).collect::<Vec<&Query>>();
+        let tagger = NameTagger {
+            style: NameTagStyle::Exact,
+            flavor: NameTagFlavor::Default,
+            template: None,
+        };
+
+        // The docstring lines (i.e. the description) right after the
+        // name tag are part of the section, and so is the generated
+        // header before it
+        let header = GeneratedHeader::new(Path::new("b.sql.j2"), &HashSet::new());
+        let section_a = "-- name: a\n-- Returns all the songs\n--\n-- Second paragraph\nSELECT 1;";
+        let section_b = header.add("-- name: b\n-- Returns the song\nSELECT 2;");
+        fs::write(&filepath, format!("{section_a}\n\n{section_b}\n")).unwrap();
+        let parsed = parse_combined_sql(&filepath, &tagger, None, &queries).unwrap();
+        assert_eq!(format!("{section_a}\n"), parsed["a"]);
+        assert_eq!(section_b, parsed["b"]);
+    }
+
+    #[test]
+    fn test_split_sections() {
+        let header = GeneratedHeader::new(Path::new("b.sql.j2"), &HashSet::new());
+        let section_b = header.add("-- name: b\n-- Docstring of b\nSELECT 2;\n\n");
+        let contents =
+            format!("\n-- name: a\nSELECT 1;\n-- a comment\n\n{section_b}-- name: c\nSELECT 3;");
+        assert_eq!(
+            vec![
+                (None, "\n"),
+                (Some("a"), "-- name: a\nSELECT 1;\n-- a comment\n\n"),
+                (Some("b"), section_b.as_str()),
+                (Some("c"), "-- name: c\nSELECT 3;"),
+            ],
+            split_sections(&contents)
+        );
+    }
+
+    #[test]
+    fn test_combined_output_partial() {
+        let tmp = TempDir::new("combined");
+        let dir = tmp.path();
+        let filepath = dir.join("queries.sql");
+        let qs = setup_queries(vec![
+            ("a", "a.sql.j2", vec![], filepath.to_str().unwrap(), None),
+            ("b", "b.sql.j2", vec![], filepath.to_str().unwrap(), None),
+            ("c", "c.sql.j2", vec![], filepath.to_str().unwrap(), None),
+        ]);
+        let queries = qs.iter().map(|q| q.as_ref()).collect::<Vec<&Query>>();
+        let tagger = NameTagger {
+            style: NameTagStyle::Exact,
+            flavor: NameTagFlavor::Default,
+            template: None,
+        };
+        fs::write(
+            &filepath,
+            "-- name: a\nSELECT 1;\n\n-- name: b\nSELECT 2;\n\n-- name: c\nSELECT 3;\n\n",
+        )
+        .unwrap();
+
+        // Only query `b` is rendered, the sections of the other
+        // queries are retained from the existing file as they are
+        let b = qs.get("b").unwrap();
+        let files = vec![SqlToWrite {
+            query_id: Some("b"),
+            path: &filepath,
+            sql: "SELECT 20;".to_owned(),
+            name_tag: Some(&b.name_tag),
+            result: None,
+            description: None,
+            header: None,
+        }];
+        let output = combined_output(&filepath, &files, &queries, Some(&tagger))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            "-- name: a\nSELECT 1;\n\n-- name: b\nSELECT 20;\n\n-- name: c\nSELECT 3;\n\n",
+            output.content
+        );
+
+        // Generated headers of the retained sections (i.e. the lines
+        // right before the name tags) are retained along with them
+        let header_a = GeneratedHeader::new(Path::new("a.sql.j2"), &HashSet::new());
+        let header_c = GeneratedHeader::new(Path::new("c.sql.j2"), &HashSet::new());
+        let section_a = header_a.add("-- name: a\nSELECT 1;\n\n");
+        let section_c = header_c.add("-- name: c\nSELECT 3;\n\n");
+        fs::write(
+            &filepath,
+            format!("{section_a}-- name: b\nSELECT 2;\n\n{section_c}"),
+        )
+        .unwrap();
+        let output = combined_output(&filepath, &files, &queries, Some(&tagger))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            format!("{section_a}-- name: b\nSELECT 20;\n\n{section_c}"),
+            output.content
+        );
+
+        // A partial write requires a name tagger for identifying the
+        // existing sections
+        match combined_output(&filepath, &files, &queries, None) {
+            Err(Error::Layout(_)) => assert!(true),
+            _ => assert!(false),
+        }
+
+        // When a section is missing in the existing file
+        fs::write(&filepath, "-- name: a\nSELECT 1;\n\n").unwrap();
+        match combined_output(&filepath, &files, &queries, Some(&tagger)) {
+            Err(Error::QueryOutputParsing(msg)) => assert!(msg.starts_with("Query 'c' not found")),
+            _ => assert!(false),
+        }
+
+        // When none of the files are to be written to the path
+        let other = dir.join("other.sql");
+        assert!(combined_output(&other, &files, &queries, Some(&tagger))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let tmp = TempDir::new("write-atomically");
+        let dir = tmp.path();
+        let existing = dir.join("a.sql");
+        fs::write(&existing, "SELECT 1;\n").unwrap();
+
+        // When one of the files can't be written (as it's parent is a
+        // file), none of the files are modified
+        let files = vec![
+            GeneratedFile::new(existing.clone(), "SELECT 2;\n".to_owned()),
+            GeneratedFile::new(existing.join("b.sql"), "SELECT 3;\n".to_owned()),
+        ];
+        assert!(write_atomically(&files).is_err());
+        assert_eq!("SELECT 1;\n", fs::read_to_string(&existing).unwrap());
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+
+        // When all files can be written
+        let files = vec![
+            GeneratedFile::new(existing.clone(), "SELECT 2;\n".to_owned()),
+            GeneratedFile::new(dir.join("sub/b.sql"), "SELECT 3;\n".to_owned()),
+        ];
+        write_atomically(&files).unwrap();
+        assert_eq!("SELECT 2;\n", fs::read_to_string(&existing).unwrap());
+        assert_eq!(
+            "SELECT 3;\n",
+            fs::read_to_string(dir.join("sub/b.sql")).unwrap()
+        );
+        assert_eq!(2, fs::read_dir(&dir).unwrap().count());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_format_outputs() {
+        use crate::formatters::PgFormatter;
+        use crate::util::test_util::stub_executable;
+
+        let tmp = TempDir::new("format-outputs");
+        let dir = tmp.path();
+        // Stub for pg_format that converts the input to uppercase,
+        // either formatting the files in place or stdin
+        let exec = dir.join("pg_format");
+        stub_executable(
+            &exec,
+            r#"n=0
+for f in "$@"; do
+  case "$f" in
+    *.sql) tr a-z A-Z < "$f" > "$f.tmp" && mv "$f.tmp" "$f"; n=1;;
+  esac
+done
+if [ "$n" -eq 0 ]; then tr a-z A-Z; fi"#,
+        );
+        let formatter = Formatter::PgFormatter(PgFormatter::new(exec, None));
+        let batch_dir = dir.join("format");
+        let template = Path::new("templates/q.sql.j2");
+        let unformatted = || {
+            (0..150)
+                .map(|i| Unformatted {
+                    path: PathBuf::from(format!("output/q{i}.sql")),
+                    content: format!("select {i};\n"),
+                    headers: if i % 2 == 0 {
+                        Headers::Whole(GeneratedHeader::new(template, &HashSet::new()))
+                    } else {
+                        Headers::None
+                    },
+                })
+                .collect::<Vec<Unformatted>>()
+        };
+
+        // The formatted outputs are mapped back to the paths and
+        // headers of the corresponding inputs, with the headers added
+        // after formatting
+        let files = format_outputs(Some(&formatter), unformatted(), 4, &batch_dir).unwrap();
+        assert_eq!(150, files.len());
+        let header = GeneratedHeader::new(template, &HashSet::new());
+        for (i, file) in files.iter().enumerate() {
+            assert_eq!(PathBuf::from(format!("output/q{i}.sql")), file.path);
+            let formatted = format!("SELECT {i};\n");
+            if i % 2 == 0 {
+                assert_eq!(header.add(&formatted), file.contents);
+            } else {
+                assert_eq!(formatted, file.contents);
+            }
+        }
+
+        // A single output
+        let mut outputs = unformatted();
+        outputs.truncate(1);
+        let files = format_outputs(Some(&formatter), outputs, 4, &batch_dir).unwrap();
+        assert_eq!(1, files.len());
+        assert_eq!(header.add("SELECT 0;\n"), files[0].contents);
+
+        // Without formatter
+        let files = format_outputs(None, unformatted(), 4, &batch_dir).unwrap();
+        assert_eq!(150, files.len());
+        assert_eq!(header.add("select 0;\n"), files[0].contents);
+        assert_eq!("select 1;\n", files[1].contents);
+    }
 }
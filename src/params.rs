@@ -0,0 +1,328 @@
+use crate::error::{parse_error, Error};
+use crate::export::QueryExport;
+use crate::metadata::Metadata;
+use crate::output::GeneratedFile;
+use crate::query::{slugify_id, Queries, Query};
+use crate::render::Param;
+use crate::tagging::is_name_tag_line;
+use crate::toml::{decode_bool, decode_pathbuf};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+#[derive(Debug, PartialEq)]
+pub enum ParamsFormat {
+    Json,
+    Toml,
+}
+
+impl ParamsFormat {
+    fn decode(value: &Value) -> Result<Self, Error> {
+        match value.as_str() {
+            Some("json") => Ok(Self::Json),
+            Some("toml") => Ok(Self::Toml),
+            Some(s) => Err(parse_error!(
+                "Invalid value for 'query_params.format': '{s}'"
+            )),
+            None => Err(parse_error!(
+                "Value of 'query_params.format' expected to be a string"
+            )),
+        }
+    }
+
+    fn extension(&self) -> &str {
+        match self {
+            Self::Json => "json",
+            Self::Toml => "toml",
+        }
+    }
+
+    fn serialize<T: Serialize>(&self, data: &T) -> Result<String, Error> {
+        let mut s = match self {
+            Self::Json => serde_json::to_string_pretty(data).map_err(Error::Json)?,
+            Self::Toml => toml::to_string(data).map_err(Error::TomlSer)?,
+        };
+        if !s.ends_with('\n') {
+            s.push('\n');
+        }
+        Ok(s)
+    }
+}
+
+/// Parameters of a query as written to the sidecar files
+#[derive(Serialize)]
+pub struct QueryParams<'a> {
+    pub id: &'a str,
+    pub output: &'a Path,
+    pub placeholder: &'a str,
+    pub params: Vec<Param>,
+}
+
+/// Catalog of parameters of all queries i.e. contents of the sidecar
+/// file in case of `one-file-all-queries` layout
+#[derive(Serialize)]
+struct Catalog<'a> {
+    queries: &'a [QueryParams<'a>],
+}
+
+/// Config for exporting the parameters of every rendered query
+/// i.e. names of the parameters in the order in which they are to be
+/// passed along with their placeholders
+#[derive(Debug)]
+pub struct ParamsExport {
+    pub format: ParamsFormat,
+    /// Path of the single sidecar file for all queries. If `None`, a
+    /// separate sidecar file is written for every query in
+    /// `output_dir`.
+    pub output_file: Option<PathBuf>,
+    pub output_dir: PathBuf,
+    /// Whether to add a `-- params:` comment to the query output
+    pub comment: bool,
+}
+
+impl ParamsExport {
+    pub fn decode(value: &Value, queries_output_dir: &Path) -> Result<Self, Error> {
+        let t = value.as_table().ok_or(parse_error!(
+            "Value of 'query_params' expected to be a table"
+        ))?;
+        let format = match t.get("format") {
+            Some(v) => ParamsFormat::decode(v)?,
+            None => ParamsFormat::Json,
+        };
+        let output_file = match t.get("layout").map(|v| v.as_str()) {
+            None | Some(Some("one-file-all-queries")) => {
+                let path = match t.get("output_file") {
+                    Some(v) => {
+                        decode_pathbuf(v, Some(queries_output_dir), "query_params.output_file")?
+                    }
+                    None => queries_output_dir.join(format!("query_params.{}", format.extension())),
+                };
+                Some(path)
+            }
+            Some(Some("one-file-one-query")) => None,
+            Some(Some(s)) => {
+                return Err(parse_error!(
+                    "Invalid value for 'query_params.layout': '{s}'"
+                ))
+            }
+            Some(None) => {
+                return Err(parse_error!(
+                    "Value of 'query_params.layout' expected to be a string"
+                ))
+            }
+        };
+        let comment = match t.get("comment") {
+            Some(v) => decode_bool(v, "query_params.comment")?,
+            None => false,
+        };
+        Ok(Self {
+            format,
+            output_file,
+            output_dir: queries_output_dir.to_path_buf(),
+            comment,
+        })
+    }
+
+    fn query_output_path(&self, query: &Query) -> PathBuf {
+        let filename = format!(
+            "{}.params.{}",
+            slugify_id(&query.id),
+            self.format.extension()
+        );
+        self.output_dir.join(filename)
+    }
+
+    /// Returns paths of all the sidecar files
+    pub fn output_paths(&self, queries: &Queries) -> Vec<PathBuf> {
+        match &self.output_file {
+            Some(p) => vec![p.clone()],
+            None => queries.iter().map(|q| self.query_output_path(q)).collect(),
+        }
+    }
+
+    /// Returns the sidecar files to be written for the params of all
+    /// the queries (in the same order as the queries)
+    pub fn generate(
+        &self,
+        queries: &Queries,
+        params: &[QueryParams],
+    ) -> Result<Vec<GeneratedFile>, Error> {
+        match &self.output_file {
+            Some(p) => {
+                let contents = self.format.serialize(&Catalog { queries: params })?;
+                Ok(vec![GeneratedFile::new(p.clone(), contents)])
+            }
+            None => {
+                let mut result = Vec::with_capacity(params.len());
+                for qp in params {
+                    // @UNWRAP: `params` are expected to be collected
+                    // from the same `queries`
+                    let query = queries.get(qp.id).unwrap();
+                    let contents = self.format.serialize(qp)?;
+                    result.push(GeneratedFile::new(self.query_output_path(query), contents));
+                }
+                Ok(result)
+            }
+        }
+    }
+}
+
+/// Returns the params sidecar files for all queries if configured in
+/// the manifest
+///
+/// `exports` are expected to be of all the queries (refer to
+/// `export::query_exports`).
+pub fn generated_files(
+    metadata: &Metadata,
+    exports: &[(&Query, QueryExport)],
+) -> Result<Vec<GeneratedFile>, Error> {
+    match &metadata.query_params {
+        Some(pe) => {
+            let params = exports
+                .iter()
+                .map(|(query, export)| QueryParams {
+                    id: &query.id,
+                    output: &query.output,
+                    placeholder: metadata.query_placeholder(query).label(),
+                    params: export.params.clone(),
+                })
+                .collect::<Vec<QueryParams>>();
+            pe.generate(&metadata.queries, &params)
+        }
+        None => Ok(vec![]),
+    }
+}
+
+/// Adds a `-- params:` comment listing the names of the parameters
+/// (in order) to the sql
+///
+/// If the sql begins with a name tag, the comment is added right
+/// after it. Otherwise it's added at the beginning so that it ends up
+/// under the name tag that gets added later. Nothing is added if the
+/// query doesn't have any params.
+pub fn add_params_comment<'a>(sql: &'a str, params: &[Param]) -> Cow<'a, str> {
+    if params.is_empty() {
+        return Cow::from(sql);
+    }
+    let names = params
+        .iter()
+        .map(|p| p.name.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ");
+    let comment = format!("-- params: {names}");
+    let sql = sql.trim_start();
+    match sql.split_once('\n') {
+        Some((first, rest)) if is_name_tag_line(first) => {
+            Cow::from(format!("{first}\n{comment}\n{rest}"))
+        }
+        _ => Cow::from(format!("{comment}\n{sql}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn param(name: &str, placeholder: &str) -> Param {
+        Param {
+            name: name.to_owned(),
+            placeholder: placeholder.to_owned(),
+            sql_type: None,
+        }
+    }
+
+    #[test]
+    fn test_add_params_comment() {
+        let params = vec![
+            param("genre", "?"),
+            param("limit", "?"),
+            param("genre", "?"),
+        ];
+        let sql = "SELECT * FROM songs WHERE genre = ? LIMIT ?;";
+        assert_eq!(
+            "-- params: genre, limit, genre\nSELECT * FROM songs WHERE genre = ? LIMIT ?;",
+            add_params_comment(sql, &params)
+        );
+
+        // When the sql begins with a name tag
+        let sql = "\n-- name: songs\nSELECT * FROM songs WHERE genre = ? LIMIT ?;";
+        assert_eq!(
+            "-- name: songs\n-- params: genre, limit, genre\nSELECT * FROM songs WHERE genre = ? LIMIT ?;",
+            add_params_comment(sql, &params)
+        );
+
+        // When there are no params
+        let sql = "SELECT * FROM songs;";
+        assert_eq!(sql, add_params_comment(sql, &[]));
+    }
+
+    #[test]
+    fn test_params_export() {
+        let table = r#"
+format = 'toml'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let pe = ParamsExport::decode(&Value::Table(table), Path::new("output/queries")).unwrap();
+        assert_eq!(
+            Some(PathBuf::from("output/queries/query_params.toml")),
+            pe.output_file
+        );
+        let qps = vec![QueryParams {
+            id: "artists_long_songs@genre*limit",
+            output: Path::new("output/queries/artists_long_songs-genre-limit.sql"),
+            placeholder: "posargs",
+            params: vec![
+                param("genre", "$1"),
+                Param {
+                    sql_type: Some("int".to_owned()),
+                    ..param("limit", "$2")
+                },
+            ],
+        }];
+        let expected = r#"[[queries]]
+id = "artists_long_songs@genre*limit"
+output = "output/queries/artists_long_songs-genre-limit.sql"
+placeholder = "posargs"
+
+[[queries.params]]
+name = "genre"
+placeholder = "$1"
+
+[[queries.params]]
+name = "limit"
+placeholder = "$2"
+type = "int"
+"#;
+        assert_eq!(
+            expected,
+            pe.format.serialize(&Catalog { queries: &qps }).unwrap()
+        );
+
+        let table = r#"
+layout = 'one-file-one-query'
+comment = true
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let pe = ParamsExport::decode(&Value::Table(table), Path::new("output/queries")).unwrap();
+        assert!(pe.output_file.is_none());
+        assert!(pe.comment);
+        assert_eq!(ParamsFormat::Json, pe.format);
+
+        let table = r#"
+layout = 'one-file-per-query'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        match ParamsExport::decode(&Value::Table(table), Path::new("output/queries")) {
+            Err(Error::Parsing(msg)) => assert_eq!(
+                "Invalid value for 'query_params.layout': 'one-file-per-query'",
+                msg
+            ),
+            _ => assert!(false),
+        }
+    }
+}
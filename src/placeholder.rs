@@ -4,36 +4,79 @@ use toml::Value;
 
 #[derive(Debug)]
 pub enum Placeholder {
+    /// `$1`, `$2` etc.
     PosArgs,
+    /// `:name`
     Variables,
+    /// `?` (anonymous)
+    QMark,
+    /// `%s` (anonymous)
+    Format,
+    /// `%(name)s`
+    PyFormat,
+    /// `@name`
+    AtVariables,
 }
 
 impl TryFrom<&Value> for Placeholder {
     type Error = Error;
 
     fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        Self::decode(value, "placeholder")
+    }
+}
+
+impl Placeholder {
+    /// Tries decoding a toml `Value` into a `Placeholder`
+    ///
+    /// The second arg `key` will be used in the error message in case
+    /// decoding fails.
+    pub fn decode(value: &Value, key: &str) -> Result<Self, Error> {
         match value.as_str() {
             Some(s) => {
                 if s == "posargs" {
                     Ok(Self::PosArgs)
                 } else if s == "variables" {
                     Ok(Self::Variables)
+                } else if s == "qmark" {
+                    Ok(Self::QMark)
+                } else if s == "format" {
+                    Ok(Self::Format)
+                } else if s == "pyformat" {
+                    Ok(Self::PyFormat)
+                } else if s == "at-variables" {
+                    Ok(Self::AtVariables)
                 } else {
-                    Err(parse_error!("Invalid placeholder: '{}", s))
+                    Err(parse_error!("Invalid value for '{}': '{}'", key, s))
                 }
             }
-            None => Err(parse_error!("Value of key 'placeholder' must be a string")),
+            None => Err(parse_error!("Value of key '{}' must be a string", key)),
         }
     }
-}
 
-impl Placeholder {
     pub fn label(&self) -> &str {
         match self {
             Self::PosArgs => "posargs",
             Self::Variables => "variables",
+            Self::QMark => "qmark",
+            Self::Format => "format",
+            Self::PyFormat => "pyformat",
+            Self::AtVariables => "at-variables",
         }
     }
+
+    /// Returns true if the placeholders don't refer to the parameters
+    /// by name or position, in which case the parameter needs to be
+    /// passed once per occurrence in the query
+    pub fn is_anonymous(&self) -> bool {
+        matches!(self, Self::QMark | Self::Format)
+    }
+
+    /// Returns true if a literal `%` in the query needs to be escaped
+    /// as `%%` (as required by python db drivers e.g. psycopg)
+    pub fn escapes_percent(&self) -> bool {
+        matches!(self, Self::Format | Self::PyFormat)
+    }
 }
 
 #[cfg(test)]
@@ -60,8 +103,24 @@ mod tests {
             _ => assert!(false),
         }
 
+        let t = "placeholder = 'pyformat'".parse::<Table>().unwrap();
+        let p = Placeholder::try_from(&t["placeholder"]);
+        assert!(p.is_ok());
+        match p.unwrap() {
+            Placeholder::PyFormat => assert!(true),
+            _ => assert!(false),
+        }
+
         let t = "placeholder = 'question-marks'".parse::<Table>().unwrap();
         let p = Placeholder::try_from(&t["placeholder"]);
         assert!(p.is_err());
+
+        let t = "placeholder = 'dollar'".parse::<Table>().unwrap();
+        match Placeholder::decode(&t["placeholder"], "queries[].placeholder") {
+            Err(Error::Parsing(msg)) => {
+                assert_eq!("Invalid value for 'queries[].placeholder': 'dollar'", msg)
+            }
+            _ => assert!(false),
+        }
     }
 }
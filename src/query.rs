@@ -1,17 +1,18 @@
 use crate::error::{parse_error, Error};
-use crate::output::Layout;
+use crate::output::{Layout, OutputPattern};
+use crate::placeholder::Placeholder;
 use crate::query_template::QueryTemplates;
-use crate::tagging::NameTag;
+use crate::tagging::{NameTag, NameTagger, QueryResult};
 use crate::toml::{decode_pathbuf, decode_string, decode_strset};
 use crate::validation::ManifestMistake;
 use regex::Regex;
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::Arc;
 use toml::Value;
 
-fn slugify_id(id: &str) -> Cow<'_, str> {
+pub fn slugify_id(id: &str) -> Cow<'_, str> {
     let re = Regex::new(r"@|\+|&|\*").unwrap();
     re.replace_all(id, "-")
 }
@@ -22,31 +23,66 @@ fn id_to_output(id: &str, base_dir: &Path) -> PathBuf {
     base_dir.join(filepath)
 }
 
+/// Returns file name of the template without the extension(s)
+/// e.g. `artists_long_songs` for `artists_long_songs.sql.j2`
+///
+/// # Panics
+///
+/// This fn assumes that the template path is valid unicode and will
+/// panic if that's not the case.
+pub fn template_stem(template: &Path) -> &str {
+    let file_name = template
+        .file_name()
+        .map(|ostr| ostr.to_str().unwrap())
+        .unwrap_or("");
+    file_name.split('.').next().unwrap_or(file_name)
+}
+
 /// Computes the fallback value for 'output' field when one is not
 /// explicitly specified.
 ///
 /// The fallback value depends on the kind of `output_layout`.
 ///
-///   - OneFileOneQuery: derive output file name from `id` field
+///   - OneFileOneQuery: derive output file name from `id` field, or
+///     as per the `pattern` (i.e. `query_output_pattern` in the
+///     manifest) if specified
 ///   - OneFileAllQueries: use the value associated with the enum
 ///     i.e. `query_output_file` set in manifest.
+///   - OneFilePerTemplate: derive output file name from the file
+///     name of the `template`
+///   - Grouped: derive output file name from the `group` if
+///     specified, otherwise from the file name of the `template`
 ///
 /// # Error:
 /// Returns `Error::Parsing` if output_layout = `OneFileAllQueries'
 /// and `query_output_file` key is not set in the manifest
 fn fallback_output<P: AsRef<Path>>(
     id: &str,
+    template: &Path,
+    conds: &HashSet<String>,
+    group: Option<&str>,
     base_dir: P,
     output_layout: &Layout,
+    pattern: Option<&OutputPattern>,
 ) -> Result<PathBuf, Error> {
     match output_layout {
-        Layout::OneFileOneQuery => Ok(id_to_output(id, base_dir.as_ref())),
+        Layout::OneFileOneQuery => match pattern {
+            Some(p) => Ok(base_dir.as_ref().join(p.expand(id, template, conds))),
+            None => Ok(id_to_output(id, base_dir.as_ref())),
+        },
         Layout::OneFileAllQueries(output_file) => match output_file {
             Some(f) => Ok(f.to_path_buf()),
             None => Err(parse_error!(
                 "Either 'queries[].output' or 'query_output_file' must be specified in case of 'one-file-all-queries' layout"
             )),
         },
+        Layout::OneFilePerTemplate => {
+            Ok(base_dir.as_ref().join(format!("{}.sql", template_stem(template))))
+        }
+        Layout::Grouped => {
+            let group = group.unwrap_or_else(|| template_stem(template));
+            Ok(base_dir.as_ref().join(format!("{group}.sql")))
+        }
     }
 }
 
@@ -57,6 +93,19 @@ pub struct Query {
     pub conds: HashSet<String>,
     pub output: PathBuf,
     pub name_tag: NameTag,
+    /// Overrides the placeholder style of the query template and the
+    /// one set globally in the manifest
+    pub placeholder: Option<Placeholder>,
+    /// Kind of result returned by the query, used for rendering the
+    /// name tag (overrides the one set for the query template)
+    pub result: Option<QueryResult>,
+    /// Docstring of the query (jinja template). Overrides the one
+    /// set for the query template
+    pub description: Option<String>,
+    /// Label for grouping queries into a common output file in case
+    /// of `grouped` layout. Falls back to the `group` set for the
+    /// query template
+    pub group: Option<String>,
 }
 
 impl Query {
@@ -64,6 +113,9 @@ impl Query {
         templates_base_dir: P,
         output_base_dir: P,
         output_layout: &Layout,
+        output_pattern: Option<&OutputPattern>,
+        name_tagger: Option<&NameTagger>,
+        query_templates: &QueryTemplates,
         value: &Value,
     ) -> Result<Self, Error> {
         match value.as_table() {
@@ -82,6 +134,12 @@ impl Query {
                     Some(v) => decode_strset(v, "queries[].conds")?,
                     None => HashSet::new(),
                 };
+                let group = match t.get("group") {
+                    Some(v) => Some(decode_string(v, "queries[].group")?),
+                    None => query_templates
+                        .get(&template)
+                        .and_then(|qt| qt.group.clone()),
+                };
                 let output = match t.get("output") {
                     Some(v) => {
                         // @NOTE: When `output` is specified, it's
@@ -95,18 +153,42 @@ impl Query {
                         // discrepancy but validation will catch it.
                         decode_pathbuf(v, Some(output_base_dir.as_ref()), "queries[].output")?
                     }
-                    None => fallback_output(&id, output_base_dir.as_ref(), output_layout)?,
+                    None => fallback_output(
+                        &id,
+                        &template,
+                        &conds,
+                        group.as_deref(),
+                        output_base_dir.as_ref(),
+                        output_layout,
+                        output_pattern,
+                    )?,
                 };
                 let name_tag = match t.get("name_tag") {
                     Some(v) => NameTag::Custom(decode_string(v, "queries[].name_tag")?),
-                    None => NameTag::DeriveFromId(id.clone()),
+                    None => NameTag::derive(&id, &template, &conds, name_tagger)?,
                 };
+                let placeholder = t
+                    .get("placeholder")
+                    .map(|v| Placeholder::decode(v, "queries[].placeholder"))
+                    .transpose()?;
+                let result = t
+                    .get("result")
+                    .map(|v| QueryResult::decode(v, "queries[].result"))
+                    .transpose()?;
+                let description = t
+                    .get("description")
+                    .map(|v| decode_string(v, "queries[].description"))
+                    .transpose()?;
                 Ok(Self {
                     id,
                     template,
                     conds,
                     output,
                     name_tag,
+                    placeholder,
+                    result,
+                    description,
+                    group,
                 })
             }
             None => Err(parse_error!("Invalid 'query' entry")),
@@ -128,6 +210,14 @@ impl Query {
                         conds: diff,
                     })
                 }
+                let mut conds = self.conds.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+                conds.sort();
+                for violation in qt.conds_violations(&conds) {
+                    mistakes.push(ManifestMistake::CondsConstraintViolated {
+                        query_id: &self.id,
+                        violation,
+                    })
+                }
             }
             None => mistakes.push(ManifestMistake::QueryTemplateRefNotFound {
                 query_id: &self.id,
@@ -157,14 +247,14 @@ impl Query {
 
 #[derive(Debug)]
 pub struct Queries {
-    inner: Vec<Rc<Query>>,
-    index: HashMap<String, Rc<Query>>,
+    inner: Vec<Arc<Query>>,
+    index: HashMap<String, Arc<Query>>,
 }
 
 impl Queries {
     pub fn new() -> Self {
-        let inner: Vec<Rc<Query>> = vec![];
-        let index: HashMap<String, Rc<Query>> = HashMap::new();
+        let inner: Vec<Arc<Query>> = vec![];
+        let index: HashMap<String, Arc<Query>> = HashMap::new();
         Self { inner, index }
     }
 
@@ -172,6 +262,9 @@ impl Queries {
         templates_base_dir: P,
         output_base_dir: P,
         output_layout: &Layout,
+        output_pattern: Option<&OutputPattern>,
+        name_tagger: Option<&NameTagger>,
+        query_templates: &QueryTemplates,
         value: &Value,
     ) -> Result<Self, Error> {
         // @NOTE: The index is populated at the time of initialization
@@ -179,15 +272,18 @@ impl Queries {
         // would be populating the index at the time of lookup (like a
         // read-through cache) but in that case we'd need to manage
         // multiple mutable references.
-        let mut index: HashMap<String, Rc<Query>> = HashMap::new();
+        let mut index: HashMap<String, Arc<Query>> = HashMap::new();
         let items = match value.as_array() {
             Some(xs) => {
                 let mut res = Vec::with_capacity(xs.len());
                 for x in xs {
-                    let q = Rc::new(Query::decode(
+                    let q = Arc::new(Query::decode(
                         &templates_base_dir,
                         &output_base_dir,
                         output_layout,
+                        output_pattern,
+                        name_tagger,
+                        query_templates,
                         x,
                     )?);
                     let idx_key = q.id.clone();
@@ -205,6 +301,69 @@ impl Queries {
         })
     }
 
+    /// Generates queries for the query templates that opt into
+    /// expansion of cond combinations
+    ///
+    /// The generated queries are appended after the ones defined in
+    /// the manifest. If a query for the same template and the same
+    /// set of conds is already defined in the manifest, the
+    /// combination is skipped. This allows overriding the `id`,
+    /// `output` etc. of specific combinations.
+    pub fn expand<P: AsRef<Path>>(
+        &mut self,
+        query_templates: &QueryTemplates,
+        output_base_dir: P,
+        output_layout: &Layout,
+        output_pattern: Option<&OutputPattern>,
+        name_tagger: Option<&NameTagger>,
+    ) -> Result<(), Error> {
+        for qt in query_templates.iter() {
+            for conds in qt.expanded_conds() {
+                let conds = conds
+                    .into_iter()
+                    .map(String::from)
+                    .collect::<HashSet<String>>();
+                let defined = self
+                    .inner
+                    .iter()
+                    .any(|q| q.template == qt.path && q.conds == conds);
+                if defined {
+                    continue;
+                }
+                let mut sorted_conds = conds.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+                sorted_conds.sort();
+                let id = qt.expanded_id(&sorted_conds);
+                let output = fallback_output(
+                    &id,
+                    &qt.path,
+                    &conds,
+                    qt.group.as_deref(),
+                    output_base_dir.as_ref(),
+                    output_layout,
+                    output_pattern,
+                )?;
+                let name_tag = NameTag::derive(&id, &qt.path, &conds, name_tagger)?;
+                let q = Arc::new(Query {
+                    id: id.clone(),
+                    template: qt.path.clone(),
+                    conds,
+                    output,
+                    name_tag,
+                    placeholder: None,
+                    result: None,
+                    description: None,
+                    group: qt.group.clone(),
+                });
+                // @NOTE: In case of conflicting ids, the index entry
+                // of the query defined earlier is retained. The
+                // conflict will be reported upon validation.
+                self.index.entry(id).or_insert_with(|| q.clone());
+                self.inner.push(q);
+            }
+        }
+        Ok(())
+    }
+
     pub fn validate<'a>(
         &'a self,
         query_templates: &'a QueryTemplates,
@@ -273,6 +432,55 @@ impl Queries {
                     }
                 }
             }
+            Layout::OneFilePerTemplate | Layout::Grouped => {
+                // Validate that all queries in an output group
+                // (i.e. the queries with the same query template or
+                // the same `group` label based on the layout) have
+                // the same value for `output` and that it's not
+                // shared with any other group
+                let mut groups: Vec<&str> = vec![];
+                let mut group_outputs: HashMap<&str, Vec<&Path>> = HashMap::new();
+                for query in &self.inner {
+                    let group = match output_layout {
+                        Layout::Grouped => query
+                            .group
+                            .as_deref()
+                            .unwrap_or_else(|| template_stem(&query.template)),
+                        _ => query.template.to_str().unwrap(),
+                    };
+                    let outputs = group_outputs.entry(group).or_insert_with(|| {
+                        groups.push(group);
+                        vec![]
+                    });
+                    if !outputs.contains(&query.output.as_path()) {
+                        outputs.push(&query.output);
+                    }
+                }
+                let mut output_groups: HashMap<&Path, usize> = HashMap::new();
+                for group in groups {
+                    // @UNWRAP: All groups are inserted in the map
+                    // above
+                    let outputs = group_outputs.remove(group).unwrap();
+                    for output in outputs.iter() {
+                        output_groups
+                            .entry(output)
+                            .and_modify(|c| *c += 1)
+                            .or_insert(1);
+                    }
+                    if outputs.len() > 1 {
+                        mistakes.push(ManifestMistake::DisparateGroupOutputs { group, outputs })
+                    }
+                }
+                for (key, val) in output_groups.iter() {
+                    if val > &1 {
+                        let m = ManifestMistake::Duplicates {
+                            key: "queries[].output",
+                            value: key.to_str().unwrap(),
+                        };
+                        mistakes.push(m)
+                    }
+                }
+            }
         }
         mistakes
     }
@@ -281,11 +489,21 @@ impl Queries {
         self.inner.iter().map(|q| q.output.as_path())
     }
 
-    pub fn get(&self, id: &str) -> Option<&Rc<Query>> {
+    /// Returns the queries (in order) that are written to the output
+    /// file `path`
+    pub fn by_output(&self, path: &Path) -> Vec<&Query> {
+        self.inner
+            .iter()
+            .filter(|q| q.output == path)
+            .map(|q| q.as_ref())
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Arc<Query>> {
         self.index.get(id)
     }
 
-    pub fn iter(&self) -> std::slice::Iter<'_, Rc<Query>> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Arc<Query>> {
         self.inner.iter()
     }
 
@@ -295,7 +513,7 @@ impl Queries {
 }
 
 #[cfg(test)]
-mod test_util {
+pub mod test_util {
 
     use super::*;
 
@@ -316,12 +534,16 @@ mod test_util {
                 || NameTag::DeriveFromId(String::from(id)),
                 |s| NameTag::Custom(String::from(s)),
             );
-            let q = Rc::new(Query {
+            let q = Arc::new(Query {
                 id: String::from(id),
                 template: PathBuf::from(template),
                 conds: strset(conds),
                 output: PathBuf::from(output),
                 name_tag,
+                placeholder: None,
+                result: None,
+                description: None,
+                group: None,
             });
             let idx_key = q.id.clone();
             let idx_val = q.clone();
@@ -338,6 +560,7 @@ mod tests {
     use super::test_util::*;
     use super::*;
     use crate::query_template;
+    use crate::validation::CondsViolation;
     use toml;
 
     #[test]
@@ -352,12 +575,78 @@ output = 'my_query_explicit.sql'
         .parse::<toml::Table>()
         .unwrap();
         let value = toml::Value::Table(table);
-        match Query::decode("base", "output", &Layout::OneFileOneQuery, &value) {
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
             Ok(q) => {
                 assert_eq!("my_query", q.id);
                 assert_eq!(PathBuf::from("base/my_query_template.sql.j2"), q.template);
                 assert_eq!(PathBuf::from("output/my_query_explicit.sql"), q.output);
                 assert_eq!(strset(vec!["foo"]), q.conds);
+                assert_eq!(None, q.description);
+            }
+            Err(_) => assert!(false),
+        }
+
+        // When description is specified
+        let table = r#"
+id = 'my_query'
+template = 'my_query_template.sql.j2'
+conds = [ 'foo' ]
+description = """
+Returns songs filtered by foo
+(limited to {{ cond__limit }})
+"""
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
+            Ok(q) => assert_eq!(
+                Some("Returns songs filtered by foo\n(limited to {{ cond__limit }})\n"),
+                q.description.as_deref()
+            ),
+            Err(_) => assert!(false),
+        }
+
+        // When description is not a string
+        let table = r#"
+id = 'my_query'
+template = 'my_query_template.sql.j2'
+description = 1
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
+            Ok(_) => assert!(false),
+            Err(Error::Parsing(msg)) => {
+                assert_eq!(
+                    "Value of 'queries[].description' expected to be a string",
+                    msg
+                )
             }
             Err(_) => assert!(false),
         }
@@ -371,7 +660,15 @@ output = 'my_query_explicit.sql'
         .parse::<toml::Table>()
         .unwrap();
         let value = toml::Value::Table(table);
-        match Query::decode("base", "output", &Layout::OneFileOneQuery, &value) {
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
             Ok(q) => {
                 assert_eq!("my_query", q.id);
                 assert_eq!(PathBuf::from("base/my_query_template.sql.j2"), q.template);
@@ -390,7 +687,15 @@ conds = [ 'foo' ]
         .parse::<toml::Table>()
         .unwrap();
         let value = toml::Value::Table(table);
-        match Query::decode("base", "output", &Layout::OneFileOneQuery, &value) {
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
             Ok(q) => {
                 assert_eq!("my_query", q.id);
                 assert_eq!(PathBuf::from("base/my_query_template.sql.j2"), q.template);
@@ -409,7 +714,15 @@ output = 'my_query_explicit.sql'
         .parse::<toml::Table>()
         .unwrap();
         let value = toml::Value::Table(table);
-        match Query::decode("base", "output", &Layout::OneFileOneQuery, &value) {
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
             Ok(_) => assert!(false),
             Err(Error::Parsing(msg)) => {
                 assert_eq!("Missing 'id' in 'query' entry", msg);
@@ -426,7 +739,15 @@ output = 'my_query_explicit.sql'
         .parse::<toml::Table>()
         .unwrap();
         let value = toml::Value::Table(table);
-        match Query::decode("base", "output", &Layout::OneFileOneQuery, &value) {
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
             Ok(_) => assert!(false),
             Err(Error::Parsing(msg)) => {
                 assert_eq!("Missing 'template' in 'query' entry", msg);
@@ -434,6 +755,55 @@ output = 'my_query_explicit.sql'
             Err(_) => assert!(false),
         }
 
+        // When placeholder is specified
+        let table = r#"
+id = 'my_query'
+template = 'my_query_template.sql.j2'
+placeholder = 'qmark'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
+            Ok(q) => match q.placeholder {
+                Some(Placeholder::QMark) => assert!(true),
+                _ => assert!(false),
+            },
+            Err(_) => assert!(false),
+        }
+
+        // When placeholder is invalid
+        let table = r#"
+id = 'my_query'
+template = 'my_query_template.sql.j2'
+placeholder = 'dollar'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
+            Err(Error::Parsing(msg)) => {
+                assert_eq!("Invalid value for 'queries[].placeholder': 'dollar'", msg);
+            }
+            _ => assert!(false),
+        }
+
         // When `all_conds` is not of correct type
         let table = r#"
 id = 'my_query'
@@ -444,7 +814,15 @@ output = 'my_query_explicit.sql'
         .parse::<toml::Table>()
         .unwrap();
         let value = toml::Value::Table(table);
-        match Query::decode("base", "output", &Layout::OneFileOneQuery, &value) {
+        match Query::decode(
+            "base",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &QueryTemplates::new(),
+            &value,
+        ) {
             Ok(_) => assert!(false),
             Err(Error::Parsing(msg)) => {
                 assert_eq!(
@@ -456,18 +834,146 @@ output = 'my_query_explicit.sql'
         }
     }
 
+    #[test]
+    fn test_queries_validate_conds_constraints() {
+        let table = r#"
+[[query_templates]]
+path = 'artists.sql.j2'
+all_conds = [ 'order', 'limit' ]
+requires = { limit = [ 'order' ] }
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let qts = QueryTemplates::decode("base", table.get("query_templates").unwrap()).unwrap();
+        let qs = setup_queries(vec![
+            (
+                "artists@order+limit",
+                "base/artists.sql.j2",
+                vec!["order", "limit"],
+                "output/artists-order-limit.sql",
+                None,
+            ),
+            (
+                "artists@limit",
+                "base/artists.sql.j2",
+                vec!["limit"],
+                "output/artists-limit.sql",
+                None,
+            ),
+        ]);
+        let mistakes = qs.validate(&qts, &Layout::OneFileOneQuery);
+        assert_eq!(1, mistakes.len());
+        match &mistakes[0] {
+            ManifestMistake::CondsConstraintViolated {
+                query_id,
+                violation: CondsViolation::Requires { cond, missing },
+            } => {
+                assert_eq!("artists@limit", *query_id);
+                assert_eq!("limit", *cond);
+                assert_eq!(vec!["order"], *missing);
+            }
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_queries_expand() {
+        let table = r#"
+[[query_templates]]
+path = 'artists_long_songs.sql.j2'
+all_conds = [ 'genre', 'limit' ]
+expand = 'all'
+
+[[query_templates]]
+path = 'songs_formats.sql.j2'
+all_conds = [ 'artist', 'album_name' ]
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let qts = QueryTemplates::decode("base", table.get("query_templates").unwrap()).unwrap();
+        let mut qs = setup_queries(vec![
+            (
+                "artists_long_songs*limit",
+                "base/artists_long_songs.sql.j2",
+                vec!["limit"],
+                "output/artists_long_songs-limit.sql",
+                None,
+            ),
+            (
+                "songs_formats@artist",
+                "base/songs_formats.sql.j2",
+                vec!["artist"],
+                "output/songs_formats-artist.sql",
+                None,
+            ),
+        ]);
+        qs.expand(&qts, "output", &Layout::OneFileOneQuery, None, None)
+            .unwrap();
+        let ids = qs.iter().map(|q| q.id.as_str()).collect::<Vec<&str>>();
+        // Combination with conds = ["limit"] is already defined
+        // explicitly hence skipped
+        assert_eq!(
+            vec![
+                "artists_long_songs*limit",
+                "songs_formats@artist",
+                "artists_long_songs",
+                "artists_long_songs@genre",
+                "artists_long_songs@genre+limit",
+            ],
+            ids
+        );
+        let q = qs.get("artists_long_songs@genre+limit").unwrap();
+        assert_eq!(strset(vec!["genre", "limit"]), q.conds);
+        assert_eq!(PathBuf::from("base/artists_long_songs.sql.j2"), q.template);
+        assert_eq!(
+            PathBuf::from("output/artists_long_songs-genre-limit.sql"),
+            q.output
+        );
+        assert!(qs.validate(&qts, &Layout::OneFileOneQuery).is_empty());
+    }
+
     #[test]
     fn test_fallback_output() {
         // When output_layout = OneFileOneQuery
-        match fallback_output("foo", "base", &Layout::OneFileOneQuery) {
+        let template = Path::new("base/foo_template.sql.j2");
+        let conds = HashSet::new();
+        match fallback_output(
+            "foo",
+            template,
+            &conds,
+            None,
+            "base",
+            &Layout::OneFileOneQuery,
+            None,
+        ) {
             Ok(p) => assert_eq!(PathBuf::from("base/foo.sql"), p),
             Err(_) => assert!(false),
         }
 
+        // When output_layout = OneFileOneQuery and a pattern is
+        // specified
+        let pattern = OutputPattern::decode(
+            &Value::String("{template_stem}/{slug}.sql".to_owned()),
+            "query_output_pattern",
+        )
+        .unwrap();
+        match fallback_output(
+            "foo@bar",
+            template,
+            &conds,
+            None,
+            "base",
+            &Layout::OneFileOneQuery,
+            Some(&pattern),
+        ) {
+            Ok(p) => assert_eq!(PathBuf::from("base/foo_template/foo-bar.sql"), p),
+            Err(_) => assert!(false),
+        }
+
         // When output layout = OneFileAllQueries and output_file is
         // specified
         let layout = Layout::OneFileAllQueries(Some(PathBuf::from("base/queries.sql")));
-        match fallback_output("foo", "base", &layout) {
+        match fallback_output("foo", template, &conds, None, "base", &layout, None) {
             Ok(p) => assert_eq!(PathBuf::from("base/queries.sql"), p),
             Err(_) => assert!(false),
         }
@@ -475,12 +981,182 @@ output = 'my_query_explicit.sql'
         // When output layout = OneFileAllQueries and output_file is
         // not specified
         let layout = Layout::OneFileAllQueries(None);
-        match fallback_output("foo", "base", &layout) {
+        match fallback_output("foo", template, &conds, None, "base", &layout, None) {
             Err(Error::Parsing(msg)) => {
                 assert_eq!("Either 'queries[].output' or 'query_output_file' must be specified in case of 'one-file-all-queries' layout", msg);
             }
             _ => assert!(false),
         }
+
+        // When output layout = OneFilePerTemplate
+        match fallback_output(
+            "foo",
+            template,
+            &conds,
+            Some("bar"),
+            "base",
+            &Layout::OneFilePerTemplate,
+            None,
+        ) {
+            Ok(p) => assert_eq!(PathBuf::from("base/foo_template.sql"), p),
+            Err(_) => assert!(false),
+        }
+
+        // When output layout = Grouped, the group takes precedence
+        // over the template
+        match fallback_output(
+            "foo",
+            template,
+            &conds,
+            Some("bar"),
+            "base",
+            &Layout::Grouped,
+            None,
+        ) {
+            Ok(p) => assert_eq!(PathBuf::from("base/bar.sql"), p),
+            Err(_) => assert!(false),
+        }
+        match fallback_output(
+            "foo",
+            template,
+            &conds,
+            None,
+            "base",
+            &Layout::Grouped,
+            None,
+        ) {
+            Ok(p) => assert_eq!(PathBuf::from("base/foo_template.sql"), p),
+            Err(_) => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_queries_validate_grouped() {
+        let decode_qts = |s: &str| {
+            let table = s.parse::<toml::Table>().unwrap();
+            QueryTemplates::decode("templates/queries", table.get("query_templates").unwrap())
+                .unwrap()
+        };
+        let decode_qs = |s: &str, layout: &Layout, qts: &QueryTemplates| {
+            let table = s.parse::<toml::Table>().unwrap();
+            Queries::decode(
+                "templates/queries",
+                "output",
+                layout,
+                None,
+                None,
+                qts,
+                table.get("queries").unwrap(),
+            )
+            .unwrap()
+        };
+
+        let qts = decode_qts(
+            r#"
+[[query_templates]]
+path = 'artists_long_songs.sql.j2'
+all_conds = [ 'genre' ]
+
+[[query_templates]]
+path = 'songs_formats.sql.j2'
+all_conds = [ 'artist' ]
+group = 'songs'
+expand = [ [ 'artist' ] ]
+"#,
+        );
+        let manifest = r#"
+[[queries]]
+id = 'artists_long_songs'
+template = 'artists_long_songs.sql.j2'
+
+[[queries]]
+id = 'artists_long_songs@genre'
+template = 'artists_long_songs.sql.j2'
+conds = [ 'genre' ]
+
+[[queries]]
+id = 'songs_formats'
+template = 'songs_formats.sql.j2'
+"#;
+
+        // The group of the query template applies to the queries
+        // defined in the manifest as well as the expanded ones
+        let mut qs = decode_qs(manifest, &Layout::Grouped, &qts);
+        qs.expand(&qts, "output", &Layout::Grouped, None, None)
+            .unwrap();
+        for (id, group, output) in [
+            ("artists_long_songs", None, "output/artists_long_songs.sql"),
+            ("songs_formats", Some("songs"), "output/songs.sql"),
+            ("songs_formats@artist", Some("songs"), "output/songs.sql"),
+        ] {
+            let q = qs.get(id).unwrap();
+            assert_eq!(group, q.group.as_deref());
+            assert_eq!(PathBuf::from(output), q.output);
+        }
+        assert!(qs.validate(&qts, &Layout::Grouped).is_empty());
+
+        let qs = decode_qs(manifest, &Layout::OneFilePerTemplate, &qts);
+        assert!(qs.validate(&qts, &Layout::OneFilePerTemplate).is_empty());
+
+        // When an output is shared by multiple groups
+        let qs = decode_qs(
+            r#"
+[[queries]]
+id = 'artists_long_songs'
+template = 'artists_long_songs.sql.j2'
+
+[[queries]]
+id = 'songs_formats'
+template = 'songs_formats.sql.j2'
+output = 'artists_long_songs.sql'
+"#,
+            &Layout::OneFilePerTemplate,
+            &qts,
+        );
+        let mistakes = qs.validate(&qts, &Layout::OneFilePerTemplate);
+        assert_eq!(1, mistakes.len());
+        match mistakes[0] {
+            ManifestMistake::Duplicates { key, value } => {
+                assert_eq!("queries[].output", key);
+                assert_eq!("output/artists_long_songs.sql", value);
+            }
+            _ => assert!(false),
+        }
+
+        // When queries of the same group have disparate outputs. The
+        // group of the query overrides the one set for the query
+        // template
+        let qs = decode_qs(
+            r#"
+[[queries]]
+id = 'artists_long_songs@genre'
+template = 'artists_long_songs.sql.j2'
+conds = [ 'genre' ]
+group = 'songs'
+
+[[queries]]
+id = 'songs_formats'
+template = 'songs_formats.sql.j2'
+output = 'songs_formats.sql'
+"#,
+            &Layout::Grouped,
+            &qts,
+        );
+        let mistakes = qs.validate(&qts, &Layout::Grouped);
+        assert_eq!(1, mistakes.len());
+        match &mistakes[0] {
+            ManifestMistake::DisparateGroupOutputs { group, outputs } => {
+                assert_eq!("songs", *group);
+                assert_eq!(
+                    vec![
+                        Path::new("output/songs.sql"),
+                        Path::new("output/songs_formats.sql")
+                    ],
+                    *outputs
+                );
+            }
+            _ => assert!(false),
+        }
     }
 
     #[test]
@@ -1,15 +1,168 @@
 use crate::error::{parse_error, Error};
-use crate::toml::{decode_pathbuf, decode_strset};
-use crate::validation::{validate_path, ManifestMistake};
-use std::collections::{HashMap, HashSet};
+use crate::placeholder::Placeholder;
+use crate::query::template_stem;
+use crate::tagging::QueryResult;
+use crate::toml::{decode_pathbuf, decode_string, decode_strset};
+use crate::validation::{validate_path, CondsViolation, ManifestMistake};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::Arc;
 use toml::Value;
 
+/// Max no. of conds that a query template may have in case of
+/// `expand = "all"` as the no. of combinations doubles with every
+/// cond
+pub const MAX_EXPAND_ALL_CONDS: usize = 10;
+
+/// Combinations of conds for which queries are to be generated
+#[derive(Debug, PartialEq)]
+pub enum Combinations {
+    /// Every subset of `all_conds` including the empty set (see
+    /// `MAX_EXPAND_ALL_CONDS`)
+    All,
+    /// Only the specified subsets
+    Subsets(Vec<BTreeSet<String>>),
+}
+
+impl Combinations {
+    fn decode(value: &Value) -> Result<Self, Error> {
+        let key = "query_templates[].expand";
+        match value {
+            Value::String(s) if s == "all" => Ok(Self::All),
+            Value::Array(xs) => {
+                let mut subsets = Vec::with_capacity(xs.len());
+                for x in xs {
+                    let conds = decode_strset(x, key)?;
+                    subsets.push(conds.into_iter().collect());
+                }
+                Ok(Self::Subsets(subsets))
+            }
+            _ => Err(parse_error!(
+                "Value of '{}' is expected to be either \"all\" or an array of arrays of strings",
+                key
+            )),
+        }
+    }
+}
+
+/// Config for automatically generating queries from a query
+/// template, one for every combination of conds
+#[derive(Debug)]
+pub struct Expansion {
+    pub combinations: Combinations,
+    /// Pattern for the id of the generated queries. The `{base}` and
+    /// `{conds}` placeholders are replaced with the base name of the
+    /// template file and the conds joined by `conds_sep`
+    /// respectively
+    pub id_pattern: String,
+    pub conds_sep: String,
+}
+
+impl Expansion {
+    const DEFAULT_ID_PATTERN: &'static str = "{base}@{conds}";
+    const DEFAULT_CONDS_SEP: &'static str = "+";
+
+    fn decode(table: &toml::Table) -> Result<Option<Self>, Error> {
+        let combinations = match table.get("expand") {
+            Some(v) => Combinations::decode(v)?,
+            None => return Ok(None),
+        };
+        let id_pattern = match table.get("expand_id") {
+            Some(v) => decode_string(v, "query_templates[].expand_id")?,
+            None => Self::DEFAULT_ID_PATTERN.to_owned(),
+        };
+        if !id_pattern.contains("{conds}") {
+            return Err(parse_error!(
+                "Value of 'query_templates[].expand_id' must contain the '{{conds}}' placeholder"
+            ));
+        }
+        let conds_sep = match table.get("expand_conds_sep") {
+            Some(v) => decode_string(v, "query_templates[].expand_conds_sep")?,
+            None => Self::DEFAULT_CONDS_SEP.to_owned(),
+        };
+        Ok(Some(Self {
+            combinations,
+            id_pattern,
+            conds_sep,
+        }))
+    }
+}
+
+/// Decodes the `requires` table i.e. mapping of a cond to the set of
+/// conds it requires
+fn decode_requires(
+    value: &Value,
+    all_conds: &HashSet<String>,
+) -> Result<BTreeMap<String, BTreeSet<String>>, Error> {
+    let key = "query_templates[].requires";
+    let table = value
+        .as_table()
+        .ok_or(parse_error!("Value of '{}' is expected to be a table", key))?;
+    let mut result = BTreeMap::new();
+    for (cond, v) in table {
+        let required = decode_strset(v, key)?;
+        ensure_known_conds(std::iter::once(cond).chain(required.iter()), all_conds, key)?;
+        result.insert(cond.to_owned(), required.into_iter().collect());
+    }
+    Ok(result)
+}
+
+/// Decodes the `excludes` array i.e. groups of mutually exclusive
+/// conds
+fn decode_excludes(
+    value: &Value,
+    all_conds: &HashSet<String>,
+) -> Result<Vec<BTreeSet<String>>, Error> {
+    let key = "query_templates[].excludes";
+    let xs = value.as_array().ok_or(parse_error!(
+        "Value of '{}' is expected to be an array of arrays of strings",
+        key
+    ))?;
+    let mut result = Vec::with_capacity(xs.len());
+    for x in xs {
+        let group = decode_strset(x, key)?;
+        ensure_known_conds(group.iter(), all_conds, key)?;
+        result.push(group.into_iter().collect());
+    }
+    Ok(result)
+}
+
+fn ensure_known_conds<'a>(
+    mut conds: impl Iterator<Item = &'a String>,
+    all_conds: &HashSet<String>,
+    key: &str,
+) -> Result<(), Error> {
+    match conds.find(|c| !all_conds.contains(*c)) {
+        Some(c) => Err(parse_error!(
+            "Cond '{}' in '{}' is not defined in 'all_conds'",
+            c,
+            key
+        )),
+        None => Ok(()),
+    }
+}
+
 #[derive(Debug)]
 pub struct QueryTemplate {
     pub path: PathBuf,
     pub all_conds: HashSet<String>,
+    pub expand: Option<Expansion>,
+    /// Mapping of a cond to the conds that must be included along
+    /// with it
+    pub requires: BTreeMap<String, BTreeSet<String>>,
+    /// Groups of conds out of which at most one may be included
+    pub excludes: Vec<BTreeSet<String>>,
+    /// Overrides the placeholder style set globally in the manifest
+    pub placeholder: Option<Placeholder>,
+    /// Kind of result returned by the queries, used for rendering the
+    /// name tags
+    pub result: Option<QueryResult>,
+    /// Docstring of the queries (jinja template)
+    pub description: Option<String>,
+    /// Label for grouping the queries into a common output file in
+    /// case of `grouped` layout (overridden by the `group` of the
+    /// query)
+    pub group: Option<String>,
 }
 
 impl QueryTemplate {
@@ -26,7 +179,42 @@ impl QueryTemplate {
                     Some(v) => decode_strset(v, "query_templates[].all_conds")?,
                     None => HashSet::new(),
                 };
-                Ok(Self { path, all_conds })
+                let expand = Expansion::decode(t)?;
+                let requires = match t.get("requires") {
+                    Some(v) => decode_requires(v, &all_conds)?,
+                    None => BTreeMap::new(),
+                };
+                let excludes = match t.get("excludes") {
+                    Some(v) => decode_excludes(v, &all_conds)?,
+                    None => vec![],
+                };
+                let placeholder = t
+                    .get("placeholder")
+                    .map(|v| Placeholder::decode(v, "query_templates[].placeholder"))
+                    .transpose()?;
+                let result = t
+                    .get("result")
+                    .map(|v| QueryResult::decode(v, "query_templates[].result"))
+                    .transpose()?;
+                let description = t
+                    .get("description")
+                    .map(|v| decode_string(v, "query_templates[].description"))
+                    .transpose()?;
+                let group = t
+                    .get("group")
+                    .map(|v| decode_string(v, "query_templates[].group"))
+                    .transpose()?;
+                Ok(Self {
+                    path,
+                    all_conds,
+                    expand,
+                    requires,
+                    excludes,
+                    placeholder,
+                    result,
+                    description,
+                    group,
+                })
             }
             None => Err(parse_error!("Invalid 'query_template' entry")),
         }
@@ -59,24 +247,133 @@ impl QueryTemplate {
             .unwrap()
     }
 
-    fn validate(&self) -> Option<ManifestMistake> {
-        match validate_path(&self.path, "query_templates[].path") {
-            Ok(()) => None,
-            Err(m) => Some(m),
+    /// Returns the combinations of conds for which queries are to be
+    /// generated. Each combination is sorted.
+    ///
+    /// In case of `expand = "all"`, the combinations that violate the
+    /// `requires` or `excludes` constraints are skipped and the rest
+    /// are ordered by the no. of conds first and then
+    /// lexicographically. Otherwise they are in the order specified in
+    /// the manifest (violations, if any, will be reported upon
+    /// validation). The result is empty if expansion is not
+    /// configured for the template, or if there are too many conds to
+    /// expand in case of `expand = "all"` (reported upon validation).
+    pub fn expanded_conds(&self) -> Vec<Vec<&str>> {
+        match &self.expand {
+            Some(e) => match &e.combinations {
+                Combinations::All if self.all_conds.len() > MAX_EXPAND_ALL_CONDS => vec![],
+                Combinations::All => {
+                    let mut all_conds = self
+                        .all_conds
+                        .iter()
+                        .map(|s| s.as_str())
+                        .collect::<Vec<&str>>();
+                    all_conds.sort();
+                    let mut result = (0..(1_usize << all_conds.len()))
+                        .map(|mask| {
+                            all_conds
+                                .iter()
+                                .enumerate()
+                                .filter(|(i, _)| mask & (1 << i) != 0)
+                                .map(|(_, c)| *c)
+                                .collect::<Vec<&str>>()
+                        })
+                        .filter(|conds| self.conds_violations(conds).is_empty())
+                        .collect::<Vec<Vec<&str>>>();
+                    result.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+                    result
+                }
+                Combinations::Subsets(subsets) => {
+                    let mut result: Vec<Vec<&str>> = Vec::with_capacity(subsets.len());
+                    for subset in subsets {
+                        let conds = subset.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+                        if !result.contains(&conds) {
+                            result.push(conds);
+                        }
+                    }
+                    result
+                }
+            },
+            None => vec![],
+        }
+    }
+
+    /// Returns the id of the query generated for the combination of
+    /// `conds` as per the naming rule
+    ///
+    /// For an empty combination, the base name is used as the id as
+    /// is.
+    pub fn expanded_id(&self, conds: &[&str]) -> String {
+        let base = template_stem(&self.path);
+        match &self.expand {
+            Some(e) if !conds.is_empty() => e
+                .id_pattern
+                .replace("{base}", base)
+                .replace("{conds}", &conds.join(&e.conds_sep)),
+            _ => base.to_owned(),
+        }
+    }
+
+    /// Checks the `conds` against the `requires` and `excludes`
+    /// constraints and returns the violations if any
+    pub fn conds_violations<'a>(&'a self, conds: &[&'a str]) -> Vec<CondsViolation<'a>> {
+        let mut violations = vec![];
+        for (cond, required) in self.requires.iter() {
+            if conds.contains(&cond.as_str()) {
+                let missing = required
+                    .iter()
+                    .map(|c| c.as_str())
+                    .filter(|c| !conds.contains(c))
+                    .collect::<Vec<&str>>();
+                if !missing.is_empty() {
+                    violations.push(CondsViolation::Requires { cond, missing });
+                }
+            }
+        }
+        for group in self.excludes.iter() {
+            let included = group
+                .iter()
+                .map(|c| c.as_str())
+                .filter(|c| conds.contains(c))
+                .collect::<Vec<&str>>();
+            if included.len() > 1 {
+                violations.push(CondsViolation::Excludes { conds: included });
+            }
+        }
+        violations
+    }
+
+    fn validate(&self) -> Vec<ManifestMistake<'_>> {
+        let mut mistakes = vec![];
+        if let Err(m) = validate_path(&self.path, "query_templates[].path") {
+            mistakes.push(m);
         }
+        if let Some(Expansion {
+            combinations: Combinations::All,
+            ..
+        }) = &self.expand
+        {
+            if self.all_conds.len() > MAX_EXPAND_ALL_CONDS {
+                mistakes.push(ManifestMistake::TooManyExpandConds {
+                    template: &self.path,
+                    num_conds: self.all_conds.len(),
+                });
+            }
+        }
+        mistakes
     }
 }
 
 #[derive(Debug)]
 pub struct QueryTemplates {
-    inner: Vec<Rc<QueryTemplate>>,
-    index: HashMap<String, Rc<QueryTemplate>>,
+    inner: Vec<Arc<QueryTemplate>>,
+    index: HashMap<String, Arc<QueryTemplate>>,
 }
 
 impl QueryTemplates {
     pub fn new() -> Self {
-        let inner: Vec<Rc<QueryTemplate>> = vec![];
-        let index: HashMap<String, Rc<QueryTemplate>> = HashMap::new();
+        let inner: Vec<Arc<QueryTemplate>> = vec![];
+        let index: HashMap<String, Arc<QueryTemplate>> = HashMap::new();
         Self { inner, index }
     }
 
@@ -86,12 +383,12 @@ impl QueryTemplates {
         // would be populating the index at the time of lookup (like a
         // read-through cache) but in that case we'd need to manage
         // multiple mutable references.
-        let mut index: HashMap<String, Rc<QueryTemplate>> = HashMap::new();
+        let mut index: HashMap<String, Arc<QueryTemplate>> = HashMap::new();
         let items = match value.as_array() {
             Some(xs) => {
                 let mut res = Vec::with_capacity(xs.len());
                 for x in xs {
-                    let qt = Rc::new(QueryTemplate::decode(&base_dir, x)?);
+                    let qt = Arc::new(QueryTemplate::decode(&base_dir, x)?);
                     let idx_key = qt.id().to_owned();
                     let idx_val = qt.clone();
                     res.push(qt);
@@ -108,14 +405,12 @@ impl QueryTemplates {
         })
     }
 
-    pub fn validate(&self) -> Vec<ManifestMistake> {
+    pub fn validate(&self) -> Vec<ManifestMistake<'_>> {
         let mut mistakes = vec![];
         let count = self.inner.len();
         let mut all_paths: HashMap<&Path, usize> = HashMap::with_capacity(count);
         for qt in &self.inner {
-            if let Some(m) = qt.validate() {
-                mistakes.push(m);
-            }
+            mistakes.append(&mut qt.validate());
             all_paths
                 .entry(&qt.path)
                 .and_modify(|c| *c += 1)
@@ -133,12 +428,12 @@ impl QueryTemplates {
         mistakes
     }
 
-    pub fn get(&self, path: &Path) -> Option<&Rc<QueryTemplate>> {
+    pub fn get(&self, path: &Path) -> Option<&Arc<QueryTemplate>> {
         let key = path.to_str().unwrap().to_owned();
         self.index.get(&key)
     }
 
-    pub fn iter(&self) -> std::slice::Iter<'_, Rc<QueryTemplate>> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Arc<QueryTemplate>> {
         self.inner.iter()
     }
 }
@@ -162,9 +457,16 @@ pub mod test_util {
     pub fn setup_query_templates(data: Vec<(&str, Vec<&str>)>) -> QueryTemplates {
         let mut qts = QueryTemplates::new();
         for (p, ac) in data {
-            let qt = Rc::new(QueryTemplate {
+            let qt = Arc::new(QueryTemplate {
                 path: PathBuf::from(p),
                 all_conds: strset(ac),
+                expand: None,
+                requires: BTreeMap::new(),
+                excludes: vec![],
+                placeholder: None,
+                result: None,
+                description: None,
+                group: None,
             });
             let idx_key = qt.id().to_owned();
             let idx_val = qt.clone();
@@ -196,10 +498,45 @@ all_conds = [ 'foo', 'bar' ]
             Ok(qt) => {
                 assert_eq!(PathBuf::from("base/my_query_template.sql.j2"), qt.path);
                 assert_eq!(strset(vec!["foo", "bar"]), qt.all_conds);
+                assert_eq!(None, qt.description);
             }
             Err(_) => assert!(false),
         }
 
+        // When `description` is specified
+        let table = r#"
+path = 'my_query_template.sql.j2'
+all_conds = [ 'foo' ]
+description = 'Songs{% if cond__foo %} filtered by foo{% endif %}'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode("base", &value) {
+            Ok(qt) => assert_eq!(
+                Some("Songs{% if cond__foo %} filtered by foo{% endif %}"),
+                qt.description.as_deref()
+            ),
+            Err(_) => assert!(false),
+        }
+
+        // When `description` is not a string
+        let table = r#"
+path = 'my_query_template.sql.j2'
+description = [ 'Songs' ]
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode("base", &value) {
+            Ok(_) => assert!(false),
+            Err(Error::Parsing(msg)) => assert_eq!(
+                "Value of 'query_templates[].description' expected to be a string",
+                msg
+            ),
+            Err(_) => assert!(false),
+        }
+
         // When `path` is missing
         let table = r#"
 all_conds = [ 'foo', 'bar' ]
@@ -260,6 +597,202 @@ all_conds = [ 1, 2 ]
         }
     }
 
+    #[test]
+    fn test_expansion() {
+        // When expand = "all"
+        let table = r#"
+path = 'artists_long_songs.sql.j2'
+all_conds = [ 'limit', 'genre' ]
+expand = 'all'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode("base", &value) {
+            Ok(qt) => {
+                assert_eq!(
+                    vec![vec![], vec!["genre"], vec!["limit"], vec!["genre", "limit"]],
+                    qt.expanded_conds()
+                );
+                assert_eq!("artists_long_songs", qt.expanded_id(&[]));
+                assert_eq!(
+                    "artists_long_songs@genre+limit",
+                    qt.expanded_id(&["genre", "limit"])
+                );
+            }
+            Err(_) => assert!(false),
+        }
+
+        // When specific subsets and a custom naming rule are specified
+        let table = r#"
+path = 'songs_formats.sql.j2'
+all_conds = [ 'artist', 'file_format', 'album_name' ]
+expand = [ [ 'artist' ], [ 'file_format', 'artist' ], [ 'artist' ] ]
+expand_id = 'formats_by_{conds}'
+expand_conds_sep = '&'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode("base", &value) {
+            Ok(qt) => {
+                assert_eq!(
+                    vec![vec!["artist"], vec!["artist", "file_format"]],
+                    qt.expanded_conds()
+                );
+                assert_eq!(
+                    "formats_by_artist&file_format",
+                    qt.expanded_id(&["artist", "file_format"])
+                );
+            }
+            Err(_) => assert!(false),
+        }
+
+        // When expansion is not configured
+        let table = r#"
+path = 'songs_formats.sql.j2'
+all_conds = [ 'artist' ]
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode("base", &value) {
+            Ok(qt) => assert!(qt.expanded_conds().is_empty()),
+            Err(_) => assert!(false),
+        }
+
+        // When the value of `expand` is invalid
+        let table = r#"
+path = 'songs_formats.sql.j2'
+all_conds = [ 'artist' ]
+expand = 'some'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode("base", &value) {
+            Err(Error::Parsing(msg)) => {
+                assert_eq!(
+                    "Value of 'query_templates[].expand' is expected to be either \"all\" or an array of arrays of strings",
+                    msg
+                );
+            }
+            _ => assert!(false),
+        }
+
+        // When the naming rule doesn't include the conds
+        let table = r#"
+path = 'songs_formats.sql.j2'
+all_conds = [ 'artist' ]
+expand = 'all'
+expand_id = '{base}'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode("base", &value) {
+            Err(Error::Parsing(msg)) => {
+                assert_eq!(
+                    "Value of 'query_templates[].expand_id' must contain the '{conds}' placeholder",
+                    msg
+                );
+            }
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_conds_constraints() {
+        let table = r#"
+path = 'artists.sql.j2'
+all_conds = [ 'order', 'limit', 'by_artist', 'by_album' ]
+expand = 'all'
+requires = { limit = [ 'order' ] }
+excludes = [ [ 'by_artist', 'by_album' ] ]
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode("base", &value) {
+            Ok(qt) => {
+                assert!(qt.conds_violations(&["limit", "order"]).is_empty());
+                match qt.conds_violations(&["by_album", "by_artist", "limit"])[..] {
+                    [CondsViolation::Requires { cond, ref missing }, CondsViolation::Excludes { ref conds }] =>
+                    {
+                        assert_eq!("limit", cond);
+                        assert_eq!(vec!["order"], *missing);
+                        assert_eq!(vec!["by_album", "by_artist"], *conds);
+                    }
+                    _ => assert!(false),
+                }
+                // Expansion skips the combinations that violate the
+                // constraints
+                assert_eq!(
+                    vec![
+                        vec![],
+                        vec!["by_album"],
+                        vec!["by_artist"],
+                        vec!["order"],
+                        vec!["by_album", "order"],
+                        vec!["by_artist", "order"],
+                        vec!["limit", "order"],
+                        vec!["by_album", "limit", "order"],
+                        vec!["by_artist", "limit", "order"],
+                    ],
+                    qt.expanded_conds()
+                );
+            }
+            Err(_) => assert!(false),
+        }
+
+        // When a constraint refers to an undefined cond
+        let table = r#"
+path = 'artists.sql.j2'
+all_conds = [ 'order', 'limit' ]
+requires = { limit = [ 'sort' ] }
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode("base", &value) {
+            Err(Error::Parsing(msg)) => {
+                assert_eq!(
+                    "Cond 'sort' in 'query_templates[].requires' is not defined in 'all_conds'",
+                    msg
+                );
+            }
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_expansion_limit() {
+        let all_conds = (0..=MAX_EXPAND_ALL_CONDS)
+            .map(|i| format!("'c{i}'"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let table = format!(
+            "path = 'examples/chinook/templates/queries/artists_long_songs.sql.j2'\nall_conds = [ {all_conds} ]\nexpand = 'all'"
+        )
+        .parse::<toml::Table>()
+        .unwrap();
+        let value = toml::Value::Table(table);
+        match QueryTemplate::decode(".", &value) {
+            Ok(qt) => {
+                // Queries are not generated and the template is
+                // reported as invalid instead
+                assert!(qt.expanded_conds().is_empty());
+                match qt.validate()[..] {
+                    [ManifestMistake::TooManyExpandConds { num_conds, .. }] => {
+                        assert_eq!(MAX_EXPAND_ALL_CONDS + 1, num_conds)
+                    }
+                    _ => assert!(false),
+                }
+            }
+            Err(_) => assert!(false),
+        }
+    }
+
     #[test]
     fn test_validation_duplicates() {
         let qts = setup_query_templates(vec![
@@ -1,14 +1,83 @@
 use crate::error::Error;
 use crate::metadata::Metadata;
 use crate::placeholder::Placeholder;
-use minijinja::{context, path_loader, Environment};
+use minijinja::value::Kwargs;
+use minijinja::{context, path_loader, Environment, ErrorKind};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::convert::From;
+use std::fmt;
 use std::path::Path;
 
-pub fn placeholder(name: String) -> Result<String, minijinja::Error> {
-    Ok(format!("{{{{ {name} }}}}"))
+/// Jinja function for defining a parameter in a query template
+///
+/// It renders to a variable which gets replaced by the actual
+/// placeholder in the second pass. If the optional `type` kwarg is
+/// specified, it's included in the intermediate output as a jinja
+/// comment so that it can be collected by `param_types` (the comment
+/// itself gets dropped in the second pass).
+pub fn placeholder(name: String, kwargs: Kwargs) -> Result<String, minijinja::Error> {
+    let sql_type: Option<String> = kwargs.get("type")?;
+    kwargs.assert_all_used()?;
+    match sql_type {
+        Some(t) => {
+            let t = t.trim();
+            if t.is_empty() || t.contains("#}") {
+                return Err(minijinja::Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("invalid type for placeholder '{name}': '{t}'"),
+                ));
+            }
+            Ok(format!("{{{{ {name} }}}}{{# param_type {name}: {t} #}}"))
+        }
+        None => Ok(format!("{{{{ {name} }}}}")),
+    }
+}
+
+/// Parameter declared with different types
+#[derive(Debug, PartialEq)]
+pub struct ParamTypeConflict {
+    pub name: String,
+    pub types: (String, String),
+}
+
+impl fmt::Display for ParamTypeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "param '{}' declared with different types: '{}' and '{}'",
+            self.name, self.types.0, self.types.1
+        )
+    }
+}
+
+/// Returns the types of the parameters declared in the intermediate
+/// output of a query template (see `placeholder`)
+///
+/// An error is returned if the same parameter is declared with
+/// different types.
+pub fn param_types(template: &str) -> Result<HashMap<String, String>, ParamTypeConflict> {
+    let re = Regex::new(r"\{# param_type (\w+): (.+?) #\}").unwrap();
+    let mut result: HashMap<String, String> = HashMap::new();
+    for cap in re.captures_iter(template) {
+        // @UNWRAP: Both groups are non-optional in the regex
+        let name = cap.get(1).unwrap().as_str();
+        let sql_type = cap.get(2).unwrap().as_str();
+        match result.get(name) {
+            Some(t) if t != sql_type => {
+                return Err(ParamTypeConflict {
+                    name: name.to_owned(),
+                    types: (t.to_owned(), sql_type.to_owned()),
+                });
+            }
+            Some(_) => {}
+            None => {
+                result.insert(name.to_owned(), sql_type.to_owned());
+            }
+        }
+    }
+    Ok(result)
 }
 
 fn cond_vars(all_conds: &HashSet<String>, conds: &HashSet<String>) -> HashMap<String, bool> {
@@ -32,34 +101,124 @@ fn capture_udvars<'a>(line: &'a str, re: &Regex, valid_udvars: &HashSet<String>)
     result
 }
 
-pub fn pos_args_mapping(template: &str, udvars: &HashSet<String>) -> HashMap<String, String> {
-    let mut result: HashMap<String, u8> = HashMap::with_capacity(udvars.len());
+/// Returns the user defined vars in the order in which they occur in
+/// the template, including repeated occurrences
+fn occurrences<'a>(template: &'a str, udvars: &HashSet<String>) -> Vec<&'a str> {
     let re = Regex::new(r"\{\{\s?(\w+)\s?\}\}").unwrap();
-    let mut counter = 1;
+    let mut result = vec![];
     for line in template.lines() {
         if line.is_empty() {
             continue;
         }
-        for var in capture_udvars(line, &re, udvars) {
-            if !result.contains_key(var) {
-                result.insert(var.to_owned(), counter);
-                counter += 1;
-            }
+        result.append(&mut capture_udvars(line, &re, udvars));
+    }
+    result
+}
+
+/// Returns the user defined vars in the order of their first
+/// occurrence in the template
+fn unique_occurrences<'a>(template: &'a str, udvars: &HashSet<String>) -> Vec<&'a str> {
+    let mut result: Vec<&str> = Vec::with_capacity(udvars.len());
+    for var in occurrences(template, udvars) {
+        if !result.contains(&var) {
+            result.push(var);
         }
     }
     result
+}
+
+pub fn pos_args_mapping(template: &str, udvars: &HashSet<String>) -> HashMap<String, String> {
+    unique_occurrences(template, udvars)
         .into_iter()
-        .map(|(k, v)| (k, format!("${v}")))
+        .enumerate()
+        .map(|(i, var)| (var.to_owned(), format!("${}", i + 1)))
         .collect::<HashMap<String, String>>()
 }
 
-pub fn variables_mapping(udvars: &HashSet<String>) -> HashMap<String, String> {
+fn named_mapping<F>(udvars: &HashSet<String>, f: F) -> HashMap<String, String>
+where
+    F: Fn(&str) -> String,
+{
     udvars
         .iter()
-        .map(|v| (v.to_owned(), format!(":{v}")))
+        .map(|v| (v.to_owned(), f(v)))
         .collect::<HashMap<String, String>>()
 }
 
+pub fn variables_mapping(udvars: &HashSet<String>) -> HashMap<String, String> {
+    named_mapping(udvars, |v| format!(":{v}"))
+}
+
+pub fn pyformat_mapping(udvars: &HashSet<String>) -> HashMap<String, String> {
+    named_mapping(udvars, |v| format!("%({v})s"))
+}
+
+pub fn at_variables_mapping(udvars: &HashSet<String>) -> HashMap<String, String> {
+    named_mapping(udvars, |v| format!("@{v}"))
+}
+
+/// Mapping for anonymous placeholders i.e. all vars are mapped to the
+/// same `marker` e.g. `?`
+pub fn anonymous_mapping(udvars: &HashSet<String>, marker: &str) -> HashMap<String, String> {
+    named_mapping(udvars, |_| marker.to_owned())
+}
+
+/// Returns the names of the parameters in the order in which they
+/// must be passed upon executing the query
+///
+/// In case of anonymous placeholders (`?` and `%s`), a parameter is
+/// repeated once per occurrence in the query as they can't be
+/// referred to more than once. Otherwise every parameter is included
+/// once, in the order of first occurrence.
+pub fn params_order<'a>(
+    template: &'a str,
+    udvars: &HashSet<String>,
+    placeholder: &Placeholder,
+) -> Vec<&'a str> {
+    if placeholder.is_anonymous() {
+        occurrences(template, udvars)
+    } else {
+        unique_occurrences(template, udvars)
+    }
+}
+
+/// Parameter of a rendered query
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Param {
+    pub name: String,
+    /// Placeholder that refers to the parameter in the query
+    /// e.g. `$1`, `:name`, `?`
+    pub placeholder: String,
+    /// SQL type of the parameter if specified in the query template
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub sql_type: Option<String>,
+}
+
+/// Output of rendering a query template along with the parameters
+/// in the order in which they must be passed upon executing the
+/// query
+#[derive(Debug)]
+pub struct RenderedQuery {
+    pub sql: String,
+    pub params: Vec<Param>,
+}
+
+impl RenderedQuery {
+    /// Returns the comma separated SQL types of the params, which
+    /// can be used for defining a prepared statement e.g. `PREPARE
+    /// name(varchar, int) AS ...`
+    ///
+    /// Params without a type are included as `unknown` so that
+    /// postgres infers their types.
+    pub fn param_types(&self) -> String {
+        self.params
+            .iter()
+            .map(|p| p.sql_type.as_deref().unwrap_or("unknown"))
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+}
+
 fn strip_trailing_semicolon(s: &str) -> &str {
     s.strip_suffix(';').unwrap_or(s)
 }
@@ -90,11 +249,10 @@ impl<'a> From<&'a Metadata> for Engine<'a> {
 }
 
 impl<'a> Engine<'a> {
-    pub fn render_query(
-        &self,
-        query_id: &str,
-        placeholder_override: Option<&Placeholder>,
-    ) -> Result<String, Error> {
+    /// Renders the query template for the conds of the query i.e. the
+    /// first pass of rendering, in which the params are rendered as
+    /// jinja vars (refer to `placeholder`)
+    fn intermediate_output(&self, query_id: &str) -> Result<String, Error> {
         let query = self
             .metadata
             .queries
@@ -108,7 +266,32 @@ impl<'a> Engine<'a> {
             .get_template(query_template.file_name())
             .map_err(Error::MiniJinja)?;
         let ctx = cond_vars(&query_template.all_conds, &query.conds);
-        let intermediate_output = tmpl.render(ctx).map_err(Error::MiniJinja)?;
+        tmpl.render(ctx).map_err(Error::MiniJinja)
+    }
+
+    /// Renders the query and also returns its parameters
+    ///
+    /// Refer to `params_order` for the order of the parameters.
+    pub fn render_query(
+        &self,
+        query_id: &str,
+        placeholder_override: Option<&Placeholder>,
+    ) -> Result<RenderedQuery, Error> {
+        let query = self
+            .metadata
+            .queries
+            .get(query_id)
+            .ok_or(Error::UndefinedQuery(query_id.to_owned()))?;
+        let intermediate_output = self.intermediate_output(query_id)?;
+        let types = param_types(&intermediate_output)
+            .map_err(|c| Error::ParamType(format!("{c}; query: '{query_id}'")))?;
+        let placeholder =
+            placeholder_override.unwrap_or_else(|| self.metadata.query_placeholder(query));
+        let intermediate_output = if placeholder.escapes_percent() {
+            intermediate_output.replace('%', "%%")
+        } else {
+            intermediate_output
+        };
         // Temporary environment to treat intermediate output as a
         // jinja template and render it
         let tmp_env = Environment::new();
@@ -116,18 +299,56 @@ impl<'a> Engine<'a> {
             .template_from_str(&intermediate_output)
             .map_err(Error::MiniJinja)?;
         let udvars = intermediate_tmpl.undeclared_variables(false);
-        let placeholder = placeholder_override.unwrap_or(&self.metadata.placeholder);
         let vars = match placeholder {
             Placeholder::PosArgs => pos_args_mapping(&intermediate_output, &udvars),
             Placeholder::Variables => variables_mapping(&udvars),
+            Placeholder::QMark => anonymous_mapping(&udvars, "?"),
+            Placeholder::Format => anonymous_mapping(&udvars, "%s"),
+            Placeholder::PyFormat => pyformat_mapping(&udvars),
+            Placeholder::AtVariables => at_variables_mapping(&udvars),
+        };
+        let params = params_order(&intermediate_output, &udvars, placeholder)
+            .into_iter()
+            .map(|name| Param {
+                name: name.to_owned(),
+                // @UNWRAP: All vars in `params_order` are included in
+                // the mapping
+                placeholder: vars.get(name).unwrap().to_owned(),
+                sql_type: types.get(name).cloned(),
+            })
+            .collect::<Vec<Param>>();
+        let sql = intermediate_tmpl.render(vars).map_err(Error::MiniJinja)?;
+        Ok(RenderedQuery { sql, params })
+    }
+
+    /// Renders the description of the query if specified
+    ///
+    /// The description is a jinja template in which the same
+    /// `cond__*` vars as in the query template are available.
+    pub fn render_description(&self, query_id: &str) -> Result<Option<String>, Error> {
+        let query = self
+            .metadata
+            .queries
+            .get(query_id)
+            .ok_or(Error::UndefinedQuery(query_id.to_owned()))?;
+        let description = match self.metadata.query_description(query) {
+            Some(d) => d,
+            None => return Ok(None),
         };
-        intermediate_tmpl.render(vars).map_err(Error::MiniJinja)
+        let query_template = self.metadata.query_templates.get(&query.template).ok_or(
+            Error::UndefinedQueryTemplate(query.template_file_name().to_owned()),
+        )?;
+        let ctx = cond_vars(&query_template.all_conds, &query.conds);
+        let env = Environment::new();
+        env.render_str(description, ctx)
+            .map(|s| Some(s.trim().to_owned()))
+            .map_err(Error::MiniJinja)
     }
 
     pub fn render_test(
         &self,
         path: &Path,
-        prepared_statement: Option<&str>,
+        prepared_statement: Option<&RenderedQuery>,
     ) -> Result<String, Error> {
         let test_template =
             self.metadata
@@ -140,12 +361,18 @@ impl<'a> Engine<'a> {
             .test_templates_env
             .get_template(test_template.file_name())
             .map_err(Error::MiniJinja)?;
-        // @TODO: Can we avoid allocation below by using `Cow`?
+        let rendered;
         let ps = match prepared_statement {
-            Some(s) => s.to_owned(),
-            None => self.render_query(&test_template.query, Some(&Placeholder::PosArgs))?,
+            Some(rq) => rq,
+            None => {
+                rendered = self.render_query(&test_template.query, Some(&Placeholder::PosArgs))?;
+                &rendered
+            }
+        };
+        let ctx = context! {
+            prepared_statement => strip_trailing_semicolon(&ps.sql),
+            param_types => ps.param_types(),
         };
-        let ctx = context! { prepared_statement => strip_trailing_semicolon(&ps) };
         tmpl.render(ctx).map_err(Error::MiniJinja)
     }
 }
@@ -154,6 +381,9 @@ impl<'a> Engine<'a> {
 mod tests {
 
     use super::*;
+    use crate::output::Layout;
+    use crate::query::Queries;
+    use crate::query_template::QueryTemplates;
 
     fn strset(xs: Vec<&str>) -> HashSet<String> {
         xs.iter().map(|s| String::from(*s)).collect()
@@ -202,4 +432,158 @@ AND tag = "{{ sometag }}"
         assert_eq!(1, result.len());
         assert_eq!("$1", result.get("firstname").unwrap());
     }
+
+    #[test]
+    fn test_params_order() {
+        let udvars = HashSet::from_iter(vec!["lastname".to_owned(), "department".to_owned()]);
+        let template = r#"
+SELECT * FROM employees
+WHERE department = {{ department }} AND (lastname = {{ lastname }} OR nickname = {{ lastname }})
+AND tag = "{{ sometag }}";
+"#;
+        assert_eq!(
+            vec!["department", "lastname"],
+            params_order(template, &udvars, &Placeholder::PosArgs)
+        );
+        assert_eq!(
+            vec!["department", "lastname"],
+            params_order(template, &udvars, &Placeholder::PyFormat)
+        );
+        // In case of anonymous placeholders, parameters are repeated
+        // once per occurrence
+        assert_eq!(
+            vec!["department", "lastname", "lastname"],
+            params_order(template, &udvars, &Placeholder::QMark)
+        );
+        assert_eq!(
+            vec!["department", "lastname", "lastname"],
+            params_order(template, &udvars, &Placeholder::Format)
+        );
+
+        let result = pyformat_mapping(&udvars);
+        assert_eq!("%(lastname)s", result.get("lastname").unwrap());
+        let result = at_variables_mapping(&udvars);
+        assert_eq!("@lastname", result.get("lastname").unwrap());
+        let result = anonymous_mapping(&udvars, "?");
+        assert_eq!("?", result.get("lastname").unwrap());
+        assert_eq!("?", result.get("department").unwrap());
+    }
+
+    #[test]
+    fn test_param_types() {
+        let mut env = Environment::new();
+        env.add_function("placeholder", placeholder);
+        let tmpl = env
+            .template_from_str(
+                "SELECT * FROM songs WHERE genre = {{ placeholder('genre', type='varchar') }} LIMIT {{ placeholder('limit') }} OFFSET {{ placeholder('offset', type=' int ') }};",
+            )
+            .unwrap();
+        let output = tmpl.render(context! {}).unwrap();
+        assert_eq!(
+            "SELECT * FROM songs WHERE genre = {{ genre }}{# param_type genre: varchar #} LIMIT {{ limit }} OFFSET {{ offset }}{# param_type offset: int #};",
+            output
+        );
+        let types = param_types(&output).unwrap();
+        assert_eq!(2, types.len());
+        assert_eq!("varchar", types.get("genre").unwrap());
+        assert_eq!("int", types.get("offset").unwrap());
+
+        // Same type declared more than once is fine
+        let output =
+            "{{ limit }}{# param_type limit: int #} {{ limit }}{# param_type limit: int #}";
+        assert_eq!(1, param_types(output).unwrap().len());
+
+        // Different types for the same param
+        let output =
+            "{{ limit }}{# param_type limit: int #} {{ limit }}{# param_type limit: bigint #}";
+        assert_eq!(
+            "param 'limit' declared with different types: 'int' and 'bigint'",
+            param_types(output).unwrap_err().to_string()
+        );
+
+        // Unknown kwargs are not allowed
+        let tmpl = env
+            .template_from_str("{{ placeholder('limit', typ='int') }}")
+            .unwrap();
+        assert!(tmpl.render(context! {}).is_err());
+    }
+
+    #[test]
+    fn test_render_description() {
+        let manifest = r#"
+[[query_templates]]
+path = 'songs.sql.j2'
+all_conds = [ 'genre', 'limit' ]
+description = """
+Songs{% if cond__genre %} of the genre{% endif %}
+
+{% if cond__limit %}Limited to n rows{% endif %}
+"""
+
+[[query_templates]]
+path = 'artists.sql.j2'
+all_conds = [ 'limit' ]
+
+[[queries]]
+id = 'songs'
+template = 'songs.sql.j2'
+
+[[queries]]
+id = 'songs@genre*limit'
+template = 'songs.sql.j2'
+conds = [ 'genre', 'limit' ]
+
+[[queries]]
+id = 'songs@limit'
+template = 'songs.sql.j2'
+conds = [ 'limit' ]
+description = 'Custom description (limit = {{ cond__limit }}, genre = {{ cond__genre }})'
+
+[[queries]]
+id = 'artists'
+template = 'artists.sql.j2'
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let mut metadata = Metadata::default();
+        metadata.query_templates =
+            QueryTemplates::decode("templates", manifest.get("query_templates").unwrap()).unwrap();
+        metadata.queries = Queries::decode(
+            "templates",
+            "output",
+            &Layout::OneFileOneQuery,
+            None,
+            None,
+            &metadata.query_templates,
+            manifest.get("queries").unwrap(),
+        )
+        .unwrap();
+        let engine = Engine::from(&metadata);
+
+        // Description of the query template, rendered with the conds
+        // of the query and trimmed
+        assert_eq!(
+            Some("Songs".to_owned()),
+            engine.render_description("songs").unwrap()
+        );
+        assert_eq!(
+            Some("Songs of the genre\n\nLimited to n rows".to_owned()),
+            engine.render_description("songs@genre*limit").unwrap()
+        );
+
+        // Description of the query takes precedence over that of the
+        // query template
+        assert_eq!(
+            Some("Custom description (limit = true, genre = false)".to_owned()),
+            engine.render_description("songs@limit").unwrap()
+        );
+
+        // No description
+        assert_eq!(None, engine.render_description("artists").unwrap());
+
+        match engine.render_description("unknown") {
+            Err(Error::UndefinedQuery(id)) => assert_eq!("unknown", id),
+            _ => assert!(false),
+        }
+    }
 }
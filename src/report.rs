@@ -0,0 +1,240 @@
+use crate::error::Error;
+use crate::output::Status;
+use crate::query::Query;
+use crate::test_template::TestTemplate;
+use crate::validation::ManifestMistake;
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// Version of the schema of the machine-readable output
+///
+/// It must be incremented upon any backward incompatible change to
+/// the records e.g. removing or renaming a field. Adding new fields or
+/// new record types is considered backward compatible.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Format in which the commands print their output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable tables and text
+    #[default]
+    Text,
+    /// Single JSON document containing all the records
+    Json,
+    /// Newline delimited JSON i.e. one record per line
+    Ndjson,
+}
+
+impl OutputFormat {
+    pub fn is_text(&self) -> bool {
+        *self == Self::Text
+    }
+}
+
+#[derive(Serialize)]
+pub struct TestRecord<'a> {
+    pub template: &'a Path,
+    pub output: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+impl<'a> TestRecord<'a> {
+    pub fn new(test_template: &'a TestTemplate, status: Option<Status>) -> Self {
+        Self {
+            template: &test_template.path,
+            output: &test_template.output,
+            status,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct QueryRecord<'a> {
+    pub id: &'a str,
+    pub template: &'a Path,
+    pub conds: Vec<&'a str>,
+    pub output: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    pub tests: Vec<TestRecord<'a>>,
+}
+
+impl<'a> QueryRecord<'a> {
+    pub fn new(query: &'a Query, status: Option<Status>, tests: Vec<TestRecord<'a>>) -> Self {
+        let mut conds = query
+            .conds
+            .iter()
+            .map(|c| c.as_str())
+            .collect::<Vec<&str>>();
+        // Sort the conds so that the output is deterministic
+        conds.sort();
+        Self {
+            id: &query.id,
+            template: &query.template,
+            conds,
+            output: &query.output,
+            description: None,
+            status,
+            tests,
+        }
+    }
+
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.description = description;
+        self
+    }
+}
+
+#[derive(Serialize)]
+pub struct MistakeRecord<'a> {
+    #[serde(flatten)]
+    pub mistake: &'a ManifestMistake<'a>,
+    pub message: String,
+}
+
+impl<'a> From<&'a ManifestMistake<'a>> for MistakeRecord<'a> {
+    fn from(mistake: &'a ManifestMistake<'a>) -> Self {
+        Self {
+            mistake,
+            message: mistake.err_msg(),
+        }
+    }
+}
+
+/// Collection of records to be printed by a command in one of the
+/// machine-readable formats
+///
+/// Every record is a JSON object with a `type` field that identifies
+/// the kind of record (e.g. `query`, `mistake`, `coverage`).
+pub struct Report {
+    command: &'static str,
+    records: Vec<Value>,
+}
+
+impl Report {
+    pub fn new(command: &'static str) -> Self {
+        Self {
+            command,
+            records: vec![],
+        }
+    }
+
+    pub fn push<T: Serialize>(&mut self, record_type: &str, record: &T) -> Result<(), Error> {
+        let mut value = serde_json::to_value(record).map_err(Error::Json)?;
+        match value.as_object_mut() {
+            Some(obj) => {
+                obj.insert("type".to_owned(), Value::from(record_type));
+            }
+            None => {
+                value = json!({"type": record_type, "value": value});
+            }
+        }
+        self.records.push(value);
+        Ok(())
+    }
+
+    /// Renders the report as a string as per the format
+    ///
+    /// In case of `json`, the output is a single object with the keys
+    /// `schema_version`, `command` and `records`. In case of
+    /// `ndjson`, the first line is a `meta` record containing the
+    /// `schema_version` and `command`, followed by one line per
+    /// record. Nothing is rendered in case of `text`.
+    fn render(&self, format: OutputFormat) -> Result<String, Error> {
+        match format {
+            OutputFormat::Text => Ok(String::new()),
+            OutputFormat::Json => {
+                let doc = json!({
+                    "schema_version": SCHEMA_VERSION,
+                    "command": self.command,
+                    "records": self.records,
+                });
+                serde_json::to_string_pretty(&doc).map_err(Error::Json)
+            }
+            OutputFormat::Ndjson => {
+                let meta = json!({
+                    "type": "meta",
+                    "schema_version": SCHEMA_VERSION,
+                    "command": self.command,
+                });
+                let mut lines = Vec::with_capacity(self.records.len() + 1);
+                lines.push(serde_json::to_string(&meta).map_err(Error::Json)?);
+                for record in self.records.iter() {
+                    lines.push(serde_json::to_string(record).map_err(Error::Json)?);
+                }
+                Ok(lines.join("\n"))
+            }
+        }
+    }
+
+    pub fn print(&self, format: OutputFormat) -> Result<(), Error> {
+        if !format.is_text() {
+            println!("{}", self.render(format)?);
+        }
+        Ok(())
+    }
+}
+
+/// Prints the validation mistakes in the manifest as per the format
+pub fn print_mistakes(
+    command: &'static str,
+    manifest: &Path,
+    mistakes: &[ManifestMistake],
+    format: OutputFormat,
+) -> Result<(), Error> {
+    if format.is_text() {
+        println!("Invalid manifest file: '{}'", manifest.display());
+        for mistake in mistakes {
+            println!("{}", mistake.err_msg())
+        }
+        Ok(())
+    } else {
+        let mut report = Report::new(command);
+        report.push("validation", &json!({"manifest": manifest, "valid": false}))?;
+        for mistake in mistakes {
+            report.push("mistake", &MistakeRecord::from(mistake))?;
+        }
+        report.print(format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_report_render() {
+        let mistakes = [
+            ManifestMistake::InvalidConds {
+                query_id: "artists_long_songs*limit",
+                conds: vec!["limits"],
+            },
+            ManifestMistake::NonUniqueDirs,
+        ];
+        let mut report = Report::new("validate");
+        for mistake in mistakes.iter() {
+            report
+                .push("mistake", &MistakeRecord::from(mistake))
+                .unwrap();
+        }
+
+        assert_eq!("", report.render(OutputFormat::Text).unwrap());
+
+        let expected = r#"{"command":"validate","schema_version":1,"type":"meta"}
+{"fields":{"conds":["limits"],"query_id":"artists_long_songs*limit"},"kind":"invalid_conds","message":"Invalid 'conds': [\"limits\"] defined for query: 'artists_long_songs*limit'","type":"mistake"}
+{"kind":"non_unique_dirs","message":"Values for all '*_dir' keys in the manifest file must be unique","type":"mistake"}"#;
+        assert_eq!(expected, report.render(OutputFormat::Ndjson).unwrap());
+
+        let doc: Value = serde_json::from_str(&report.render(OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(Some(1), doc["schema_version"].as_u64());
+        assert_eq!(Some("validate"), doc["command"].as_str());
+        assert_eq!(2, doc["records"].as_array().unwrap().len());
+        assert_eq!(Some("invalid_conds"), doc["records"][0]["kind"].as_str());
+    }
+}
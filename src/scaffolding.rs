@@ -24,12 +24,14 @@ fn create_project_dir(path: &Path) -> Result<(), Error> {
 #[derive(Serialize)]
 struct NameTaggerContext {
     style: String,
+    flavor: String,
 }
 
 impl From<&NameTagger> for NameTaggerContext {
     fn from(tagger: &NameTagger) -> Self {
         Self {
             style: tagger.style.to_string(),
+            flavor: tagger.flavor.to_string(),
         }
     }
 }
@@ -41,6 +43,7 @@ struct DefaultManifestContext<'a> {
     test_templates_dir: &'a Path,
     queries_output_dir: &'a Path,
     tests_output_dir: &'a Path,
+    generated_marker: bool,
     formatter: Option<SerializableTomlTable>,
     name_tagger: Option<NameTaggerContext>,
 }
@@ -55,6 +58,7 @@ impl<'a> From<&'a Metadata> for DefaultManifestContext<'a> {
             test_templates_dir: m.test_templates_dir.as_path(),
             queries_output_dir: m.queries_output_dir.as_path(),
             tests_output_dir: m.tests_output_dir.as_path(),
+            generated_marker: m.generated_marker,
             formatter,
             name_tagger,
         }
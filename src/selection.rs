@@ -0,0 +1,165 @@
+use crate::error::Error;
+use crate::metadata::Metadata;
+use crate::query::Query;
+use crate::test_template::TestTemplate;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+/// Converts a glob pattern into an anchored regex
+///
+/// Only `*` (any sequence of chars) and `?` (any single char) are
+/// supported. All other chars are matched literally. Note that query
+/// ids may themselves contain `*` (e.g. `artists_long_songs*limit`)
+/// in which case the pattern still matches the id exactly.
+fn glob_to_regex(pattern: &str) -> Regex {
+    let mut re = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    // @UNWRAP: All chars except the wildcards are escaped so the
+    // regex is always valid
+    Regex::new(&re).unwrap()
+}
+
+/// Checks whether a path specified on the command line refers to
+/// `target` (a path as decoded from the manifest)
+///
+/// The user may specify the path either relative to the project root
+/// (i.e. including the templates dir) or relative to the templates
+/// dir itself.
+fn path_matches(input: &Path, target: &Path, base_dir: &Path) -> bool {
+    input == target || base_dir.join(input) == target
+}
+
+/// Subset of queries and tests that a command should operate on
+///
+/// Queries can be selected by id (glob patterns allowed) or by query
+/// template path. Tests can be selected by test template path. When
+/// no selectors are specified, everything is selected.
+#[derive(Debug, Default)]
+pub struct Selection {
+    query_patterns: Vec<Regex>,
+    query_templates: Vec<PathBuf>,
+    test_templates: Vec<PathBuf>,
+}
+
+impl Selection {
+    pub fn new(
+        query_ids: &[String],
+        query_templates: &[PathBuf],
+        test_templates: &[PathBuf],
+    ) -> Self {
+        Self {
+            query_patterns: query_ids.iter().map(|p| glob_to_regex(p)).collect(),
+            query_templates: query_templates.to_vec(),
+            test_templates: test_templates.to_vec(),
+        }
+    }
+
+    /// Returns true if no selectors are specified
+    pub fn is_all(&self) -> bool {
+        self.query_patterns.is_empty()
+            && self.query_templates.is_empty()
+            && self.test_templates.is_empty()
+    }
+
+    /// Returns true if the query is selected either by it's id or by
+    /// the query template
+    pub fn includes_query(&self, query: &Query, metadata: &Metadata) -> bool {
+        if self.is_all() {
+            return true;
+        }
+        self.query_patterns.iter().any(|re| re.is_match(&query.id))
+            || self
+                .query_templates
+                .iter()
+                .any(|p| path_matches(p, &query.template, &metadata.query_templates_dir))
+    }
+
+    /// Returns true if the test template is selected, either
+    /// explicitly or implicitly (i.e. by virtue of the query it tests
+    /// being selected)
+    pub fn includes_test(&self, test_template: &TestTemplate, metadata: &Metadata) -> bool {
+        if self.is_all() {
+            return true;
+        }
+        let explicit = self
+            .test_templates
+            .iter()
+            .any(|p| path_matches(p, &test_template.path, &metadata.test_templates_dir));
+        explicit
+            || metadata
+                .queries
+                .get(&test_template.query)
+                .is_some_and(|q| self.includes_query(q, metadata))
+    }
+
+    /// Returns an error if the selectors don't match a single query
+    /// or test defined in the manifest
+    pub fn ensure_non_empty(&self, metadata: &Metadata) -> Result<(), Error> {
+        if self.is_all() {
+            return Ok(());
+        }
+        let any_query = metadata
+            .queries
+            .iter()
+            .any(|q| self.includes_query(q, metadata));
+        let any_test = metadata
+            .test_templates
+            .iter()
+            .any(|tt| self.includes_test(tt, metadata));
+        if any_query || any_test {
+            Ok(())
+        } else {
+            Err(Error::Cli(
+                "No queries or tests in the manifest match the specified selectors".to_owned(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_glob_to_regex() {
+        let re = glob_to_regex("artists_long_songs");
+        assert!(re.is_match("artists_long_songs"));
+        assert!(!re.is_match("artists_long_songs*limit"));
+
+        let re = glob_to_regex("artists_long_songs*");
+        assert!(re.is_match("artists_long_songs"));
+        assert!(re.is_match("artists_long_songs*limit"));
+        assert!(re.is_match("artists_long_songs@genre*limit"));
+        assert!(!re.is_match("songs_formats@artist+album"));
+
+        // Special chars used in query ids are matched literally
+        let re = glob_to_regex("songs_formats@artist+album");
+        assert!(re.is_match("songs_formats@artist+album"));
+        assert!(!re.is_match("songs_formats@artisttalbum"));
+
+        let re = glob_to_regex("songs_formats@artist?album");
+        assert!(re.is_match("songs_formats@artist+album"));
+        assert!(!re.is_match("songs_formats@artist&file_format+album"));
+    }
+
+    #[test]
+    fn test_path_matches() {
+        let target = Path::new("templates/queries/foo.sql.j2");
+        let base_dir = Path::new("templates/queries");
+        assert!(path_matches(
+            Path::new("templates/queries/foo.sql.j2"),
+            target,
+            base_dir
+        ));
+        assert!(path_matches(Path::new("foo.sql.j2"), target, base_dir));
+        assert!(!path_matches(Path::new("bar.sql.j2"), target, base_dir));
+    }
+}
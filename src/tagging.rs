@@ -1,20 +1,96 @@
+use minijinja::{context, Environment};
 use regex::Regex;
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fmt;
+use std::path::Path;
 use toml::Value;
 
 use crate::error::{parse_error, Error};
+use crate::query::template_stem;
+use crate::toml::decode_string;
 
 #[derive(Debug)]
 pub enum NameTag {
     DeriveFromId(String),
+    /// Derived from the output of `name_tagger.template` rendered for
+    /// the query
+    DeriveFromTemplate(String),
     Custom(String),
 }
 
+impl NameTag {
+    /// Returns the name tag for a query that doesn't specify a custom
+    /// one
+    ///
+    /// If `name_tagger.template` is configured, it's rendered with
+    /// the query id, the stem of the query template's file name and
+    /// the (sorted) conds in the context. Otherwise the name tag is
+    /// derived from the id.
+    pub fn derive(
+        id: &str,
+        template: &Path,
+        conds: &HashSet<String>,
+        tagger: Option<&NameTagger>,
+    ) -> Result<Self, Error> {
+        let tmpl = match tagger.and_then(|t| t.template.as_ref()) {
+            Some(t) => t,
+            None => return Ok(Self::DeriveFromId(id.to_owned())),
+        };
+        // Template path is decoded from a toml string and hence it's
+        // valid unicode
+        let template_stem = template_stem(template);
+        let mut conds = conds.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+        conds.sort();
+        let tag = tmpl.render(context! { id, template_stem, conds })?;
+        let tag = tag.trim();
+        if tag.is_empty() || tag.contains(char::is_whitespace) {
+            return Err(parse_error!(
+                "Value of 'name_tagger.template' rendered an invalid tag for query '{id}': '{tag}'"
+            ));
+        }
+        Ok(Self::DeriveFromTemplate(tag.to_owned()))
+    }
+}
+
+/// Compiled `name_tagger.template`
+///
+/// The template is compiled once when the manifest is parsed and
+/// rendered for every query whose name tag is to be derived from it.
+#[derive(Debug)]
+pub struct NameTagTemplate {
+    source: String,
+    env: Environment<'static>,
+}
+
+impl NameTagTemplate {
+    const NAME: &'static str = "name_tagger.template";
+
+    pub fn new(source: String) -> Result<Self, Error> {
+        let mut env = Environment::new();
+        env.add_template_owned(Self::NAME, source.clone())
+            .map_err(Error::MiniJinja)?;
+        Ok(Self { source, env })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn render<S: serde::Serialize>(&self, ctx: S) -> Result<String, Error> {
+        // @UNWRAP: The template is added to the env upon construction
+        let tmpl = self.env.get_template(Self::NAME).unwrap();
+        tmpl.render(ctx).map_err(Error::MiniJinja)
+    }
+}
+
 #[derive(Debug)]
 pub enum NameTagStyle {
     SnakeCase,
     KebabCase,
+    CamelCase,
+    PascalCase,
+    ScreamingSnakeCase,
     Exact,
 }
 
@@ -27,6 +103,9 @@ impl NameTagStyle {
                 match s {
                     "snake_case" => Ok(Self::SnakeCase),
                     "kebab-case" => Ok(Self::KebabCase),
+                    "camelCase" => Ok(Self::CamelCase),
+                    "PascalCase" => Ok(Self::PascalCase),
+                    "SCREAMING_SNAKE_CASE" => Ok(Self::ScreamingSnakeCase),
                     "exact" => Ok(Self::Exact),
                     _ => Err(parse_error!("Invalid value for 'name_tagger.style': {s}")),
                 }
@@ -39,23 +118,146 @@ impl NameTagStyle {
 
     // Constructs a tag from the id as per the `NameTagStyle`. Any
     // non-alphanumeric char will be replaced by either hyphen
-    // (kebab-case) or underscore (snake_case).
+    // (kebab-case) or underscore (snake_case, SCREAMING_SNAKE_CASE).
+    // In case of camelCase and PascalCase, such chars are considered
+    // as word boundaries and removed.
     pub fn make_tag<'a>(&self, id: &'a str) -> Cow<'a, str> {
         // @TODO: Can this regex pattern be defined globally?
         let re = Regex::new(r"_|-|@|\+|&|\*").unwrap();
         match self {
             Self::SnakeCase => re.replace_all(id, "_"),
             Self::KebabCase => re.replace_all(id, "-"),
+            Self::ScreamingSnakeCase => Cow::from(re.replace_all(id, "_").to_uppercase()),
+            Self::CamelCase | Self::PascalCase => {
+                let mut result = String::with_capacity(id.len());
+                for (i, word) in re.split(id).filter(|w| !w.is_empty()).enumerate() {
+                    let mut chars = word.chars();
+                    // @UNWRAP: Empty words are filtered out
+                    let first = chars.next().unwrap();
+                    if i == 0 && matches!(self, Self::CamelCase) {
+                        result.extend(first.to_lowercase());
+                    } else {
+                        result.extend(first.to_uppercase());
+                    }
+                    result.push_str(chars.as_str());
+                }
+                Cow::from(result)
+            }
             Self::Exact => Cow::from(id),
         }
     }
 }
 
+/// Flavor of the name tag i.e. the format of the comment as expected
+/// by the library that loads the queries
+#[derive(Debug, PartialEq)]
+pub enum NameTagFlavor {
+    /// `-- name: foo`
+    Default,
+    /// `-- :name foo :? :*`
+    HugSql,
+    /// `-- :name foo :many`
+    PugSql,
+    /// `-- name: foo^`
+    AioSql,
+}
+
+impl NameTagFlavor {
+    fn decode(value: &Value) -> Result<Self, Error> {
+        match value.as_str() {
+            Some("default") => Ok(Self::Default),
+            Some("hugsql") => Ok(Self::HugSql),
+            Some("pugsql") => Ok(Self::PugSql),
+            Some("aiosql") => Ok(Self::AioSql),
+            Some(s) => Err(parse_error!("Invalid value for 'name_tagger.flavor': {s}")),
+            None => Err(parse_error!(
+                "Value of 'name_tagger.flavor' expected to be a string"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NameTagFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameTagFlavor::Default => write!(f, "default"),
+            NameTagFlavor::HugSql => write!(f, "hugsql"),
+            NameTagFlavor::PugSql => write!(f, "pugsql"),
+            NameTagFlavor::AioSql => write!(f, "aiosql"),
+        }
+    }
+}
+
+/// Kind of result returned upon executing a query
+///
+/// It's used for rendering the name tag in case of the flavors that
+/// support it (see `NameTagFlavor`).
+#[derive(Debug, PartialEq)]
+pub enum QueryResult {
+    /// A single row
+    One,
+    /// Multiple rows
+    Many,
+    /// Number of affected rows
+    Affected,
+    /// Row(s) returned by an `INSERT ... RETURNING` statement
+    InsertReturning,
+    /// Script i.e. one or more statements without any result
+    Script,
+}
+
+impl QueryResult {
+    pub fn decode(value: &Value, key: &str) -> Result<Self, Error> {
+        match value.as_str() {
+            Some("one") => Ok(Self::One),
+            Some("many") => Ok(Self::Many),
+            Some("affected") => Ok(Self::Affected),
+            Some("insert-returning") => Ok(Self::InsertReturning),
+            Some("script") => Ok(Self::Script),
+            Some(s) => Err(parse_error!("Invalid value for '{key}': '{s}'")),
+            None => Err(parse_error!("Value of '{key}' expected to be a string")),
+        }
+    }
+
+    fn hugsql_flags(&self) -> &str {
+        match self {
+            Self::One => ":? :1",
+            Self::Many => ":? :*",
+            Self::Affected => ":! :n",
+            Self::InsertReturning => ":<! :1",
+            Self::Script => ":! :raw",
+        }
+    }
+
+    fn pugsql_flag(&self) -> &str {
+        match self {
+            Self::One => ":one",
+            Self::Many => ":many",
+            Self::Affected => ":affected",
+            Self::InsertReturning => ":insert",
+            Self::Script => ":raw",
+        }
+    }
+
+    fn aiosql_suffix(&self) -> &str {
+        match self {
+            Self::One => "^",
+            Self::Many => "",
+            Self::Affected => "!",
+            Self::InsertReturning => "<!",
+            Self::Script => "#",
+        }
+    }
+}
+
 impl fmt::Display for NameTagStyle {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             NameTagStyle::SnakeCase => write!(f, "snake_case"),
             NameTagStyle::KebabCase => write!(f, "kebab-case"),
+            NameTagStyle::CamelCase => write!(f, "camelCase"),
+            NameTagStyle::PascalCase => write!(f, "PascalCase"),
+            NameTagStyle::ScreamingSnakeCase => write!(f, "SCREAMING_SNAKE_CASE"),
             NameTagStyle::Exact => write!(f, "exact"),
         }
     }
@@ -74,50 +276,173 @@ impl fmt::Display for NameTagStyle {
 //   -- name: foobar
 //   -- This query simply fetches from the foobar table
 //   SELECT * FROM foobar;
+//
+// All flavors of name tags are recognized (see `NameTagFlavor`).
 fn has_name_tag(sql: &str) -> bool {
-    let pattern = Regex::new(r"--\s*name:\s*\w+").unwrap();
+    let pattern = Regex::new(r"--\s*(name:|:name\s)\s*\w+").unwrap();
     let first_line = sql.lines().find(|s| !s.is_empty()).unwrap_or("");
     pattern.is_match(first_line)
 }
 
+// Checks whether the line is a name tag line i.e. a comment with
+// nothing else but the name tag.
+//
+// Unlike `has_name_tag`, this fn is strict about the entire line
+// being the name tag so that regular comments in the middle of the
+// query that happen to start with `name:` are not mistaken for name
+// tags.
+pub fn is_name_tag_line(line: &str) -> bool {
+    parse_name_tag_line(line).is_some()
+}
+
+// Parses a name tag line of any flavor and returns the name of the
+// query i.e. without the result flags (hugsql, pugsql) or the
+// operator suffix (aiosql)
+pub fn parse_name_tag_line(line: &str) -> Option<&str> {
+    // @TODO: Can this regex pattern be defined globally?
+    let pattern =
+        Regex::new(r"^--\s*(?:name:\s*(\S+?)(?:<!|\*!|!|\^|\$|#)?|:name\s+(\S+)(?:\s+:\S+)*)\s*$")
+            .unwrap();
+    pattern
+        .captures(line)
+        .and_then(|c| c.get(1).or_else(|| c.get(2)))
+        .map(|m| m.as_str())
+}
+
+// Returns the name in the name tag if the sql has one as the first
+// comment (see `has_name_tag`)
+pub fn extract_name_tag(sql: &str) -> Option<&str> {
+    let first_line = sql.lines().find(|s| !s.is_empty())?;
+    parse_name_tag_line(first_line)
+}
+
+// Returns the description as sql comment lines (each ending with a
+// newline char)
+fn docstring(description: &str) -> String {
+    let mut result = String::new();
+    for line in description.trim().lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            result.push_str("--\n");
+        } else {
+            result.push_str("-- ");
+            result.push_str(line);
+            result.push('\n');
+        }
+    }
+    result
+}
+
 #[derive(Debug)]
 pub struct NameTagger {
     pub style: NameTagStyle,
+    pub flavor: NameTagFlavor,
+    /// Jinja template for deriving the name tags (see
+    /// `NameTag::derive`). The `style` is applied to it's output.
+    pub template: Option<NameTagTemplate>,
 }
 
 impl NameTagger {
     pub fn decode(value: &Value) -> Result<Option<Self>, Error> {
         match value.as_table() {
             Some(t) => {
-                let style = t
-                    .get("style")
-                    .ok_or(parse_error!("Key 'name_tagger.style' is missing"))
-                    .map(NameTagStyle::decode)??;
-                Ok(Some(Self { style }))
+                let template = t
+                    .get("template")
+                    .map(|v| {
+                        decode_string(v, "name_tagger.template").and_then(NameTagTemplate::new)
+                    })
+                    .transpose()?;
+                // `style` is optional if the tags are derived from
+                // the template, in which case the output of the
+                // template is used as it is by default
+                let style = match (t.get("style"), &template) {
+                    (Some(v), _) => NameTagStyle::decode(v)?,
+                    (None, Some(_)) => NameTagStyle::Exact,
+                    (None, None) => return Err(parse_error!("Key 'name_tagger.style' is missing")),
+                };
+                let flavor = match t.get("flavor") {
+                    Some(v) => NameTagFlavor::decode(v)?,
+                    None => NameTagFlavor::Default,
+                };
+                Ok(Some(Self {
+                    style,
+                    flavor,
+                    template,
+                }))
             }
             None => Ok(None),
         }
     }
 
-    pub fn make_name_tag(&self, name_tag: &NameTag) -> String {
-        let tag = match name_tag {
-            NameTag::DeriveFromId(id) => self.style.make_tag(id),
+    // Returns the tag i.e. the name of the query without the `--
+    // name:` prefix
+    pub fn tag<'a>(&self, name_tag: &'a NameTag) -> Cow<'a, str> {
+        match name_tag {
+            NameTag::DeriveFromId(s) | NameTag::DeriveFromTemplate(s) => self.style.make_tag(s),
             NameTag::Custom(s) => Cow::from(s),
-        };
-        format!("-- name: {tag}")
+        }
+    }
+
+    // Returns the name tag comment as per the flavor
+    //
+    // The `result` is included only in case of the flavors that
+    // support it i.e. it's ignored in case of the default flavor.
+    pub fn make_name_tag(&self, name_tag: &NameTag, result: Option<&QueryResult>) -> String {
+        let tag = self.tag(name_tag);
+        match (&self.flavor, result) {
+            (NameTagFlavor::Default, _) => format!("-- name: {tag}"),
+            (NameTagFlavor::AioSql, Some(r)) => format!("-- name: {tag}{}", r.aiosql_suffix()),
+            (NameTagFlavor::AioSql, None) => format!("-- name: {tag}"),
+            (NameTagFlavor::HugSql, Some(r)) => format!("-- :name {tag} {}", r.hugsql_flags()),
+            (NameTagFlavor::PugSql, Some(r)) => format!("-- :name {tag} {}", r.pugsql_flag()),
+            (NameTagFlavor::HugSql | NameTagFlavor::PugSql, None) => format!("-- :name {tag}"),
+        }
     }
 
     // Prepends `sql` with a comment line containing the name tag if one
     // doesn't already exist
     //
+    // If `description` is specified, it's added as comment lines
+    // right after the name tag i.e. as the docstring of the query. In
+    // case the sql already has a name tag, the description is added
+    // right after it, before any docstring that the sql has of it's
+    // own (unless the sql already begins with the same docstring).
+    //
     // Note that this function also trims any leading blank lines
-    pub fn ensure_name_tag<'a>(&self, sql: &'a str, name_tag: &'a NameTag) -> Cow<'a, str> {
+    pub fn ensure_name_tag<'a>(
+        &self,
+        sql: &'a str,
+        name_tag: &'a NameTag,
+        result: Option<&QueryResult>,
+        description: Option<&str>,
+    ) -> Cow<'a, str> {
         let sql = sql.trim_start();
         if has_name_tag(sql) {
-            Cow::from(sql)
+            let desc = match description.map(docstring) {
+                Some(d) if !d.is_empty() => d,
+                _ => return Cow::from(sql),
+            };
+            let (tag_line, rest) = match sql.find('\n') {
+                Some(i) => sql.split_at(i + 1),
+                None => (sql, ""),
+            };
+            if rest.starts_with(&desc) {
+                return Cow::from(sql);
+            }
+            let mut result = String::with_capacity(sql.len() + desc.len() + 1);
+            result.push_str(tag_line);
+            if !tag_line.ends_with('\n') {
+                result.push('\n');
+            }
+            result.push_str(&desc);
+            result.push_str(rest);
+            Cow::from(result)
         } else {
-            let mut result = self.make_name_tag(name_tag);
+            let mut result = self.make_name_tag(name_tag, result);
             result.push('\n');
+            if let Some(desc) = description {
+                result.push_str(&docstring(desc));
+            }
             result.push_str(sql);
             Cow::from(result)
         }
@@ -132,14 +457,18 @@ mod tests {
     #[test]
     fn test_ensure_name_tag() {
         let tag_style = NameTagStyle::SnakeCase;
-        let tagger = NameTagger { style: tag_style };
+        let tagger = NameTagger {
+            style: tag_style,
+            flavor: NameTagFlavor::Default,
+            template: None,
+        };
 
         // When name tag doesn't exist
         let sql = "SELECT 1;";
         let name_tag = NameTag::DeriveFromId("simple-query".to_owned());
         let expected = r#"-- name: simple_query
 SELECT 1;"#;
-        assert_eq!(expected, tagger.ensure_name_tag(sql, &name_tag));
+        assert_eq!(expected, tagger.ensure_name_tag(sql, &name_tag, None, None));
 
         // When name tag exists (along with whitespace before the
         // pre_comments and additional comments/documentation)
@@ -154,7 +483,7 @@ SELECT 1;
 SELECT 1;
 
 "#;
-        assert_eq!(expected, tagger.ensure_name_tag(sql, &name_tag));
+        assert_eq!(expected, tagger.ensure_name_tag(sql, &name_tag, None, None));
 
         // When a comment similar to the name tag is found somewhere
         // in the middle of the sql
@@ -179,7 +508,160 @@ WHERE
     email = 'email'
     AND department = 'department';
 "#;
-        assert_eq!(expected, tagger.ensure_name_tag(sql, &name_tag));
+        assert_eq!(expected, tagger.ensure_name_tag(sql, &name_tag, None, None));
+
+        // With description
+        let sql = "SELECT 1;";
+        let name_tag = NameTag::DeriveFromId("simple-query".to_owned());
+        let expected = r#"-- name: simple_query
+-- Fetch static value one
+--
+-- Useful for health checks
+SELECT 1;"#;
+        assert_eq!(
+            expected,
+            tagger.ensure_name_tag(
+                sql,
+                &name_tag,
+                None,
+                Some("Fetch static value one\n\nUseful for health checks\n")
+            )
+        );
+
+        // With description when the name tag exists along with a
+        // docstring of it's own
+        let sql = r#"
+-- name: simple_query
+-- Useful for health checks
+SELECT 1;
+"#;
+        let expected = r#"-- name: simple_query
+-- Fetch static value one
+-- Useful for health checks
+SELECT 1;
+"#;
+        let tagged = tagger.ensure_name_tag(sql, &name_tag, None, Some("Fetch static value one"));
+        assert_eq!(expected, tagged);
+
+        // The description is not added again if the sql already
+        // begins with it
+        assert_eq!(
+            expected,
+            tagger.ensure_name_tag(&tagged, &name_tag, None, Some("Fetch static value one"))
+        );
+
+        // With description when the sql is just the name tag
+        assert_eq!(
+            "-- name: simple_query\n-- Fetch static value one\n",
+            tagger.ensure_name_tag(
+                "-- name: simple_query",
+                &name_tag,
+                None,
+                Some("Fetch static value one")
+            )
+        );
+    }
+
+    #[test]
+    fn test_make_tag() {
+        let id = "artists_long_songs@genre*limit";
+        assert_eq!(
+            "artists_long_songs_genre_limit",
+            NameTagStyle::SnakeCase.make_tag(id)
+        );
+        assert_eq!(
+            "artists-long-songs-genre-limit",
+            NameTagStyle::KebabCase.make_tag(id)
+        );
+        assert_eq!(
+            "artistsLongSongsGenreLimit",
+            NameTagStyle::CamelCase.make_tag(id)
+        );
+        assert_eq!(
+            "ArtistsLongSongsGenreLimit",
+            NameTagStyle::PascalCase.make_tag(id)
+        );
+        assert_eq!(
+            "ARTISTS_LONG_SONGS_GENRE_LIMIT",
+            NameTagStyle::ScreamingSnakeCase.make_tag(id)
+        );
+        assert_eq!(id, NameTagStyle::Exact.make_tag(id));
+
+        // Consecutive separators are considered as a single word
+        // boundary
+        assert_eq!(
+            "songsGenre",
+            NameTagStyle::CamelCase.make_tag("songs__genre")
+        );
+        assert_eq!("fetchUser", NameTagStyle::CamelCase.make_tag("FetchUser"));
+    }
+
+    #[test]
+    fn test_derive_name_tag() {
+        let table = r#"
+template = "{{ template_stem }}__{{ conds|join('_') }}"
+"#
+        .parse::<toml::Table>()
+        .unwrap();
+        let tagger = NameTagger::decode(&Value::Table(table)).unwrap().unwrap();
+        // Style defaults to `exact` when template is specified
+        assert_eq!("exact", tagger.style.to_string());
+
+        let conds = HashSet::from(["limit".to_owned(), "genre".to_owned()]);
+        let name_tag = NameTag::derive(
+            "artists_long_songs@genre*limit",
+            Path::new("templates/queries/artists_long_songs.sql.j2"),
+            &conds,
+            Some(&tagger),
+        )
+        .unwrap();
+        assert_eq!("artists_long_songs__genre_limit", tagger.tag(&name_tag));
+
+        // Without template, the name tag is derived from the id
+        let name_tag = NameTag::derive(
+            "artists_long_songs@genre*limit",
+            Path::new("templates/queries/artists_long_songs.sql.j2"),
+            &conds,
+            None,
+        )
+        .unwrap();
+        match name_tag {
+            NameTag::DeriveFromId(id) => assert_eq!("artists_long_songs@genre*limit", id),
+            _ => assert!(false),
+        }
+
+        // Template rendering an empty tag
+        let tagger = NameTagger {
+            style: NameTagStyle::Exact,
+            flavor: NameTagFlavor::Default,
+            template: Some(NameTagTemplate::new("{{ conds|join('_') }}".to_owned()).unwrap()),
+        };
+        match NameTag::derive(
+            "artists_long_songs",
+            Path::new("artists_long_songs.sql.j2"),
+            &HashSet::new(),
+            Some(&tagger),
+        ) {
+            Err(Error::Parsing(msg)) => assert_eq!(
+                "Value of 'name_tagger.template' rendered an invalid tag for query 'artists_long_songs': ''",
+                msg
+            ),
+            _ => assert!(false),
+        }
+
+        // Syntax errors in the template are reported upon decoding
+        let table = "template = '{{ id '".parse::<toml::Table>().unwrap();
+        match NameTagger::decode(&Value::Table(table)) {
+            Err(Error::MiniJinja(_)) => assert!(true),
+            _ => assert!(false),
+        }
+
+        // `style` is required if template is not specified
+        let table = "flavor = 'aiosql'".parse::<toml::Table>().unwrap();
+        match NameTagger::decode(&Value::Table(table)) {
+            Err(Error::Parsing(msg)) => assert_eq!("Key 'name_tagger.style' is missing", msg),
+            _ => assert!(false),
+        }
     }
 
     #[test]
@@ -196,6 +678,10 @@ SELECT * FROM foobar;
 "#;
         assert!(has_name_tag(sql));
 
+        // Other flavors
+        assert!(has_name_tag("-- :name foobar :? :*\nSELECT * FROM foobar;"));
+        assert!(has_name_tag("-- name: foobar^\nSELECT * FROM foobar;"));
+
         let sql = r#"
 SELECT * FROM foobar;
 "#;
@@ -209,4 +695,84 @@ SELECT * FROM foobar;
 "#;
         assert!(!has_name_tag(sql));
     }
+
+    #[test]
+    fn test_make_name_tag() {
+        let name_tag = NameTag::DeriveFromId("artists_long_songs@genre".to_owned());
+        let mut tagger = NameTagger {
+            style: NameTagStyle::KebabCase,
+            flavor: NameTagFlavor::Default,
+            template: None,
+        };
+        let one = Some(&QueryResult::One);
+        assert_eq!(
+            "-- name: artists-long-songs-genre",
+            tagger.make_name_tag(&name_tag, one)
+        );
+
+        tagger.flavor = NameTagFlavor::HugSql;
+        assert_eq!(
+            "-- :name artists-long-songs-genre :? :1",
+            tagger.make_name_tag(&name_tag, one)
+        );
+        assert_eq!(
+            "-- :name artists-long-songs-genre",
+            tagger.make_name_tag(&name_tag, None)
+        );
+
+        tagger.flavor = NameTagFlavor::PugSql;
+        assert_eq!(
+            "-- :name artists-long-songs-genre :affected",
+            tagger.make_name_tag(&name_tag, Some(&QueryResult::Affected))
+        );
+
+        tagger.flavor = NameTagFlavor::AioSql;
+        assert_eq!(
+            "-- name: artists-long-songs-genre<!",
+            tagger.make_name_tag(&name_tag, Some(&QueryResult::InsertReturning))
+        );
+        assert_eq!(
+            "-- name: artists-long-songs-genre",
+            tagger.make_name_tag(&name_tag, Some(&QueryResult::Many))
+        );
+    }
+
+    #[test]
+    fn test_parse_name_tag_line() {
+        assert_eq!(Some("foo-bar"), parse_name_tag_line("-- name: foo-bar"));
+        assert_eq!(
+            Some("foo_bar@baz*qux"),
+            parse_name_tag_line("-- name: foo_bar@baz*qux")
+        );
+        assert_eq!(Some("foo"), parse_name_tag_line("-- :name foo :? :*"));
+        assert_eq!(Some("foo"), parse_name_tag_line("-- :name foo :many"));
+        assert_eq!(Some("foo"), parse_name_tag_line("-- :name foo"));
+        for suffix in ["^", "$", "!", "<!", "*!", "#"] {
+            assert_eq!(
+                Some("foo"),
+                parse_name_tag_line(&format!("-- name: foo{suffix}"))
+            );
+        }
+        assert_eq!(None, parse_name_tag_line("-- :name"));
+        assert_eq!(None, parse_name_tag_line("-- :name foo bar"));
+    }
+
+    #[test]
+    fn test_extract_name_tag() {
+        let sql = "\n-- name: foo-bar\nSELECT * FROM foobar;";
+        assert_eq!(Some("foo-bar"), extract_name_tag(sql));
+        let sql = "-- This query is not name tagged\nSELECT * FROM foobar;";
+        assert_eq!(None, extract_name_tag(sql));
+        assert_eq!(None, extract_name_tag(""));
+    }
+
+    #[test]
+    fn test_is_name_tag_line() {
+        assert!(is_name_tag_line("-- name: foobar"));
+        assert!(is_name_tag_line("-- name: foo-bar"));
+        assert!(is_name_tag_line("--name:foo_bar"));
+        assert!(!is_name_tag_line("  -- name: foobar"));
+        assert!(!is_name_tag_line("-- name: this is not a name tag"));
+        assert!(!is_name_tag_line("SELECT * FROM foobar;"));
+    }
 }
@@ -1,10 +1,11 @@
 use crate::error::{parse_error, Error};
+use crate::output::OutputPattern;
 use crate::query::Queries;
 use crate::toml::{decode_pathbuf, decode_string};
 use crate::validation::{validate_path, ManifestMistake};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::Arc;
 use toml::Value;
 
 // path can be of the following format
@@ -48,6 +49,8 @@ impl TestTemplate {
     fn decode<P: AsRef<Path>>(
         templates_base_dir: P,
         output_base_dir: P,
+        output_pattern: Option<&OutputPattern>,
+        queries: &Queries,
         value: &Value,
     ) -> Result<Self, Error> {
         match value.as_table() {
@@ -72,7 +75,18 @@ impl TestTemplate {
                         Some(output_base_dir.as_ref()),
                         "test_templates[].output",
                     )?,
-                    None => path_to_output(&path, output_base_dir.as_ref())?,
+                    None => match output_pattern {
+                        Some(p) => {
+                            // If the query is not defined, it will be
+                            // reported upon validation
+                            let empty = HashSet::new();
+                            let conds = queries.get(&query).map_or(&empty, |q| &q.conds);
+                            output_base_dir
+                                .as_ref()
+                                .join(p.expand(&query, &path, conds))
+                        }
+                        None => path_to_output(&path, output_base_dir.as_ref())?,
+                    },
                 };
                 Ok(Self {
                     path,
@@ -124,26 +138,34 @@ impl TestTemplate {
 
 #[derive(Debug)]
 pub struct TestTemplates {
-    inner: Vec<Rc<TestTemplate>>,
+    inner: Vec<Arc<TestTemplate>>,
 }
 
 impl TestTemplates {
     pub fn new() -> Self {
-        let inner: Vec<Rc<TestTemplate>> = vec![];
+        let inner: Vec<Arc<TestTemplate>> = vec![];
         Self { inner }
     }
 
     pub fn decode<P: AsRef<Path>>(
         templates_base_dir: P,
         output_base_dir: P,
+        output_pattern: Option<&OutputPattern>,
+        queries: &Queries,
         value: &Value,
     ) -> Result<Self, Error> {
         let items = match value.as_array() {
             Some(xs) => {
                 let mut res = Vec::with_capacity(xs.len());
                 for x in xs {
-                    let tt = TestTemplate::decode(&templates_base_dir, &output_base_dir, x)?;
-                    res.push(Rc::new(tt));
+                    let tt = TestTemplate::decode(
+                        &templates_base_dir,
+                        &output_base_dir,
+                        output_pattern,
+                        queries,
+                        x,
+                    )?;
+                    res.push(Arc::new(tt));
                 }
                 res
             }
@@ -178,7 +200,7 @@ impl TestTemplates {
         mistakes
     }
 
-    pub fn get(&self, path: &Path) -> Option<&Rc<TestTemplate>> {
+    pub fn get(&self, path: &Path) -> Option<&Arc<TestTemplate>> {
         self.inner.iter().find(|tt| tt.path == path)
     }
 
@@ -190,14 +212,14 @@ impl TestTemplates {
     /// to either use an index or modify inner itself to use a
     /// suitable data structure such as a `HashMap` of Strings (query
     /// ids) mapping to `Vec<TestTemplate>`.
-    pub fn find_by_query(&self, query_id: &str) -> Vec<&Rc<TestTemplate>> {
+    pub fn find_by_query(&self, query_id: &str) -> Vec<&Arc<TestTemplate>> {
         self.inner
             .iter()
             .filter(|tt| tt.query.as_str() == query_id)
             .collect()
     }
 
-    pub fn iter(&self) -> std::slice::Iter<'_, Rc<TestTemplate>> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Arc<TestTemplate>> {
         self.inner.iter()
     }
 }
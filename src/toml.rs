@@ -16,6 +16,17 @@ pub fn decode_string(value: &Value, key: &str) -> Result<String, Error> {
         .map(|s| s.to_owned())
 }
 
+/// Tries decoding a toml `Value` into a `bool`
+///
+/// The second arg `key` will be used in the error message in case
+/// decoding fails (i.e. in case the value in the toml file is not a
+/// boolean).
+pub fn decode_bool(value: &Value, key: &str) -> Result<bool, Error> {
+    value
+        .as_bool()
+        .ok_or(parse_error!("Value of '{}' expected to be a boolean", key))
+}
+
 /// Tries decoding a toml `Value` into a PathBuf
 ///
 /// The second arg `key` will be used in the error message in
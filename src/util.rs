@@ -1,5 +1,7 @@
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::{fs, io};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fs, io, panic, thread};
 
 /// Checks whether a file is a backup file
 ///
@@ -9,7 +11,22 @@ use std::{fs, io};
 fn is_backup_file<P: AsRef<Path>>(path: P) -> bool {
     path.as_ref()
         .extension()
-        .map_or(false, |e| e.to_str().map_or(false, |s| s.ends_with('~')))
+        .is_some_and(|e| e.to_str().is_some_and(|s| s.ends_with('~')))
+}
+
+/// Returns the 64 bit FNV-1a hash of `s` as a hex string
+///
+/// A simple non-cryptographic hash is sufficient here as it's only
+/// used for detecting changes (e.g. manual edits to the output
+/// files). Unlike `DefaultHasher`, the output is guaranteed to be
+/// stable across rust versions.
+pub fn fnv1a_hex(s: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in s.bytes() {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
 }
 
 /// Returns list of files (and not dirs) inside a directory
@@ -25,6 +42,126 @@ pub fn ls_files<P: AsRef<Path>>(path: P, include_backups: bool) -> Result<Vec<Pa
     Ok(result)
 }
 
+/// Returns list of files inside a directory and all it's
+/// subdirectories except the ones in `skip_dirs`
+pub fn ls_files_recursive<P: AsRef<Path>>(
+    path: P,
+    include_backups: bool,
+    skip_dirs: &[&Path],
+) -> Result<Vec<PathBuf>, io::Error> {
+    let mut result: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            if !skip_dirs.contains(&path.as_path()) {
+                result.append(&mut ls_files_recursive(&path, include_backups, skip_dirs)?);
+            }
+        } else if path.is_file() && (include_backups || !is_backup_file(&path)) {
+            result.push(path);
+        }
+    }
+    Ok(result)
+}
+
+/// Returns the default number of jobs for parallel processing
+/// i.e. the available parallelism (falls back to 1)
+pub fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+}
+
+/// Applies `f` to all the `items` using upto `jobs` threads and
+/// returns the results in the same order as the items
+///
+/// If `f` fails for multiple items, the error for the first of them
+/// (in order of the items) is returned, irrespective of the order in
+/// which they are processed. This keeps the error reporting
+/// deterministic and the same as that of processing the items
+/// sequentially (which is the case when `jobs` is 1).
+pub fn parallel_map<T, R, E, F>(items: &[T], jobs: usize, f: F) -> Result<Vec<R>, E>
+where
+    T: Sync,
+    R: Send,
+    E: Send,
+    F: Fn(&T) -> Result<R, E> + Sync,
+{
+    let jobs = jobs.min(items.len());
+    if jobs <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<Result<R, E>>> = Vec::with_capacity(items.len());
+    results.resize_with(items.len(), || None);
+    thread::scope(|s| {
+        let handles = (0..jobs)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut acc = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= items.len() {
+                            break;
+                        }
+                        acc.push((i, f(&items[i])));
+                    }
+                    acc
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            let acc = handle.join().unwrap_or_else(|e| panic::resume_unwind(e));
+            for (i, result) in acc {
+                results[i] = Some(result);
+            }
+        }
+    });
+    // @UNWRAP: Every item is processed by one of the threads
+    results.into_iter().map(|r| r.unwrap()).collect()
+}
+
+#[cfg(test)]
+pub mod test_util {
+
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // Temporary dir for the files created by a test, which is removed
+    // upon drop i.e. even if the test fails
+    pub struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("tapestry-test-{name}-{}", std::process::id()));
+            // Leftovers of a previous run (if it was killed) are removed
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // Writes a shell script at `path` and makes it executable, for
+    // stubbing external programs (e.g. formatters) in tests
+    #[cfg(unix)]
+    pub fn stub_executable(path: &Path, script: &str) {
+        use std::os::unix::fs::PermissionsExt;
+        fs::write(path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -35,4 +172,18 @@ mod tests {
         assert!(is_backup_file("hello.sql~"));
         assert!(!is_backup_file("hello.sql"));
     }
+
+    #[test]
+    fn test_parallel_map() {
+        let items = (1..=100).collect::<Vec<u32>>();
+        let f = |x: &u32| if x % 30 == 0 { Err(*x) } else { Ok(x * 2) };
+        for jobs in [1, 4] {
+            // Results are in the same order as the items
+            let result = parallel_map(&items[..29], jobs, f);
+            assert_eq!(Ok((1..=29).map(|x| x * 2).collect()), result);
+
+            // Error for the first failing item is returned
+            assert_eq!(Err(30), parallel_map(&items, jobs, f));
+        }
+    }
 }
@@ -1,6 +1,28 @@
+use crate::query_template::MAX_EXPAND_ALL_CONDS;
+use serde::Serialize;
 use std::path::Path;
 
-#[derive(Debug)]
+/// Violation of a constraint between conds defined for a query
+/// template
+#[derive(Debug, Serialize)]
+#[serde(tag = "constraint", rename_all = "snake_case")]
+pub enum CondsViolation<'a> {
+    /// `cond` is included but the conds it requires are missing
+    Requires {
+        cond: &'a str,
+        missing: Vec<&'a str>,
+    },
+    /// Mutually exclusive conds are included together
+    Excludes { conds: Vec<&'a str> },
+}
+
+/// Mistakes found in the manifest upon validation
+///
+/// When serialized (e.g. for JSON output), the name of the variant is
+/// included under the key `kind` (in snake_case) and the fields of
+/// the variant under the key `fields`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "fields", rename_all = "snake_case")]
 pub enum ManifestMistake<'a> {
     PathDoesnotExist {
         path: &'a Path,
@@ -32,7 +54,30 @@ pub enum ManifestMistake<'a> {
         output_path: &'a Path,
     },
     DisparateQueryOutputs,
-    NameTaggingRequired(String),
+    DisparateGroupOutputs {
+        group: &'a str,
+        outputs: Vec<&'a Path>,
+    },
+    NameTaggingRequired {
+        reason: String,
+    },
+    CondsConstraintViolated {
+        query_id: &'a str,
+        violation: CondsViolation<'a>,
+    },
+    DuplicateNameTag {
+        name_tag: String,
+        query_ids: Vec<&'a str>,
+    },
+    TooManyExpandConds {
+        template: &'a Path,
+        num_conds: usize,
+    },
+    ConflictingParamTypes {
+        param: String,
+        types: Vec<String>,
+        query_ids: Vec<&'a str>,
+    },
 }
 
 impl<'a> ManifestMistake<'a> {
@@ -75,9 +120,44 @@ impl<'a> ManifestMistake<'a> {
             Self::DisparateQueryOutputs => {
                 String::from("Disparate query outputs found. All expected to be same as 'query_output_file' when layout = one-file-all-queries")
             },
-            Self::NameTaggingRequired(reason) => {
+            Self::DisparateGroupOutputs { group, outputs } => {
+                format!("Disparate query outputs found for group '{group}': {outputs:?}. All expected to be same when layout = one-file-per-template or grouped")
+            }
+            Self::NameTaggingRequired { reason } => {
                 format!("Name tagging is required for reason: {reason}")
             }
+            Self::CondsConstraintViolated {
+                query_id,
+                violation,
+            } => match violation {
+                CondsViolation::Requires { cond, missing } => {
+                    format!("Cond '{cond}' requires conds {missing:?} which are missing for query: '{query_id}'")
+                }
+                CondsViolation::Excludes { conds } => {
+                    format!("Mutually exclusive conds {conds:?} defined for query: '{query_id}'")
+                }
+            },
+            Self::DuplicateNameTag {
+                name_tag,
+                query_ids,
+            } => {
+                format!("Name tag '{name_tag}' is shared by multiple queries: {query_ids:?}")
+            }
+            Self::TooManyExpandConds { template, num_conds } => {
+                format!(
+                    "Query template '{}' has {num_conds} conds; At most {MAX_EXPAND_ALL_CONDS} are allowed when expand = \"all\"",
+                    template.display()
+                )
+            }
+            Self::ConflictingParamTypes {
+                param,
+                types,
+                query_ids,
+            } => {
+                format!(
+                    "Param '{param}' is declared with different types {types:?} in queries: {query_ids:?}"
+                )
+            }
         }
     }
 }
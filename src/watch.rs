@@ -0,0 +1,220 @@
+use crate::metadata::Metadata;
+use crate::query::Query;
+use crate::selection::Selection;
+use crate::test_template::TestTemplate;
+use crate::util::ls_files_recursive;
+use log::{debug, info};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Reverse index mapping template paths to the queries and tests that
+/// are rendered using them
+pub struct TemplateIndex<'a> {
+    queries: HashMap<&'a Path, Vec<&'a Arc<Query>>>,
+    tests: HashMap<&'a Path, &'a Arc<TestTemplate>>,
+}
+
+impl<'a> From<&'a Metadata> for TemplateIndex<'a> {
+    fn from(metadata: &'a Metadata) -> Self {
+        let mut queries: HashMap<&Path, Vec<&Arc<Query>>> = HashMap::new();
+        for query in metadata.queries.iter() {
+            queries
+                .entry(query.template.as_path())
+                .or_default()
+                .push(query);
+        }
+        let tests = metadata
+            .test_templates
+            .iter()
+            .map(|tt| (tt.path.as_path(), tt))
+            .collect::<HashMap<&Path, &Arc<TestTemplate>>>();
+        Self { queries, tests }
+    }
+}
+
+impl<'a> TemplateIndex<'a> {
+    /// Returns the selection of queries and tests affected by the
+    /// changed paths
+    ///
+    /// If any of the changed paths is not a known template (e.g. the
+    /// manifest file itself or a template that's included by other
+    /// templates), everything is selected as it's not possible to
+    /// determine what's affected by the change.
+    pub fn affected(&self, changed: &[PathBuf]) -> Selection {
+        let mut query_templates = vec![];
+        let mut test_templates = vec![];
+        for path in changed {
+            if let Some(queries) = self.queries.get(path.as_path()) {
+                for q in queries {
+                    info!("Query '{}' affected by change in {}", q.id, path.display());
+                }
+                query_templates.push(path.clone());
+            } else if let Some(tt) = self.tests.get(path.as_path()) {
+                info!(
+                    "Test for query '{}' affected by change in {}",
+                    tt.query,
+                    path.display()
+                );
+                test_templates.push(path.clone());
+            } else {
+                info!("Unindexed file changed: {}", path.display());
+                return Selection::default();
+            }
+        }
+        Selection::new(&[], &query_templates, &test_templates)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Simple polling based watcher for files and (recursively) the
+/// files inside dirs
+///
+/// Polling is preferred over OS specific notification APIs as the
+/// no. of files that tapestry deals with is small enough.
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    snapshot: HashMap<PathBuf, SystemTime>,
+}
+
+impl Watcher {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let snapshot = take_snapshot(&paths);
+        Self { paths, snapshot }
+    }
+
+    /// Updates the paths to be watched. Changes to files under the
+    /// new paths will be detected only after this call.
+    pub fn set_paths(&mut self, paths: Vec<PathBuf>) {
+        if paths != self.paths {
+            self.snapshot = take_snapshot(&paths);
+            self.paths = paths;
+        }
+    }
+
+    /// Returns the paths that have been added, modified or removed
+    /// since the last call. The result is sorted.
+    pub fn changes(&mut self) -> Vec<PathBuf> {
+        let snapshot = take_snapshot(&self.paths);
+        let mut changed: HashSet<&PathBuf> = HashSet::new();
+        for (path, mtime) in snapshot.iter() {
+            if self.snapshot.get(path) != Some(mtime) {
+                changed.insert(path);
+            }
+        }
+        for path in self.snapshot.keys() {
+            if !snapshot.contains_key(path) {
+                changed.insert(path);
+            }
+        }
+        let mut result = changed.into_iter().cloned().collect::<Vec<PathBuf>>();
+        result.sort();
+        self.snapshot = snapshot;
+        result
+    }
+}
+
+fn take_snapshot(paths: &[PathBuf]) -> HashMap<PathBuf, SystemTime> {
+    let mut snapshot = HashMap::new();
+    for path in paths {
+        if path.is_dir() {
+            match ls_files_recursive(path, false, &[]) {
+                Ok(files) => {
+                    for f in files {
+                        if let Some(t) = modified_time(&f) {
+                            snapshot.insert(f, t);
+                        }
+                    }
+                }
+                Err(e) => debug!("Failed to list files in {}: {e}", path.display()),
+            }
+        } else if let Some(t) = modified_time(path) {
+            snapshot.insert(path.clone(), t);
+        }
+    }
+    snapshot
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::query::test_util::setup_queries;
+    use crate::util::test_util::TempDir;
+
+    #[test]
+    fn test_template_index_affected() {
+        let mut metadata = Metadata::default();
+        metadata.queries = setup_queries(vec![
+            (
+                "artists_long_songs",
+                "templates/queries/artists_long_songs.sql.j2",
+                vec![],
+                "output/queries/artists_long_songs.sql",
+                None,
+            ),
+            (
+                "artists_long_songs*limit",
+                "templates/queries/artists_long_songs.sql.j2",
+                vec!["limit"],
+                "output/queries/artists_long_songs-limit.sql",
+                None,
+            ),
+            (
+                "songs_formats",
+                "templates/queries/songs_formats.sql.j2",
+                vec![],
+                "output/queries/songs_formats.sql",
+                None,
+            ),
+        ]);
+        let index = TemplateIndex::from(&metadata);
+
+        // Change in a query template selects all queries that use it
+        let selection =
+            index.affected(&[PathBuf::from("templates/queries/artists_long_songs.sql.j2")]);
+        assert!(!selection.is_all());
+        let selected = metadata
+            .queries
+            .iter()
+            .filter(|q| selection.includes_query(q, &metadata))
+            .map(|q| q.id.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            vec!["artists_long_songs", "artists_long_songs*limit"],
+            selected
+        );
+
+        // Change in a file that's not known to be a template selects
+        // everything
+        let selection = index.affected(&[
+            PathBuf::from("templates/queries/songs_formats.sql.j2"),
+            PathBuf::from("tapestry.toml"),
+        ]);
+        assert!(selection.is_all());
+    }
+
+    #[test]
+    fn test_watcher_changes() {
+        let tmp = TempDir::new("watch");
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("common")).unwrap();
+        fs::write(dir.join("songs.sql.j2"), "SELECT 1;").unwrap();
+        let mut watcher = Watcher::new(vec![dir.to_path_buf()]);
+        assert!(watcher.changes().is_empty());
+
+        // Files inside subdirs are also watched
+        let nested = dir.join("common/filters.sql.j2");
+        fs::write(&nested, "WHERE 1 = 1").unwrap();
+        assert_eq!(vec![nested.clone()], watcher.changes());
+        assert!(watcher.changes().is_empty());
+
+        fs::remove_file(&nested).unwrap();
+        assert_eq!(vec![nested], watcher.changes());
+    }
+}